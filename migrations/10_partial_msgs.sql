@@ -0,0 +1,13 @@
+-------------------------------------------------------------------------------
+-- Parts of large messages fetched so far, so that fetching can resume from
+-- where it was interrupted, rather than from the start of the message.
+-- Deleted once the whole message is stored.
+-------------------------------------------------------------------------------
+CREATE TABLE IF NOT EXISTS partial_msgs (
+    account TEXT NOT NULL,
+    mailbox TEXT NOT NULL,
+    uid INTEGER NOT NULL,
+    offset INTEGER NOT NULL,
+    data BLOB NOT NULL,
+    PRIMARY KEY (account, mailbox, uid, offset)
+);
@@ -0,0 +1,25 @@
+-------------------------------------------------------------------------------
+-- Flags and INTERNALDATE of msgs at their locations, as of fetching them, so
+-- that restoring a msg can bring them back along with it.
+-------------------------------------------------------------------------------
+CREATE TABLE IF NOT EXISTS location_attrs (
+    account TEXT NOT NULL,
+    mailbox TEXT NOT NULL,
+    uid INTEGER NOT NULL,
+    -- Space-separated, as IMAP has them: "\Seen \Flagged $Label1"
+    flags TEXT NOT NULL,
+    -- As IMAP has it: "17-Jul-1996 02:44:25 -0700"
+    internal_date TEXT,
+    PRIMARY KEY (account, mailbox, uid)
+);
+
+-------------------------------------------------------------------------------
+-- Msgs uploaded by restore, so that an interrupted restore can resume from
+-- where it was, rather than re-uploading or re-checking everything.
+-------------------------------------------------------------------------------
+CREATE TABLE IF NOT EXISTS restored_msgs (
+    msg_hash TEXT NOT NULL,
+    account TEXT NOT NULL,
+    mailbox TEXT NOT NULL,
+    PRIMARY KEY (account, mailbox, msg_hash)
+);
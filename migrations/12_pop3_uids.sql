@@ -0,0 +1,12 @@
+-------------------------------------------------------------------------------
+-- Msgs fetched from POP3 accounts, by their UIDL unique-ids, which, unlike
+-- their message numbers, persist across sessions, so that no msg is fetched
+-- twice. POP3 has no mailboxes or UIDs to record in locations.
+-------------------------------------------------------------------------------
+CREATE TABLE IF NOT EXISTS pop3_uids (
+    account TEXT NOT NULL,
+    uid TEXT NOT NULL,
+    msg_hash TEXT NOT NULL,
+    FOREIGN KEY (msg_hash) REFERENCES messages(hash),
+    PRIMARY KEY (account, uid)
+);
@@ -0,0 +1,10 @@
+-------------------------------------------------------------------------------
+-- UIDs of msgs, as served over IMAP by `ma serve-imap`. Handed out, in the
+-- order msgs were stored, when first served, and never reused or changed,
+-- unlike rowids, which VACUUM may renumber.
+-------------------------------------------------------------------------------
+CREATE TABLE IF NOT EXISTS imap_uids (
+    uid INTEGER PRIMARY KEY AUTOINCREMENT,
+    msg_hash TEXT NOT NULL UNIQUE,
+    FOREIGN KEY (msg_hash) REFERENCES messages(hash)
+);
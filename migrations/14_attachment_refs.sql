@@ -0,0 +1,7 @@
+-------------------------------------------------------------------------------
+-- Attachments are decoded from their msgs when extracted, rather than kept
+-- in a copy of their own, next to the encoded original in messages.raw. Such
+-- copies, of attachments stored before, are dropped, for SQLite to reuse
+-- their space (or VACUUM to give it back).
+-------------------------------------------------------------------------------
+UPDATE attachments SET data = x'' WHERE length(data) > 0;
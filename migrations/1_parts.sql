@@ -0,0 +1,19 @@
+-------------------------------------------------------------------------------
+-- Msg MIME parts:
+-------------------------------------------------------------------------------
+CREATE TABLE IF NOT EXISTS parts (
+    msg_hash TEXT NOT NULL,
+    idx INTEGER NOT NULL,          -- Position in mail_parser's flattened part list.
+    content_type TEXT NOT NULL,
+    charset TEXT,
+    disposition TEXT,
+    filename TEXT,
+    size INTEGER NOT NULL,         -- Decoded size in bytes.
+    text TEXT,                     -- Decoded text of text/* parts. HTML as-is.
+    text_plain TEXT,               -- Plain-text rendition of text/html parts.
+    FOREIGN KEY (msg_hash) REFERENCES messages(hash),
+    UNIQUE (msg_hash, idx)
+);
+
+CREATE INDEX IF NOT EXISTS idx_parts_msg_hash ON parts(msg_hash);
+CREATE INDEX IF NOT EXISTS idx_parts_content_type ON parts(content_type);
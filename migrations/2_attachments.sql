@@ -0,0 +1,23 @@
+-------------------------------------------------------------------------------
+-- Attachments, content-addressed by hash of their decoded data:
+-------------------------------------------------------------------------------
+CREATE TABLE IF NOT EXISTS attachments (
+    hash TEXT PRIMARY KEY,
+    mime_type TEXT NOT NULL,
+    size INTEGER NOT NULL,
+    data BLOB NOT NULL             -- Empty: see 14_attachment_refs.sql.
+);
+
+CREATE TABLE IF NOT EXISTS attachment_links (
+    attachment_hash TEXT NOT NULL,
+    msg_hash TEXT NOT NULL,
+    part_idx INTEGER NOT NULL,
+    name TEXT,
+    FOREIGN KEY (attachment_hash) REFERENCES attachments(hash),
+    FOREIGN KEY (msg_hash) REFERENCES messages(hash),
+    UNIQUE (msg_hash, part_idx)
+);
+
+CREATE INDEX IF NOT EXISTS idx_attachment_links_attachment_hash ON attachment_links(attachment_hash);
+CREATE INDEX IF NOT EXISTS idx_attachment_links_msg_hash ON attachment_links(msg_hash);
+CREATE INDEX IF NOT EXISTS idx_attachments_mime_type ON attachments(mime_type);
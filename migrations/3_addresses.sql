@@ -0,0 +1,28 @@
+-------------------------------------------------------------------------------
+-- Msg addresses, from the From/To/Cc/Bcc/Reply-To headers:
+-------------------------------------------------------------------------------
+CREATE TABLE IF NOT EXISTS addresses (
+    msg_hash TEXT NOT NULL,
+    role TEXT NOT NULL,            -- from | to | cc | bcc | reply-to
+    addr TEXT NOT NULL,            -- Lowercased.
+    name TEXT,                     -- Display name, as given.
+    FOREIGN KEY (msg_hash) REFERENCES messages(hash)
+);
+
+-- NULLs are distinct to a UNIQUE constraint, so uniqueness is on
+-- coalesce(name, ''), for unnamed addresses not to be re-inserted on every
+-- re-store. Duplicates inserted before that are dropped, once, first.
+DELETE FROM addresses
+WHERE NOT EXISTS (
+    SELECT 1 FROM sqlite_master WHERE name = 'idx_addresses_unique'
+)
+AND rowid NOT IN (
+    SELECT min(rowid) FROM addresses
+    GROUP BY msg_hash, role, addr, coalesce(name, '')
+);
+CREATE UNIQUE INDEX IF NOT EXISTS idx_addresses_unique
+    ON addresses(msg_hash, role, addr, coalesce(name, ''));
+
+CREATE INDEX IF NOT EXISTS idx_addresses_msg_hash ON addresses(msg_hash);
+CREATE INDEX IF NOT EXISTS idx_addresses_addr ON addresses(addr);
+CREATE INDEX IF NOT EXISTS idx_addresses_role_addr ON addresses(role, addr);
@@ -0,0 +1,17 @@
+-------------------------------------------------------------------------------
+-- Msg metadata, parsed once so that queries need not re-parse raw messages:
+-------------------------------------------------------------------------------
+CREATE TABLE IF NOT EXISTS message_meta (
+    msg_hash TEXT PRIMARY KEY,
+    date INTEGER,                  -- Unix time (UTC) from "Date:", if valid.
+    date_offset INTEGER,           -- Original UTC offset, in minutes.
+    subject TEXT,
+    message_id TEXT,               -- Without the angle brackets.
+    size INTEGER NOT NULL,         -- Raw size in bytes.
+    part_count INTEGER NOT NULL,
+    has_attachments INTEGER NOT NULL,
+    FOREIGN KEY (msg_hash) REFERENCES messages(hash)
+);
+
+CREATE INDEX IF NOT EXISTS idx_message_meta_date ON message_meta(date);
+CREATE INDEX IF NOT EXISTS idx_message_meta_message_id ON message_meta(message_id);
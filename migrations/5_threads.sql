@@ -0,0 +1,16 @@
+-------------------------------------------------------------------------------
+-- Conversation threads:
+-------------------------------------------------------------------------------
+CREATE TABLE IF NOT EXISTS threads (
+    msg_hash TEXT PRIMARY KEY,
+    thread_id TEXT NOT NULL,       -- Message-ID of the root, possibly missing, msg.
+    parent_hash TEXT,
+    parent_message_id TEXT,        -- Direct parent per In-Reply-To/References.
+    subject_base TEXT,             -- Subject without "Re:", "[list]", etc.
+    FOREIGN KEY (msg_hash) REFERENCES messages(hash),
+    FOREIGN KEY (parent_hash) REFERENCES messages(hash)
+);
+
+CREATE INDEX IF NOT EXISTS idx_threads_thread_id ON threads(thread_id);
+CREATE INDEX IF NOT EXISTS idx_threads_parent_message_id ON threads(parent_message_id);
+CREATE INDEX IF NOT EXISTS idx_threads_subject_base ON threads(subject_base);
@@ -0,0 +1,13 @@
+-------------------------------------------------------------------------------
+-- Duplicates, as marked by `ma dedup --mark`. Raw messages are never deleted.
+-------------------------------------------------------------------------------
+CREATE TABLE IF NOT EXISTS duplicates (
+    msg_hash TEXT NOT NULL,
+    canonical_hash TEXT NOT NULL,
+    reason TEXT NOT NULL,          -- message-id | body
+    FOREIGN KEY (msg_hash) REFERENCES messages(hash),
+    FOREIGN KEY (canonical_hash) REFERENCES messages(hash),
+    UNIQUE (msg_hash, reason)
+);
+
+CREATE INDEX IF NOT EXISTS idx_duplicates_canonical_hash ON duplicates(canonical_hash);
@@ -0,0 +1,13 @@
+-------------------------------------------------------------------------------
+-- Where msgs were fetched from:
+-------------------------------------------------------------------------------
+CREATE TABLE IF NOT EXISTS locations (
+    msg_hash TEXT NOT NULL,
+    account TEXT NOT NULL,
+    mailbox TEXT NOT NULL,
+    uid INTEGER NOT NULL,
+    FOREIGN KEY (msg_hash) REFERENCES messages(hash),
+    UNIQUE (account, mailbox, uid)
+);
+
+CREATE INDEX IF NOT EXISTS idx_locations_msg_hash ON locations(msg_hash);
@@ -0,0 +1,25 @@
+-------------------------------------------------------------------------------
+-- History of fetch runs:
+-------------------------------------------------------------------------------
+CREATE TABLE IF NOT EXISTS runs (
+    id INTEGER PRIMARY KEY,
+    -- Unix time. ended is NULL while running, or if the run was interrupted.
+    started INTEGER NOT NULL,
+    ended INTEGER
+);
+
+-- What each run got from each mailbox. mailbox is NULL when the account
+-- as a whole failed, like when it could not even connect.
+CREATE TABLE IF NOT EXISTS run_mailboxes (
+    run_id INTEGER NOT NULL,
+    account TEXT NOT NULL,
+    mailbox TEXT,
+    messages INTEGER NOT NULL,
+    bytes INTEGER NOT NULL,
+    error TEXT,
+    duration_ms INTEGER NOT NULL,
+    FOREIGN KEY (run_id) REFERENCES runs(id)
+);
+
+CREATE INDEX IF NOT EXISTS idx_run_mailboxes_run_id ON run_mailboxes(run_id);
+CREATE INDEX IF NOT EXISTS idx_run_mailboxes_mailbox ON run_mailboxes(account, mailbox);
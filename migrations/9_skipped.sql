@@ -0,0 +1,14 @@
+-------------------------------------------------------------------------------
+-- Messages deliberately not fetched, for being outside of the requested date
+-- window, or over the size limit, so that later fetches can backfill them.
+-------------------------------------------------------------------------------
+CREATE TABLE IF NOT EXISTS skipped_msgs (
+    account TEXT NOT NULL,
+    mailbox TEXT NOT NULL,
+    uid INTEGER NOT NULL,
+    -- 'date' or 'size'
+    reason TEXT NOT NULL,
+    -- RFC822.SIZE, if it was asked for.
+    size INTEGER,
+    PRIMARY KEY (account, mailbox, uid)
+);
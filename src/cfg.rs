@@ -6,6 +6,8 @@ use std::{
 use anyhow::Context;
 use tokio::fs;
 
+use crate::{hook::Hooks, imap::throttle};
+
 const FILE_NAME: &str = "ma.toml";
 
 #[derive(serde::Serialize, serde::Deserialize, Clone)]
@@ -15,6 +17,38 @@ pub struct ImapAccount {
     pub user: String,
     pub pass: String,
     pub ignore_mailboxes: HashSet<String>,
+
+    /// Messages larger than this many bytes (as per RFC822.SIZE) are
+    /// skipped, and recorded as such, by fetch.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_message_size: Option<u32>,
+
+    /// How many connections, at most, to fetch mailboxes over in parallel.
+    /// Mind the provider's limit. Defaults to 1.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub connections: Option<usize>,
+
+    /// How many messages to fetch per command. After each such chunk, the
+    /// progress is saved, for an interrupted fetch to resume from.
+    /// Defaults to 500.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chunk_size: Option<u32>,
+
+    /// Messages larger than this many bytes are fetched in parts of this
+    /// size, each saved as it arrives, for an interrupted fetch to resume
+    /// from. Defaults to 8 MiB.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub part_size: Option<u32>,
+
+    /// Bandwidth limit, in each direction, of all of the account's
+    /// connections together.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_bytes_per_sec: Option<u32>,
+
+    /// Limit on the rate of commands of all of the account's connections
+    /// together. Can be fractional, like 0.5 for a command every 2 seconds.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_commands_per_sec: Option<f64>,
 }
 
 impl std::fmt::Debug for ImapAccount {
@@ -25,6 +59,12 @@ impl std::fmt::Debug for ImapAccount {
             .field("user", &self.user)
             .field("pass", &"<XXXXX>")
             .field("ignore_mailboxes", &self.ignore_mailboxes)
+            .field("max_message_size", &self.max_message_size)
+            .field("connections", &self.connections)
+            .field("chunk_size", &self.chunk_size)
+            .field("part_size", &self.part_size)
+            .field("max_bytes_per_sec", &self.max_bytes_per_sec)
+            .field("max_commands_per_sec", &self.max_commands_per_sec)
             .finish()
     }
 }
@@ -37,6 +77,12 @@ impl Default for ImapAccount {
             user: String::new(),
             pass: String::new(),
             ignore_mailboxes: HashSet::new(),
+            max_message_size: None,
+            connections: None,
+            chunk_size: None,
+            part_size: None,
+            max_bytes_per_sec: None,
+            max_commands_per_sec: None,
         }
     }
 }
@@ -53,6 +99,45 @@ impl Default for Imap {
     }
 }
 
+/// An account which only speaks POP3, over TLS, of which only new messages
+/// are fetched, as per their UIDL unique-ids, and never deleted. Settings of
+/// IMAP accounts, like `max_message_size`, are refused rather than ignored.
+#[derive(serde::Serialize, serde::Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Pop3Account {
+    pub addr: String,
+    pub port: u16,
+    pub user: String,
+    pub pass: String,
+}
+
+impl std::fmt::Debug for Pop3Account {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Pop3Account")
+            .field("addr", &self.addr)
+            .field("port", &self.port)
+            .field("user", &self.user)
+            .field("pass", &"<XXXXX>")
+            .finish()
+    }
+}
+
+impl Default for Pop3Account {
+    fn default() -> Self {
+        Self {
+            addr: String::new(),
+            port: 995,
+            user: String::new(),
+            pass: String::new(),
+        }
+    }
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug, Default)]
+pub struct Pop3 {
+    pub accounts: HashMap<String, Pop3Account>,
+}
+
 #[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
 pub struct Db {
     pub file: PathBuf,
@@ -69,7 +154,14 @@ impl Default for Db {
 #[derive(serde::Serialize, serde::Deserialize, Clone, Debug, Default)]
 pub struct Cfg {
     pub imap: Imap,
+
+    #[serde(default)]
+    pub pop3: Pop3,
+
     pub db: Db,
+
+    #[serde(default)]
+    pub hooks: Hooks,
 }
 
 impl Cfg {
@@ -78,6 +170,7 @@ impl Cfg {
             tracing::debug!(file = ?path, "Reading cfg from file.");
             let data = fs::read_to_string(path).await?;
             let config: Self = toml::from_str(&data)?;
+            config.validate()?;
             tracing::debug!(?path, ?config, "Got user config from file.");
             anyhow::Ok(config)
         }
@@ -103,12 +196,14 @@ impl Cfg {
             let data = std::fs::read_to_string(&path).with_context(|| {
                 format!("Failed to read from path: {:?}", &path)
             })?;
-            let cfg = toml::from_str(&data).with_context(|| {
+            let cfg: Self = toml::from_str(&data).with_context(|| {
                 format!(
                     "Failed to parse config data which was read from: {:?}",
                     &path
                 )
             })?;
+            cfg.validate()
+                .with_context(|| format!("Invalid config in: {:?}", &path))?;
             tracing::debug!(?path, ?cfg, "Got cfg from file.");
             Ok(cfg)
         } else {
@@ -118,4 +213,81 @@ impl Cfg {
             Ok(selph)
         }
     }
+
+    /// Accounts of both kinds are selected, reported and stored by name, so
+    /// no name may be taken by both.
+    fn validate(&self) -> anyhow::Result<()> {
+        let mut both: Vec<&String> = self
+            .imap
+            .accounts
+            .keys()
+            .filter(|name| self.pop3.accounts.contains_key(*name))
+            .collect();
+        if !both.is_empty() {
+            both.sort();
+            anyhow::bail!(
+                "Account names in both [imap.accounts] and [pop3.accounts]: \
+                {both:?}"
+            );
+        }
+        for (name, account) in &self.imap.accounts {
+            throttle::Limits::new(account).with_context(|| {
+                format!(
+                    "Account {name:?}: max_commands_per_sec too small: {:?}",
+                    account.max_commands_per_sec
+                )
+            })?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn t_duplicate_names() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(FILE_NAME);
+        let mut cfg = Cfg::default();
+        cfg.imap
+            .accounts
+            .insert("a".to_string(), ImapAccount::default());
+        cfg.pop3
+            .accounts
+            .insert("b".to_string(), Pop3Account::default());
+        cfg.to_file(&path).await.unwrap();
+        assert!(Cfg::from_file(&path).await.is_ok());
+
+        cfg.pop3
+            .accounts
+            .insert("a".to_string(), Pop3Account::default());
+        cfg.to_file(&path).await.unwrap();
+        let error = Cfg::from_file(&path).await.unwrap_err();
+        assert!(format!("{error:#}").contains("[\"a\"]"), "{error:#}");
+    }
+
+    #[test]
+    fn t_pop3_imap_settings() {
+        let toml = "addr = \"x\"\nport = 995\nuser = \"u\"\npass = \"p\"\n";
+        assert!(toml::from_str::<Pop3Account>(toml).is_ok());
+        let toml = format!("{toml}max_message_size = 1000\n");
+        assert!(toml::from_str::<Pop3Account>(&toml).is_err());
+    }
+
+    #[tokio::test]
+    async fn t_tiny_rate() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(FILE_NAME);
+        let mut cfg = Cfg::default();
+        let account = ImapAccount {
+            max_commands_per_sec: Some(1e-320),
+            ..ImapAccount::default()
+        };
+        cfg.imap.accounts.insert("a".to_string(), account);
+        cfg.to_file(&path).await.unwrap();
+        let error = Cfg::from_file(&path).await.unwrap_err();
+        assert!(format!("{error:#}").contains("too small"), "{error:#}");
+    }
 }
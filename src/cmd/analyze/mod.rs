@@ -1,25 +1,48 @@
-mod routes;
-mod senders;
+pub(crate) mod routes;
+pub(crate) mod senders;
 
-use crate::cfg::Cfg;
+use std::io::{self, Write};
+
+use crate::{cfg::Cfg, data::Filter, output::Output, query::Query};
 
 #[derive(clap::Args, Debug)]
 pub struct Cmd {
+    /// Analyze only messages matching this query (see `ma find`).
+    #[clap(short, long)]
+    query: Option<Query>,
+
     #[clap(subcommand)]
     analyze: Analyze,
 }
 
+#[derive(serde::Serialize, Debug)]
+#[serde(untagged)]
+pub enum Analysis {
+    Routes(routes::Routes),
+    Senders(senders::Report),
+}
+
+impl Output for Analysis {
+    fn write_text(&self, w: &mut dyn Write) -> io::Result<()> {
+        match self {
+            Self::Routes(routes) => routes.write_text(w),
+            Self::Senders(senders) => senders.write_text(w),
+        }
+    }
+}
+
 impl Cmd {
-    pub async fn run(&self, cfg: &Cfg) -> anyhow::Result<()> {
-        match &self.analyze {
+    pub async fn run(&self, cfg: &Cfg) -> anyhow::Result<Analysis> {
+        let filter = self.query.as_ref().map(Filter::new).unwrap_or_default();
+        let analysis = match &self.analyze {
             Analyze::Routes { reduce } => {
-                routes::trace(*reduce, cfg).await?;
+                Analysis::Routes(routes::trace(*reduce, cfg, &filter).await?)
             }
-            Analyze::Senders { noise_threshold } => {
-                senders::analyze(cfg, *noise_threshold).await?;
-            }
-        }
-        Ok(())
+            Analyze::Senders { noise_threshold } => Analysis::Senders(
+                senders::analyze(cfg, *noise_threshold, &filter).await?,
+            ),
+        };
+        Ok(analysis)
     }
 }
 
@@ -33,6 +56,7 @@ enum Analyze {
         /// Reduce the number of nodes and edges by grouping host addresses
         /// (removing subdomains and only using the first octets of IP
         /// addresses).
+        #[clap(short, long)]
         reduce: bool,
     },
 
@@ -1,19 +1,80 @@
-use std::collections::HashMap;
+use std::{
+    collections::HashMap,
+    io::{self, Write},
+};
 
-use futures::StreamExt;
 use mail_parser::{Host, Received};
 
 use crate::{
     cfg::Cfg,
-    data::{self, Msg},
+    data::{self, Filter, Msg},
+    output::Output,
 };
 
-pub async fn trace(reduce: bool, cfg: &Cfg) -> anyhow::Result<()> {
+/// Hops between hosts, as text in the DOT language.
+#[derive(serde::Serialize, Debug)]
+pub struct Routes {
+    pub edges: Vec<Edge>,
+}
+
+#[derive(serde::Serialize, Debug)]
+pub struct Edge {
+    pub src: String,
+    pub dst: String,
+    /// How many times this hop was seen.
+    pub count: usize,
+    /// Relative line width, from 1 to 10, for drawing.
+    pub penwidth: u8,
+}
+
+impl Routes {
+    #[must_use]
+    pub fn dot(&self) -> String {
+        let mut dot = String::new();
+        dot.push_str("strict digraph G {\n");
+        for Edge {
+            src,
+            dst,
+            count: _,
+            penwidth,
+        } in &self.edges
+        {
+            dot.push_str(&format!(
+                "    {src:?} -> {dst:?} [penwidth={penwidth}]\n"
+            ));
+        }
+        dot.push_str("}\n");
+        dot
+    }
+}
+
+impl Output for Routes {
+    fn write_text(&self, w: &mut dyn Write) -> io::Result<()> {
+        w.write_all(self.dot().as_bytes())
+    }
+}
+
+pub async fn trace(
+    reduce: bool,
+    cfg: &Cfg,
+    filter: &Filter,
+) -> anyhow::Result<Routes> {
     let db = data::Storage::connect(&cfg.db).await?;
-    let mut msgs = db.fetch_messages();
+    graph(&db, reduce, filter).await
+}
+
+/// Edges seen more than once.
+pub async fn graph(
+    db: &data::Storage,
+    reduce: bool,
+    filter: &Filter,
+) -> anyhow::Result<Routes> {
     let mut routes = HashMap::new();
     let mut max: usize = 0;
-    while let Some(Ok(Msg { hash: _, raw })) = msgs.next().await {
+    for hash in db.find(filter).await? {
+        let Some(Msg { hash: _, raw }) = db.fetch_msg(&hash).await? else {
+            continue;
+        };
         if let Some(msg) =
             mail_parser::MessageParser::new().parse_headers(&raw[..])
         {
@@ -55,15 +116,18 @@ pub async fn trace(reduce: bool, cfg: &Cfg) -> anyhow::Result<()> {
             }
         }
     }
-    println!("strict digraph G {{");
-    for ((src, dst), count) in &routes {
-        if *count > 1 {
-            let penwidth = penwidth(*count, max);
-            println!("    {src:?} -> {dst:?} [penwidth={penwidth}]");
-        }
-    }
-    println!("}}");
-    Ok(())
+    let mut edges: Vec<Edge> = routes
+        .into_iter()
+        .filter(|(_, count)| *count > 1)
+        .map(|((src, dst), count)| Edge {
+            src,
+            dst,
+            count,
+            penwidth: penwidth(count, max),
+        })
+        .collect();
+    edges.sort_by(|a, b| (&a.src, &a.dst).cmp(&(&b.src, &b.dst)));
+    Ok(Routes { edges })
 }
 
 #[allow(clippy::cast_possible_truncation)]
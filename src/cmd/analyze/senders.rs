@@ -1,23 +1,84 @@
 use std::{
-    borrow::Cow,
     collections::{HashMap, HashSet},
+    io::{self, Write},
 };
 
-use futures::StreamExt;
-use mail_parser::{Addr, Address, Group, Message};
-
 use crate::{
     cfg::Cfg,
-    data::{self, Msg},
+    data::{self, Address, Filter},
+    output::Output,
 };
 
+/// Names and the addresses they were seen with, and vice versa.
+#[derive(serde::Serialize, Debug)]
+pub struct Report {
+    /// Name -> addresses.
+    pub by_name: Vec<Entry>,
+
+    /// Address -> names.
+    pub by_addr: Vec<Entry>,
+}
+
+#[derive(serde::Serialize, Debug)]
+pub struct Entry {
+    pub key: String,
+    pub links: Vec<Link>,
+}
+
+/// A value linked to an entry's key, along with the other keys the value
+/// is linked to.
+#[derive(serde::Serialize, Debug)]
+pub struct Link {
+    pub value: String,
+    pub count: usize,
+    pub others: Vec<(String, usize)>,
+}
+
+impl Output for Report {
+    fn write_text(&self, w: &mut dyn Write) -> io::Result<()> {
+        let indent = "\t";
+        for (entries, key_kind, value_kind) in [
+            (&self.by_name, "name", "addr"),
+            (&self.by_addr, "addr", "name"),
+        ] {
+            for Entry { key, links } in entries {
+                writeln!(w, "{key_kind} {key:?}")?;
+                for Link {
+                    value,
+                    count,
+                    others,
+                } in links
+                {
+                    writeln!(w, "{indent}{value_kind} ({count}) {value:?}")?;
+                    for (other, count) in others {
+                        writeln!(
+                            w,
+                            "{indent}{indent}{key_kind} ({count}) {other:?}"
+                        )?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
 #[tracing::instrument(name = "contacts", skip_all)]
 pub async fn analyze(
     cfg: &Cfg,
     noise_threshold: usize,
-) -> anyhow::Result<()> {
+    filter: &Filter,
+) -> anyhow::Result<Report> {
     let db = data::Storage::connect(&cfg.db).await?;
-    let mut msgs = db.fetch_messages();
+    report(&db, noise_threshold, filter).await
+}
+
+pub async fn report(
+    db: &data::Storage,
+    noise_threshold: usize,
+    filter: &Filter,
+) -> anyhow::Result<Report> {
+    let senders = db.fetch_addresses("from", filter).await?;
 
     // TODO Normalize names.
     // TODO graph_normal2seen
@@ -28,25 +89,28 @@ pub async fn analyze(
     let mut count_name2addr: HashMap<(&str, &str), usize> = HashMap::new();
     let mut count_addr2name: HashMap<(&str, &str), usize> = HashMap::new();
 
-    while let Some(Ok(Msg { hash: _, raw })) = msgs.next().await {
-        if let Some(msg) =
-            mail_parser::MessageParser::new().parse_headers(&raw[..])
-        {
-            for (name, addr) in msg_sender_addresses(&msg) {
-                graph_name2addrs
-                    .entry(name.to_string())
-                    .and_modify(|addrs: &mut HashSet<String>| {
-                        addrs.insert(addr.to_string());
-                    })
-                    .or_default();
-                graph_addr2names
-                    .entry(addr.to_string())
-                    .and_modify(|names: &mut HashSet<String>| {
-                        names.insert(name.to_string());
-                    })
-                    .or_default();
-            }
-        }
+    for Address {
+        msg_hash: _,
+        role: _,
+        addr,
+        name,
+    } in senders
+    {
+        let Some(name) = name else {
+            continue;
+        };
+        graph_name2addrs
+            .entry(name.clone())
+            .and_modify(|addrs: &mut HashSet<String>| {
+                addrs.insert(addr.clone());
+            })
+            .or_default();
+        graph_addr2names
+            .entry(addr)
+            .and_modify(|names: &mut HashSet<String>| {
+                names.insert(name);
+            })
+            .or_default();
     }
     for (name, addrs) in &graph_name2addrs {
         for addr in addrs {
@@ -81,91 +145,151 @@ pub async fn analyze(
         }
     }
 
-    let mut names_to_addrs: Vec<(&String, &HashSet<String>)> =
-        graph_name2addrs.iter().collect();
-    names_to_addrs.sort_by_key(|(name, _)| *name);
-
-    let indent = "\t";
+    Ok(Report {
+        by_name: entries(
+            &graph_name2addrs,
+            &graph_addr2names,
+            &count_name2addr,
+            noise_threshold,
+        ),
+        by_addr: entries(
+            &graph_addr2names,
+            &graph_name2addrs,
+            &count_addr2name,
+            noise_threshold,
+        ),
+    })
+}
 
-    for (name_0, addrs) in names_to_addrs {
-        println!("name {name_0:?}");
-        for addr in addrs {
-            if let Some(names) = graph_addr2names.get(addr) {
-                // How noisy is this addr? Is it just something like "notifications@github.com"?
-                let is_noisy = names.len() >= noise_threshold;
-                if !is_noisy {
-                    let count = count_name2addr
-                        .get(&(name_0.as_str(), addr.as_str()))
-                        .unwrap_or_else(|| unreachable!(""));
-                    println!("{indent}addr ({count}) {addr:?}");
-                    for name_i in names {
-                        // TODO Meassure edit distance.
-                        if name_i != name_0 {
-                            let count = count_name2addr
-                                .get(&(name_i.as_str(), addr.as_str()))
-                                .unwrap_or_else(|| unreachable!(""));
-                            println!(
-                                "{indent}{indent}name ({count}) {name_i:?}"
-                            );
-                        }
+/// Sorted entries for each key of the forward graph, skipping values linked
+/// to too many keys to be meaningful, like "notifications@github.com".
+fn entries(
+    forward: &HashMap<String, HashSet<String>>,
+    backward: &HashMap<String, HashSet<String>>,
+    counts: &HashMap<(&str, &str), usize>,
+    noise_threshold: usize,
+) -> Vec<Entry> {
+    // Counts are keyed as (name, addr) or (addr, name), same as forward.
+    let count = |key: &str, value: &str| {
+        counts
+            .get(&(key, value))
+            .copied()
+            .unwrap_or_else(|| unreachable!(""))
+    };
+    let mut entries: Vec<Entry> = forward
+        .iter()
+        .map(|(key, values)| {
+            let mut links: Vec<Link> = values
+                .iter()
+                .filter_map(|value| {
+                    let keys = backward.get(value)?;
+                    let is_noisy = keys.len() >= noise_threshold;
+                    if is_noisy {
+                        return None;
                     }
-                }
+                    // TODO Meassure edit distance.
+                    let mut others: Vec<(String, usize)> = keys
+                        .iter()
+                        .filter(|other| *other != key)
+                        .map(|other| (other.clone(), count(other, value)))
+                        .collect();
+                    others.sort();
+                    Some(Link {
+                        value: value.clone(),
+                        count: count(key, value),
+                        others,
+                    })
+                })
+                .collect();
+            links.sort_by(|a, b| a.value.cmp(&b.value));
+            Entry {
+                key: key.clone(),
+                links,
             }
-        }
-    }
+        })
+        .collect();
+    entries.sort_by(|a, b| a.key.cmp(&b.key));
+    entries
+}
 
-    let mut addrs_to_names: Vec<(&String, &HashSet<String>)> =
-        graph_addr2names.iter().collect();
-    addrs_to_names.sort_by_key(|(name, _)| *name);
+#[cfg(test)]
+mod tests {
+    use crate::cfg;
 
-    for (addr_0, names) in addrs_to_names {
-        println!("addr {addr_0:?}");
-        for name in names {
-            if let Some(addrs) = graph_name2addrs.get(name) {
-                let is_noisy = addrs.len() >= noise_threshold;
-                if !is_noisy {
-                    let count = count_addr2name
-                        .get(&(addr_0.as_str(), name.as_str()))
-                        .unwrap_or_else(|| unreachable!(""));
-                    println!("{indent}name ({count}) {name:?}");
-                    for addr_i in addrs {
-                        // TODO Meassure edit distance.
-                        if addr_i != addr_0 {
-                            let count = count_addr2name
-                                .get(&(addr_i.as_str(), name.as_str()))
-                                .unwrap_or_else(|| unreachable!(""));
-                            println!(
-                                "{indent}{indent}addr ({count}) {addr_i:?}"
-                            );
-                        }
-                    }
-                }
-            }
-        }
-    }
+    use super::*;
 
-    Ok(())
-}
+    /// (value, count, "other count"s)
+    type Links<'a> = Vec<(&'a str, usize, Vec<String>)>;
 
-fn msg_sender_addresses<'a>(
-    msg: &'a Message,
-) -> impl Iterator<Item = (Cow<'a, str>, Cow<'a, str>)> + 'a {
-    msg.from()
-        .into_iter()
-        .flat_map(|address| match address {
-            Address::List(addresses) => addresses.to_owned(),
-            Address::Group(groups) => groups
-                .iter()
-                .flat_map(|Group { name: _, addresses }| {
-                    addresses.iter().map(|a| a.to_owned())
-                })
-                .collect(),
-        })
-        .filter_map(|addr| match addr {
-            Addr {
-                name: Some(name),
-                address: Some(addr),
-            } => Some((name, addr)),
-            _ => None,
+    fn summary(entries: &[Entry]) -> Vec<(&str, Links<'_>)> {
+        entries
+            .iter()
+            .map(|Entry { key, links }| {
+                let links = links
+                    .iter()
+                    .map(|link| {
+                        let others = link
+                            .others
+                            .iter()
+                            .map(|(other, count)| format!("{other} {count}"))
+                            .collect();
+                        (link.value.as_str(), link.count, others)
+                    })
+                    .collect();
+                (key.as_str(), links)
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn t_report() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = data::Storage::connect(&cfg::Db {
+            file: dir.path().join("db"),
         })
+        .await
+        .unwrap();
+        for (i, from) in [
+            "Alice <a@x>",
+            "Alice <a@x>",
+            "Alice <alice@y>",
+            "A. <a@x>",
+            "b@x",
+        ]
+        .iter()
+        .enumerate()
+        {
+            let raw = format!("From: {from}\nSubject: {i}\n\nHi");
+            db.store_msg(raw.as_bytes()).await.unwrap();
+        }
+        let report = report(&db, 10, &Filter::default()).await.unwrap();
+        // A name is linked only to the addresses it was seen with after the
+        // first, and vice versa, so "A.", seen once, has no links at all.
+        assert_eq!(
+            vec![
+                ("A.", vec![]),
+                (
+                    "Alice",
+                    vec![
+                        ("a@x", 2, vec!["A. 1".to_string()]),
+                        ("alice@y", 1, vec![]),
+                    ]
+                ),
+            ],
+            summary(&report.by_name)
+        );
+        assert_eq!(
+            vec![
+                (
+                    "a@x",
+                    vec![
+                        ("A.", 1, vec![]),
+                        ("Alice", 2, vec!["alice@y 1".to_string()]),
+                    ]
+                ),
+                ("alice@y", vec![]),
+            ],
+            summary(&report.by_addr)
+        );
+    }
 }
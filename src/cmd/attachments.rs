@@ -0,0 +1,142 @@
+use std::{
+    io::{self, Write},
+    path::PathBuf,
+};
+
+use anyhow::anyhow;
+use tokio::fs;
+
+use crate::{cfg::Cfg, data, file, output::Output};
+
+/// How much of the hash to prefix extracted file names with, to keep
+/// same-named but different attachments from clobbering each other.
+const FILE_NAME_HASH_LEN: usize = 16;
+
+#[derive(clap::Args, Debug)]
+pub struct Cmd {
+    #[clap(subcommand)]
+    attachments: Attachments,
+}
+
+#[derive(clap::Subcommand, Debug)]
+enum Attachments {
+    /// List attachments, largest first: hash, MIME type, size in bytes,
+    /// number of messages and a name. Only these are stored, not the
+    /// attachments' data, which stays in the messages.
+    List {
+        /// Only attachments of this MIME type, like "application/pdf".
+        #[clap(short, long)]
+        mime_type: Option<String>,
+    },
+
+    /// Write attachments, decoded from their messages, to files in the given
+    /// directory, named "<hash-prefix>-<original-name>".
+    Extract {
+        dir: PathBuf,
+
+        /// Only attachments of this MIME type, like "application/pdf".
+        #[clap(short, long)]
+        mime_type: Option<String>,
+
+        /// Only attachments with hashes starting with any of these.
+        hashes: Vec<String>,
+    },
+}
+
+#[derive(serde::Serialize, Debug)]
+#[serde(untagged)]
+pub enum Listing {
+    Attachments(Vec<data::Attachment>),
+    Extracted(Vec<Extracted>),
+}
+
+#[derive(serde::Serialize, Debug)]
+pub struct Extracted {
+    pub hash: String,
+    pub path: PathBuf,
+}
+
+impl Output for Listing {
+    fn write_text(&self, w: &mut dyn Write) -> io::Result<()> {
+        match self {
+            Self::Attachments(attachments) => {
+                for data::Attachment {
+                    hash,
+                    mime_type,
+                    size,
+                    name,
+                    msgs,
+                } in attachments
+                {
+                    let name = name.as_deref().unwrap_or_default();
+                    writeln!(
+                        w,
+                        "{hash}\t{mime_type}\t{size}\t{msgs}\t{name:?}"
+                    )?;
+                }
+            }
+            Self::Extracted(extracted) => {
+                for Extracted { hash: _, path } in extracted {
+                    writeln!(w, "{}", path.display())?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Cmd {
+    pub async fn run(&self, cfg: &Cfg) -> anyhow::Result<Listing> {
+        let db = data::Storage::connect(&cfg.db).await?;
+        let listing = match &self.attachments {
+            Attachments::List { mime_type } => Listing::Attachments(
+                db.fetch_attachments(mime_type.as_deref()).await?,
+            ),
+            Attachments::Extract {
+                dir,
+                mime_type,
+                hashes,
+            } => {
+                fs::create_dir_all(dir).await?;
+                let mut extracted = Vec::new();
+                for attachment in
+                    db.fetch_attachments(mime_type.as_deref()).await?
+                {
+                    if !hashes.is_empty()
+                        && !hashes
+                            .iter()
+                            .any(|h| attachment.hash.starts_with(h))
+                    {
+                        continue;
+                    }
+                    let data = db
+                        .fetch_attachment_data(&attachment.hash)
+                        .await?
+                        .ok_or_else(|| {
+                            anyhow!(
+                                "Attachment vanished: {}",
+                                attachment.hash
+                            )
+                        })?;
+                    let path = dir.join(file_name(&attachment));
+                    fs::write(&path, data).await?;
+                    tracing::info!(?path, "Extracted attachment.");
+                    extracted.push(Extracted {
+                        hash: attachment.hash,
+                        path,
+                    });
+                }
+                Listing::Extracted(extracted)
+            }
+        };
+        Ok(listing)
+    }
+}
+
+fn file_name(attachment: &data::Attachment) -> String {
+    let prefix = &attachment.hash[..FILE_NAME_HASH_LEN];
+    match attachment.name.as_deref().map(file::safe_name) {
+        Some(name) if !name.is_empty() => format!("{prefix}-{name}"),
+        _ => prefix.to_string(),
+    }
+}
@@ -0,0 +1,53 @@
+use std::io::{self, Write};
+
+use anyhow::anyhow;
+
+use crate::{
+    cfg::Cfg,
+    data,
+    output::{self, Output},
+};
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct Cmd {
+    /// Hash, unique hash prefix or Message-ID.
+    msg: String,
+}
+
+/// The message exactly as stored, which as text is written out byte for
+/// byte.
+#[derive(serde::Serialize, Debug)]
+pub struct Raw {
+    pub hash: String,
+    #[serde(serialize_with = "output::lossy")]
+    pub raw: Vec<u8>,
+}
+
+impl Output for Raw {
+    fn write_text(&self, w: &mut dyn Write) -> io::Result<()> {
+        w.write_all(&self.raw)
+    }
+}
+
+impl Cmd {
+    pub async fn run(&self, cfg: &Cfg) -> anyhow::Result<Raw> {
+        let db = data::Storage::connect(&cfg.db).await?;
+        let data::Msg { hash, raw } = fetch(&db, &self.msg).await?;
+        Ok(Raw { hash, raw })
+    }
+}
+
+pub(crate) async fn fetch(
+    db: &data::Storage,
+    spec: &str,
+) -> anyhow::Result<data::Msg> {
+    let hash = db
+        .resolve_msg(spec)
+        .await?
+        .ok_or_else(|| anyhow!("Message not found: {spec:?}"))?;
+    let msg = db
+        .fetch_msg(&hash)
+        .await?
+        .ok_or_else(|| anyhow!("Message vanished: {hash:?}"))?;
+    Ok(msg)
+}
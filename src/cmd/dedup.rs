@@ -0,0 +1,339 @@
+use std::{
+    collections::{BTreeMap, HashSet},
+    io::{self, Write},
+};
+
+use futures::StreamExt;
+
+use super::fetch::truncate;
+use crate::{
+    cfg::Cfg,
+    data::{self, Header, SentBody},
+    hash,
+    output::Output,
+};
+
+const MAX_HEADER_VALUE_LEN: usize = 60;
+const REASON_MESSAGE_ID: &str = "message-id";
+const REASON_BODY: &str = "body";
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct Cmd {
+    /// What makes messages duplicates of each other.
+    #[clap(short, long, value_enum, default_value_t = By::Both)]
+    by: By,
+
+    /// Record the oldest-stored message of each group as the canonical copy
+    /// (in the "duplicates" table). Nothing is ever deleted.
+    #[clap(short, long)]
+    mark: bool,
+}
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+enum By {
+    /// Same Message-ID header.
+    MessageId,
+
+    /// Same text body, modulo whitespace, as well as the same Subject, Date
+    /// and From, for short bodies, like "Thanks!", to be no reason alone.
+    Body,
+
+    Both,
+}
+
+/// Groups of messages stored more than once.
+#[derive(serde::Serialize, Debug)]
+#[serde(transparent)]
+pub struct Duplicates(pub Vec<Group>);
+
+#[derive(serde::Serialize, Debug)]
+pub struct Group {
+    /// "message-id" or "body".
+    pub reason: &'static str,
+    /// The shared Message-ID or hash of the normalized body (which other
+    /// groups, of different senders, subjects or dates, may share).
+    pub key: String,
+    /// Canonical (oldest-stored) first.
+    pub members: Vec<Member>,
+}
+
+#[derive(serde::Serialize, Debug)]
+pub struct Member {
+    pub msg_hash: String,
+    /// Headers that set this member apart from the rest of the group.
+    pub distinct_headers: Vec<DistinctHeader>,
+}
+
+#[derive(serde::Serialize, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct DistinctHeader {
+    pub name: String,
+    pub value: String,
+}
+
+impl Output for Duplicates {
+    fn write_text(&self, w: &mut dyn Write) -> io::Result<()> {
+        let indent = "\t";
+        for Group {
+            reason,
+            key,
+            members,
+        } in &self.0
+        {
+            match *reason {
+                REASON_MESSAGE_ID => writeln!(w, "{reason} {key:?}")?,
+                _ => writeln!(w, "{reason} {key}")?,
+            }
+            for (i, member) in members.iter().enumerate() {
+                let mark = if i == 0 { "*" } else { " " };
+                writeln!(w, "{indent}{mark} {}", member.msg_hash)?;
+                for DistinctHeader { name, value } in &member.distinct_headers
+                {
+                    let value = truncate(value, MAX_HEADER_VALUE_LEN);
+                    writeln!(w, "{indent}{indent}{name}: {value:?}")?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Cmd {
+    pub async fn run(&self, cfg: &Cfg) -> anyhow::Result<Duplicates> {
+        let db = data::Storage::connect(&cfg.db).await?;
+        let mut groups = Vec::new();
+        if matches!(self.by, By::MessageId | By::Both) {
+            let mut by_message_id: BTreeMap<String, Vec<String>> =
+                BTreeMap::new();
+            for (message_id, msg_hash) in
+                db.fetch_message_id_duplicates().await?
+            {
+                by_message_id.entry(message_id).or_default().push(msg_hash);
+            }
+            for (message_id, msg_hashes) in by_message_id {
+                groups.push(
+                    self.group(
+                        &db,
+                        message_id,
+                        &msg_hashes,
+                        REASON_MESSAGE_ID,
+                    )
+                    .await?,
+                );
+            }
+        }
+        if matches!(self.by, By::Body | By::Both) {
+            for ((body_hash, ..), msg_hashes) in body_groups(&db).await? {
+                groups.push(
+                    self.group(&db, body_hash, &msg_hashes, REASON_BODY)
+                        .await?,
+                );
+            }
+        }
+        Ok(Duplicates(groups))
+    }
+
+    /// Find the headers that set each member apart from the rest of the
+    /// group and, if asked to, mark the group in the database.
+    async fn group(
+        &self,
+        db: &data::Storage,
+        key: String,
+        msg_hashes: &[String],
+        reason: &'static str,
+    ) -> anyhow::Result<Group> {
+        let mut headers: Vec<HashSet<(String, String)>> = Vec::new();
+        for msg_hash in msg_hashes {
+            let msg_headers: HashSet<(String, String)> = db
+                .fetch_headers(msg_hash)
+                .filter_map(|res| async { res.ok() })
+                .map(|Header { name, value, .. }| (name, value))
+                .collect()
+                .await;
+            headers.push(msg_headers);
+        }
+        let common: HashSet<(String, String)> = headers
+            .iter()
+            .skip(1)
+            .fold(headers[0].clone(), |common, h| {
+                common.intersection(h).cloned().collect()
+            });
+        let members = msg_hashes
+            .iter()
+            .zip(&headers)
+            .map(|(msg_hash, msg_headers)| {
+                let mut distinct_headers: Vec<DistinctHeader> = msg_headers
+                    .difference(&common)
+                    .map(|(name, value)| DistinctHeader {
+                        name: name.clone(),
+                        value: value.clone(),
+                    })
+                    .collect();
+                distinct_headers.sort();
+                Member {
+                    msg_hash: msg_hash.clone(),
+                    distinct_headers,
+                }
+            })
+            .collect();
+        if self.mark {
+            db.mark_duplicates(&msg_hashes[0], &msg_hashes[1..], reason)
+                .await?;
+        }
+        Ok(Group {
+            reason,
+            key,
+            members,
+        })
+    }
+}
+
+/// Normalized body's hash, Subject, Date and From.
+type BodyKey = (String, Option<String>, Option<i64>, Option<String>);
+
+/// Groups of 2 or more messages with the same normalized body, Subject, Date
+/// and From, oldest-stored first.
+async fn body_groups(
+    db: &data::Storage,
+) -> anyhow::Result<BTreeMap<BodyKey, Vec<String>>> {
+    let mut groups: BTreeMap<BodyKey, Vec<String>> = BTreeMap::new();
+    let mut bodies = db.fetch_bodies();
+    while let Some(body) = bodies.next().await {
+        let SentBody {
+            msg_hash,
+            text,
+            subject,
+            date,
+            sender,
+        } = body?;
+        let Some(text) = text else {
+            continue;
+        };
+        let normalized = normalize_body(&text);
+        if normalized.is_empty() {
+            continue;
+        }
+        groups
+            .entry((hash::sha256(normalized), subject, date, sender))
+            .or_default()
+            .push(msg_hash);
+    }
+    groups.retain(|_, msg_hashes| msg_hashes.len() > 1);
+    Ok(groups)
+}
+
+/// Body text with line endings, trailing whitespace and runs of blank lines
+/// made uniform, since relays and mail stores like to fiddle with those.
+fn normalize_body(text: &str) -> String {
+    let mut normalized = String::new();
+    let mut blank = false;
+    for line in text.lines().map(str::trim_end) {
+        if line.is_empty() {
+            blank = true;
+            continue;
+        }
+        if !normalized.is_empty() {
+            normalized.push('\n');
+            if blank {
+                normalized.push('\n');
+            }
+        }
+        blank = false;
+        normalized.push_str(line);
+    }
+    normalized
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cfg;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn t_dedup() {
+        let dir = tempfile::tempdir().unwrap();
+        let cfg = Cfg {
+            db: cfg::Db {
+                file: dir.path().join("db"),
+            },
+            ..Cfg::default()
+        };
+        let db = data::Storage::connect(&cfg.db).await.unwrap();
+        let msg = |id: u32, from: &str, subject: &str, body: &str| {
+            format!(
+                "Message-ID: <{id}@x>\nFrom: {from}\nSubject: {subject}\n\
+                Date: Sat, 1 Jun 2019 12:00:00 +0000\n\n{body}"
+            )
+        };
+        let mut hashes = Vec::new();
+        for raw in [
+            msg(1, "a@x", "Plans", "Same plans"),
+            // Relayed, under the same Message-ID.
+            format!(
+                "X-Relayed: yes\n{}",
+                msg(1, "a@x", "Plans", "Same plans")
+            ),
+            // Resent, under another.
+            msg(3, "a@x", "Plans", "Same plans  \n\n"),
+            // Merely the same body.
+            msg(4, "b@x", "Re: Plans", "Thanks!"),
+            msg(5, "c@x", "Re: Other", "Thanks!"),
+        ] {
+            hashes.push(db.store_msg(raw.as_bytes()).await.unwrap());
+        }
+        let groups = |dups: &Duplicates| -> Vec<(&str, Vec<String>)> {
+            dups.0
+                .iter()
+                .map(|group| {
+                    let members = group
+                        .members
+                        .iter()
+                        .map(|member| member.msg_hash.clone())
+                        .collect();
+                    (group.reason, members)
+                })
+                .collect()
+        };
+        let cmd = Cmd {
+            by: By::Both,
+            mark: false,
+        };
+        let dups = cmd.run(&cfg).await.unwrap();
+        let first = hashes[0].clone();
+        assert_eq!(
+            vec![
+                (REASON_MESSAGE_ID, hashes[..2].to_vec()),
+                (REASON_BODY, hashes[..3].to_vec()),
+            ],
+            groups(&dups)
+        );
+        assert_eq!(
+            vec![DistinctHeader {
+                name: "X-Relayed".to_string(),
+                value: "yes".to_string(),
+            }],
+            dups.0[0].members[1].distinct_headers
+        );
+        assert!(db.fetch_duplicates().await.unwrap().is_empty());
+
+        let cmd = Cmd {
+            by: By::Body,
+            mark: true,
+        };
+        assert_eq!(1, cmd.run(&cfg).await.unwrap().0.len());
+        let mut expected = vec![
+            (hashes[1].clone(), first.clone(), REASON_BODY.to_string()),
+            (hashes[2].clone(), first.clone(), REASON_BODY.to_string()),
+        ];
+        expected.sort();
+        assert_eq!(expected, db.fetch_duplicates().await.unwrap());
+    }
+
+    #[test]
+    fn t_normalize_body() {
+        assert_eq!("a\nb", normalize_body("a\r\nb\r\n"));
+        assert_eq!("a\n\nb", normalize_body("\n\na  \n\n\n\nb\n\n"));
+        assert_eq!(normalize_body("a \nb"), normalize_body("a\r\nb\n"));
+        assert_eq!("", normalize_body(" \n\t\n"));
+    }
+}
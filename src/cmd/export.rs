@@ -1,16 +1,80 @@
-use std::path::PathBuf;
+use std::{
+    io::{self, Write},
+    path::PathBuf,
+};
 
-use crate::{cfg::Cfg, data};
+use crate::{
+    cfg::Cfg,
+    data::{self, Filter},
+    hook,
+    output::Output,
+    query::Query,
+};
 
 #[derive(clap::Args, Debug, Clone)]
 pub struct Cmd {
     obj_dir: PathBuf,
+
+    /// Export only messages matching this query (see `ma find`).
+    #[clap(short, long)]
+    query: Option<Query>,
+
+    /// Only report how many messages would be written, without writing any.
+    #[clap(long)]
+    dry_run: bool,
+}
+
+#[derive(serde::Serialize, Debug)]
+#[serde(untagged)]
+pub enum Report {
+    Exported(Exported),
+    Planned(Planned),
+}
+
+#[derive(serde::Serialize, Debug)]
+pub struct Exported {
+    pub messages: usize,
+}
+
+/// What a dry run found.
+#[derive(serde::Serialize, Debug)]
+pub struct Planned {
+    /// To be written.
+    pub messages: usize,
+
+    /// Of those to be written, already in the tree, so to be overwritten.
+    pub existing: usize,
+}
+
+impl Output for Report {
+    fn write_text(&self, w: &mut dyn Write) -> io::Result<()> {
+        match self {
+            Self::Exported(Exported { messages }) => {
+                writeln!(w, "Exported {messages} messages.")
+            }
+            Self::Planned(Planned { messages, existing }) => writeln!(
+                w,
+                "Would export {messages} messages. {existing} of them are \
+                already in the tree."
+            ),
+        }
+    }
 }
 
 impl Cmd {
-    pub async fn run(&self, cfg: &Cfg) -> anyhow::Result<()> {
+    pub async fn run(&self, cfg: &Cfg) -> anyhow::Result<Report> {
+        let filter = self.query.as_ref().map(Filter::new).unwrap_or_default();
+        if self.dry_run {
+            let db = data::Storage::connect_read_only(&cfg.db).await?;
+            let (messages, existing) =
+                db.export_plan(&self.obj_dir, &filter).await?;
+            return Ok(Report::Planned(Planned { messages, existing }));
+        }
         let db = data::Storage::connect(&cfg.db).await?;
-        db.export(&self.obj_dir).await?;
-        Ok(())
+        let messages = db.export(&self.obj_dir, &filter).await?;
+        let exported = Exported { messages };
+        let env = [("MA_MESSAGES".to_string(), messages.to_string())];
+        hook::run_all(&cfg.hooks.export, "export", &exported, &env).await;
+        Ok(Report::Exported(exported))
     }
 }
@@ -1,231 +1,1319 @@
-use std::{collections::HashMap, sync::Arc, time::Duration};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet, VecDeque},
+    io::{self, Write},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
 
 use futures::StreamExt;
-use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+use indicatif::{ProgressBar, ProgressStyle};
 use tokio::task::{self, JoinSet};
 
 use crate::{
-    cfg::{Cfg, ImapAccount},
-    data,
-    imap::{self, Session},
+    cfg::{Cfg, ImapAccount, Pop3Account},
+    data::{self, MailboxRun, MsgAttrs, SkippedMsg, POP3_MAILBOX},
+    hook::{self, Hook},
+    imap::{self, throttle, Session},
+    output::{Outcome, Output},
+    pop3, progress,
 };
 
 const MAX_ERR_MSG_LEN: usize = 50;
 
+/// UIDs per UID FETCH.
+const DEFAULT_CHUNK_SIZE: u32 = 500;
+
+/// Bytes per partial fetch of a large message.
+const DEFAULT_PART_SIZE: u32 = 8 * 1024 * 1024;
+
 #[derive(clap::Args, Debug, Clone)]
 pub struct Cmd {
-    /// Re-download everything from scratch.
+    /// Re-download everything from scratch, in the selected accounts and
+    /// mailboxes.
     #[clap(short, long)]
     all: bool,
+
+    /// Fetch only this account, rather than all. Repeatable.
+    #[clap(long = "account", value_name = "NAME")]
+    accounts: Vec<String>,
+
+    /// Fetch only mailboxes matching this pattern, where "*" matches any
+    /// characters and "?" any one character. Repeatable. Ignored mailboxes
+    /// stay ignored.
+    #[clap(long = "mailbox", value_name = "PATTERN")]
+    mailboxes: Vec<String>,
+
+    /// Fetch only messages which arrived on or after this day (YYYY-MM-DD).
+    /// The rest are recorded as skipped, for later fetches to backfill. Not
+    /// applied to POP3 accounts, whose messages are all fetched.
+    #[clap(long, value_name = "DAY")]
+    since: Option<imap::Day>,
+
+    /// Fetch only messages which arrived before this day (YYYY-MM-DD). The
+    /// rest are recorded as skipped, for later fetches to backfill. Not
+    /// applied to POP3 accounts, whose messages are all fetched.
+    #[clap(long, value_name = "DAY")]
+    before: Option<imap::Day>,
+
+    /// Stop everything at the first failed account or mailbox, instead of
+    /// carrying on with the rest.
+    #[clap(long)]
+    fail_fast: bool,
+
+    /// Only connect and report which messages would be downloaded from each
+    /// mailbox, without downloading or recording anything.
+    #[clap(long)]
+    dry_run: bool,
+}
+
+#[derive(serde::Serialize, Debug)]
+#[serde(untagged)]
+pub enum Report {
+    Fetched(Fetched),
+    Planned(Planned),
+}
+
+impl Output for Report {
+    fn write_text(&self, w: &mut dyn Write) -> io::Result<()> {
+        match self {
+            Self::Fetched(fetched) => fetched.write_text(w),
+            Self::Planned(planned) => planned.write_text(w),
+        }
+    }
+
+    fn outcome(&self) -> Outcome {
+        match self {
+            Self::Fetched(fetched) => fetched.outcome(),
+            Self::Planned(planned) => planned.outcome(),
+        }
+    }
+}
+
+/// What would be fetched from each mailbox.
+#[derive(serde::Serialize, Debug, Default)]
+#[serde(transparent)]
+pub struct Planned(pub Vec<MailboxPlan>);
+
+#[derive(serde::Serialize, Debug, Default)]
+pub struct MailboxPlan {
+    pub account: String,
+
+    /// None when the whole account failed.
+    pub mailbox: Option<String>,
+
+    /// Downloading would start from this UID ...
+    pub first_uid: u32,
+
+    /// ... up to this one, if there's anything to download at all.
+    pub last_uid: Option<u32>,
+
+    pub messages: usize,
+
+    /// Would be left for later, as per the date window or size limit.
+    pub skipped: usize,
+
+    pub error: Option<String>,
+}
+
+impl Output for Planned {
+    fn write_text(&self, w: &mut dyn Write) -> io::Result<()> {
+        for MailboxPlan {
+            account,
+            mailbox,
+            first_uid,
+            last_uid,
+            messages,
+            skipped,
+            error,
+        } in &self.0
+        {
+            let mailbox = mailbox.as_deref().unwrap_or("*");
+            let last_uid =
+                last_uid.map_or("*".to_string(), |uid| uid.to_string());
+            write!(
+                w,
+                "{account:?}\t{mailbox:?}\t{first_uid}:{last_uid}\t\
+                {messages}\t{skipped}"
+            )?;
+            if let Some(error) = error {
+                write!(w, "\t{error:?}")?;
+            }
+            writeln!(w)?;
+        }
+        Ok(())
+    }
+
+    fn outcome(&self) -> Outcome {
+        outcome(self.0.iter().map(|m| m.error.is_some()))
+    }
+}
+
+/// What was fetched from each mailbox.
+#[derive(serde::Serialize, Debug, Default)]
+#[serde(transparent)]
+pub struct Fetched(pub Vec<MailboxRun>);
+
+impl Output for Fetched {
+    fn write_text(&self, w: &mut dyn Write) -> io::Result<()> {
+        for MailboxRun {
+            account,
+            mailbox,
+            messages,
+            bytes,
+            error,
+            duration_ms: _,
+            skipped,
+            aborted: _,
+        } in &self.0
+        {
+            let mailbox = mailbox.as_deref().unwrap_or("*");
+            write!(
+                w,
+                "{account:?}\t{mailbox:?}\t{messages}\t{bytes}\t{skipped}"
+            )?;
+            if let Some(error) = error {
+                write!(w, "\t{error:?}")?;
+            }
+            writeln!(w)?;
+        }
+        Ok(())
+    }
+
+    /// Of the mailboxes which ran their course: aborted ones are left out,
+    /// the failure which aborted them already counts.
+    fn outcome(&self) -> Outcome {
+        outcome(
+            self.0
+                .iter()
+                .filter(|run| !run.aborted)
+                .map(MailboxRun::failed),
+        )
+    }
+}
+
+/// Of all mailboxes, given whether each failed.
+fn outcome(failures: impl Iterator<Item = bool>) -> Outcome {
+    let (total, failed) = failures
+        .fold((0, 0), |(total, failed), failure| {
+            (total + 1, failed + usize::from(failure))
+        });
+    if failed == 0 {
+        Outcome::Success
+    } else if failed == total {
+        Outcome::TotalFailure
+    } else {
+        Outcome::PartialFailure
+    }
 }
 
 impl Cmd {
-    pub async fn run(&self, cfg: &Cfg) -> anyhow::Result<()> {
-        let db = data::Storage::connect(&cfg.db).await?;
-        let db = Arc::new(db);
-
-        // XXX Other than for access to set finish messages, also so that bars
-        //     don't disappear from screen when dropped on task error exit.
-        let mut task_bar: HashMap<task::Id, ProgressBar> = HashMap::new();
-        let mut task_account: HashMap<task::Id, String> = HashMap::new();
-        let mut tasks = JoinSet::new();
-        let mp = MultiProgress::new();
-        for (account_name, account_cfg) in &cfg.imap.accounts {
-            let pb_inside_task: ProgressBar = mp.add(prog_bar_spin(0)?);
-            pb_inside_task.set_message(format!("{account_name:?}"));
-            pb_inside_task.enable_steady_tick(Duration::from_millis(100));
-            let pb_outside_task = pb_inside_task.clone();
-            let handle = tasks.spawn({
-                let account_name = account_name.to_string();
-                let account_cfg = account_cfg.clone();
-                let db = Arc::clone(&db);
-                let all = self.all;
-                async move {
+    pub async fn run(&self, cfg: &Cfg) -> anyhow::Result<Report> {
+        let accounts = self.accounts(cfg)?;
+        let opts = Opts {
+            all: self.all,
+            fail_fast: self.fail_fast,
+            mailboxes: self.mailboxes.clone(),
+            since: self.since,
+            before: self.before,
+            msg_hooks: cfg.hooks.message.clone(),
+        };
+        if self.dry_run {
+            let planned = plan(cfg, &accounts, &opts).await?;
+            Ok(Report::Planned(planned))
+        } else {
+            let fetched = fetch(cfg, &accounts, opts).await?;
+            Ok(Report::Fetched(fetched))
+        }
+    }
+
+    /// The selected accounts, of both kinds, by name.
+    fn accounts<'a>(
+        &self,
+        cfg: &'a Cfg,
+    ) -> anyhow::Result<Vec<(&'a String, AccountCfg<'a>)>> {
+        let imap = cfg
+            .imap
+            .accounts
+            .iter()
+            .map(|(name, account)| (name, AccountCfg::Imap(account)));
+        let pop3 = cfg
+            .pop3
+            .accounts
+            .iter()
+            .map(|(name, account)| (name, AccountCfg::Pop3(account)));
+        let mut accounts: Vec<(&String, AccountCfg)> =
+            imap.chain(pop3).collect();
+        accounts.sort_by_key(|(name, _)| *name);
+        if let Some(unknown) = self
+            .accounts
+            .iter()
+            .find(|name| !accounts.iter().any(|(known, _)| known == name))
+        {
+            let known: Vec<&String> =
+                accounts.iter().map(|(name, _)| *name).collect();
+            anyhow::bail!("Unknown account: {unknown:?}. Known: {known:?}");
+        }
+        accounts.retain(|(name, _)| {
+            self.accounts.is_empty() || self.accounts.contains(name)
+        });
+        Ok(accounts)
+    }
+}
+
+/// An account of either kind, as configured.
+#[derive(Debug, Clone, Copy)]
+enum AccountCfg<'a> {
+    Imap(&'a ImapAccount),
+    Pop3(&'a Pop3Account),
+}
+
+async fn fetch(
+    cfg: &Cfg,
+    accounts: &[(&String, AccountCfg<'_>)],
+    opts: Opts,
+) -> anyhow::Result<Fetched> {
+    let db = data::Storage::connect(&cfg.db).await?;
+    let db = Arc::new(db);
+    let run_id = db.start_run().await?;
+    let started = Instant::now();
+
+    // XXX Other than for access to set finish messages, also so that bars
+    //     don't disappear from screen when dropped on task error exit.
+    let mut task_bar: HashMap<task::Id, ProgressBar> = HashMap::new();
+    let mut task_account: HashMap<task::Id, String> = HashMap::new();
+    let mut task_runs: HashMap<task::Id, Arc<Runs>> = HashMap::new();
+    let mut tasks = JoinSet::new();
+    let opts = Arc::new(opts);
+    let mp = progress::multi();
+    for &(account_name, account_cfg) in accounts {
+        let pb_inside_task: ProgressBar =
+            mp.add(prog_bar_spin(0, account_name)?);
+        pb_inside_task.set_message(format!("{account_name:?}"));
+        pb_inside_task.enable_steady_tick(Duration::from_millis(100));
+        let pb_outside_task = pb_inside_task.clone();
+        let name = account_name.to_string();
+        let db = Arc::clone(&db);
+        let opts = Arc::clone(&opts);
+        let runs: Arc<Runs> = Arc::default();
+        let runs_inside_task = Arc::clone(&runs);
+        let handle = match account_cfg {
+            AccountCfg::Imap(account) => {
+                let account = account.clone();
+                tasks.spawn(async move {
                     fetch_account(
                         task::id(),
-                        &account_name,
-                        &account_cfg,
+                        &name,
+                        &account,
                         &db,
-                        all,
+                        &opts,
                         pb_inside_task,
+                        &runs_inside_task,
                     )
                     .await
+                })
+            }
+            AccountCfg::Pop3(account) => {
+                let account = account.clone();
+                tasks.spawn(async move {
+                    fetch_pop3_account(
+                        task::id(),
+                        &name,
+                        &account,
+                        &db,
+                        &opts,
+                        pb_inside_task,
+                        &runs_inside_task,
+                    )
+                    .await
+                })
+            }
+        };
+        let task_id = handle.id();
+        task_account.insert(task_id, account_name.to_string());
+        task_bar.insert(task_id, pb_outside_task);
+        task_runs.insert(task_id, runs);
+    }
+
+    let mut fetched = Fetched::default();
+    let mut aborted = false;
+    while let Some(result) = tasks.join_next_with_id().await {
+        // Whatever mailboxes the task finished, even if it was aborted.
+        let task_id = match &result {
+            Ok((task_id, _)) => *task_id,
+            Err(error) => error.id(),
+        };
+        if let Some(runs) = task_runs.remove(&task_id) {
+            let mut runs = std::mem::take(&mut *runs.lock().unwrap());
+            runs.sort_by_key(|(mailbox_i, _)| *mailbox_i);
+            fetched.0.extend(runs.into_iter().map(|(_, run)| run));
+        }
+        match result {
+            Ok((task_id, result)) => {
+                let account_name = task_account
+                    .get(&task_id)
+                    .unwrap_or_else(|| unreachable!());
+                let pb =
+                    task_bar.get(&task_id).unwrap_or_else(|| unreachable!());
+                pb.set_style(prog_sty_spin_fin()?);
+                match result {
+                    Ok(()) => {
+                        tracing::info!(
+                            ?account_name,
+                            ?task_id,
+                            "Account fetch succeeded."
+                        );
+                        prog_fin_ok(account_name, pb);
+                    }
+                    Err(error) => {
+                        tracing::error!(
+                            ?account_name,
+                            ?task_id,
+                            ?error,
+                            "Account fetch failed."
+                        );
+                        prog_fin_err(
+                            account_name,
+                            pb,
+                            &error.root_cause().to_string(),
+                        );
+                        fetched.0.push(MailboxRun {
+                            account: account_name.to_string(),
+                            error: Some(error.root_cause().to_string()),
+                            duration_ms: millis(started),
+                            ..MailboxRun::default()
+                        });
+                    }
                 }
-            });
-            let task_id = handle.id();
-            task_account.insert(task_id, account_name.to_string());
-            task_bar.insert(task_id, pb_outside_task);
+            }
+            Err(e) if e.is_cancelled() => {
+                let error: task::JoinError = e;
+                let task_id = error.id();
+                let account_name = task_account
+                    .get(&task_id)
+                    .unwrap_or_else(|| unreachable!());
+                let pb =
+                    task_bar.get(&task_id).unwrap_or_else(|| unreachable!());
+                tracing::error!(
+                    ?account_name,
+                    ?task_id,
+                    ?error,
+                    "Account fetch cancelled."
+                );
+                let err_msg = if aborted {
+                    "Aborted after another failure (--fail-fast).".to_string()
+                } else {
+                    error.to_string()
+                };
+                prog_fin_err(account_name, pb, &err_msg);
+                fetched.0.push(MailboxRun {
+                    account: account_name.to_string(),
+                    error: Some(err_msg),
+                    duration_ms: millis(started),
+                    aborted,
+                    ..MailboxRun::default()
+                });
+            }
+            Err(e) if e.is_panic() => {
+                let error: task::JoinError = e;
+                let task_id = error.id();
+                let account_name = task_account
+                    .get(&task_id)
+                    .unwrap_or_else(|| unreachable!());
+                let pb =
+                    task_bar.get(&task_id).unwrap_or_else(|| unreachable!());
+                let err_msg = error.to_string();
+                let panic = error.into_panic();
+                tracing::error!(
+                    ?account_name,
+                    ?task_id,
+                    ?err_msg,
+                    ?panic,
+                    "Account fetch panicked."
+                );
+                prog_fin_err(account_name, pb, &err_msg);
+                fetched.0.push(MailboxRun {
+                    account: account_name.to_string(),
+                    error: Some(err_msg),
+                    duration_ms: millis(started),
+                    ..MailboxRun::default()
+                });
+            }
+            Err(error) => unreachable!(
+                "tokio::task::JoinError was neither panic nor cancellation:\
+                    {error:?}"
+            ),
+        }
+        let failed = fetched.outcome() != Outcome::Success;
+        if opts.fail_fast && failed && !aborted {
+            tracing::warn!("Aborting the remaining accounts.");
+            tasks.abort_all();
+            aborted = true;
+        }
+    }
+
+    db.end_run(run_id, &fetched.0).await?;
+    hook::run_all(&cfg.hooks.fetch, "fetch", &fetched, &fetched.env()).await;
+    Ok(fetched)
+}
+
+impl Fetched {
+    /// Totals, overall and per account, for hooks.
+    fn env(&self) -> Vec<(String, String)> {
+        let mut totals: BTreeMap<String, (u32, i64, u32)> = BTreeMap::new();
+        for run in &self.0 {
+            for key in [String::new(), hook::env_name(&run.account)] {
+                let (messages, bytes, errors) =
+                    totals.entry(key).or_default();
+                *messages += run.messages;
+                *bytes += run.bytes;
+                *errors += u32::from(run.failed());
+            }
         }
+        totals
+            .into_iter()
+            .flat_map(|(account, (messages, bytes, errors))| {
+                let prefix = if account.is_empty() {
+                    "MA".to_string()
+                } else {
+                    format!("MA_ACCOUNT_{account}")
+                };
+                [
+                    (format!("{prefix}_MESSAGES"), messages.to_string()),
+                    (format!("{prefix}_BYTES"), bytes.to_string()),
+                    (format!("{prefix}_ERRORS"), errors.to_string()),
+                ]
+            })
+            .collect()
+    }
+}
+
+/// What each account task needs to know, beyond the account itself.
+#[derive(Debug)]
+struct Opts {
+    all: bool,
+    fail_fast: bool,
+
+    /// Patterns of mailboxes to fetch. All, if none.
+    mailboxes: Vec<String>,
+
+    since: Option<imap::Day>,
+    before: Option<imap::Day>,
 
-        while let Some(result) = tasks.join_next_with_id().await {
-            match result {
-                Ok((task_id, result)) => {
-                    let account_name = task_account
-                        .get(&task_id)
-                        .unwrap_or_else(|| unreachable!());
-                    let pb = task_bar
-                        .get(&task_id)
-                        .unwrap_or_else(|| unreachable!());
-                    pb.set_style(prog_sty_spin_fin()?);
-                    match result {
-                        Ok(()) => {
-                            tracing::info!(
-                                ?account_name,
-                                ?task_id,
-                                "Account fetch succeeded."
-                            );
-                            prog_fin_ok(account_name, &pb);
-                        }
-                        Err(error) => {
-                            tracing::error!(
-                                ?account_name,
-                                ?task_id,
-                                ?error,
-                                "Account fetch failed."
-                            );
-                            prog_fin_err(
-                                account_name,
-                                &pb,
-                                &error.root_cause().to_string(),
-                            );
-                        }
+    msg_hooks: Vec<Hook>,
+}
+
+/// What the per-message hooks get on stdin.
+#[derive(serde::Serialize, Debug)]
+struct NewMsg<'a> {
+    hash: &'a str,
+    account: &'a str,
+    mailbox: &'a str,
+
+    /// Of an IMAP message.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    uid: Option<u32>,
+
+    /// Of a POP3 message, its unique-id.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    uidl: Option<&'a str>,
+}
+
+async fn run_msg_hooks(opts: &Opts, msg: &NewMsg<'_>) {
+    if opts.msg_hooks.is_empty() {
+        return;
+    }
+    let mut env = vec![
+        ("MA_MSG_HASH".to_string(), msg.hash.to_string()),
+        ("MA_ACCOUNT".to_string(), msg.account.to_string()),
+        ("MA_MAILBOX".to_string(), msg.mailbox.to_string()),
+    ];
+    if let Some(uid) = msg.uid {
+        env.push(("MA_UID".to_string(), uid.to_string()));
+    }
+    if let Some(uidl) = msg.uidl {
+        env.push(("MA_UIDL".to_string(), uidl.to_string()));
+    }
+    hook::run_all(&opts.msg_hooks, "message", msg, &env).await;
+}
+
+async fn plan(
+    cfg: &Cfg,
+    accounts: &[(&String, AccountCfg<'_>)],
+    opts: &Opts,
+) -> anyhow::Result<Planned> {
+    let db = data::Storage::connect_read_only(&cfg.db).await?;
+    let plans = futures::future::join_all(accounts.iter().map(
+        |(account_name, account)| {
+            let db = &db;
+            async move {
+                let mut planned = Vec::new();
+                let result = match account {
+                    AccountCfg::Imap(account) => {
+                        plan_account(
+                            account_name,
+                            account,
+                            db,
+                            opts,
+                            &mut planned,
+                        )
+                        .await
                     }
+                    AccountCfg::Pop3(account) => {
+                        plan_pop3_account(
+                            account_name,
+                            account,
+                            db,
+                            opts,
+                            &mut planned,
+                        )
+                        .await
+                    }
+                };
+                if let Err(error) = result {
+                    tracing::error!(?account_name, ?error, "Failed to plan.");
+                    planned.push(MailboxPlan {
+                        account: account_name.to_string(),
+                        error: Some(error.root_cause().to_string()),
+                        ..MailboxPlan::default()
+                    });
                 }
-                Err(e) if e.is_cancelled() => {
-                    let error: task::JoinError = e;
-                    let task_id = error.id();
-                    let account_name = task_account
-                        .get(&task_id)
-                        .unwrap_or_else(|| unreachable!());
-                    let pb = task_bar
-                        .get(&task_id)
-                        .unwrap_or_else(|| unreachable!());
-                    tracing::error!(
-                        ?account_name,
-                        ?task_id,
-                        ?error,
-                        "Account fetch cancelled."
-                    );
-                    prog_fin_err(account_name, &pb, &error.to_string());
-                }
-                Err(e) if e.is_panic() => {
-                    let error: task::JoinError = e;
-                    let task_id = error.id();
-                    let account_name = task_account
-                        .get(&task_id)
-                        .unwrap_or_else(|| unreachable!());
-                    let pb = task_bar
-                        .get(&task_id)
-                        .unwrap_or_else(|| unreachable!());
-                    let err_msg = error.to_string();
-                    let panic = error.into_panic();
-                    tracing::error!(
-                        ?account_name,
-                        ?task_id,
-                        ?err_msg,
-                        ?panic,
-                        "Account fetch panicked."
-                    );
-                    prog_fin_err(account_name, &pb, &err_msg);
-                }
-                Err(error) => unreachable!(
-                    "tokio::task::JoinError was neither panic nor cancellation:\
-                    {error:?}"
-                ),
+                planned
+            }
+        },
+    ))
+    .await;
+    Ok(Planned(plans.into_iter().flatten().collect()))
+}
+
+async fn plan_account(
+    account_name: &str,
+    account: &ImapAccount,
+    db: &data::Storage,
+    opts: &Opts,
+    planned: &mut Vec<MailboxPlan>,
+) -> anyhow::Result<()> {
+    let limits = throttle::Limits::new(account)?;
+    let mut session = Session::new(account, &limits).await?;
+    let mailboxes = list_mailboxes(&mut session, account, opts).await?;
+    for mailbox in mailboxes {
+        let last_seen_uid: u32 = db
+            .fetch_last_seen(account_name, &mailbox)
+            .await?
+            .unwrap_or(0);
+        let first_uid = if opts.all { 1 } else { last_seen_uid + 1 };
+        let mut plan = MailboxPlan {
+            account: account_name.to_string(),
+            mailbox: Some(mailbox.clone()),
+            first_uid,
+            ..MailboxPlan::default()
+        };
+        let pending = db.fetch_skipped(account_name, &mailbox).await?;
+        match select(
+            &mut session,
+            account,
+            &mailbox,
+            first_uid,
+            &pending,
+            opts,
+        )
+        .await
+        {
+            Ok(Selection { uids, skipped, .. }) => {
+                plan.last_uid = uids.last().copied();
+                plan.messages = uids.len();
+                plan.skipped = skipped.len();
+            }
+            Err(error) => {
+                tracing::error!(?mailbox, ?error, "Failed to plan mailbox.");
+                plan.error = Some(error.to_string());
             }
         }
+        planned.push(plan);
+    }
+    Ok(())
+}
 
-        Ok(())
+/// Of POP3 messages, their numbers stand in for UIDs.
+async fn plan_pop3_account(
+    account_name: &str,
+    account: &Pop3Account,
+    db: &data::Storage,
+    opts: &Opts,
+    planned: &mut Vec<MailboxPlan>,
+) -> anyhow::Result<()> {
+    if !selected(POP3_MAILBOX, opts) {
+        return Ok(());
+    }
+    let mut session = pop3::Session::new(account).await?;
+    let mut plan = MailboxPlan {
+        account: account_name.to_string(),
+        mailbox: Some(POP3_MAILBOX.to_string()),
+        first_uid: 1,
+        ..MailboxPlan::default()
+    };
+    match new_pop3_msgs(&mut session, account_name, db, opts).await? {
+        Ok(new) => {
+            plan.first_uid = new.first().map_or(1, |(msg, _)| *msg);
+            plan.last_uid = new.last().map(|(msg, _)| *msg);
+            plan.messages = new.len();
+        }
+        Err(error) => {
+            tracing::error!(?error, "Failed to plan POP3 account.");
+            plan.error = Some(error.to_string());
+        }
     }
+    planned.push(plan);
+    session.quit().await?;
+    Ok(())
 }
 
+/// Runs of the mailboxes an account's task has finished, tagged with their
+/// queue positions. Kept outside of the task, to outlast its abortion.
+type Runs = Mutex<Vec<(usize, MailboxRun)>>;
+
+/// Each mailbox's run is added to the runs as soon as it's finished, even if
+/// the account fails, or is aborted, part way.
 #[tracing::instrument(name = "account", skip_all, fields(name = account_name, task_id = ?task_id))]
 async fn fetch_account(
     task_id: task::Id,
     account_name: &str,
     account: &ImapAccount,
     db: &data::Storage,
-    all: bool,
+    opts: &Opts,
     pb: ProgressBar,
+    runs: &Runs,
 ) -> anyhow::Result<()> {
     tracing::info!(?account, "Fetching.");
-    let mut session = Session::new(account).await?;
-    let mut mailboxes = session
-        .list_mailboxes()
-        .await?
-        .filter(|mailbox| {
-            let ignored = account.ignore_mailboxes.contains(mailbox);
-            async move { !ignored }
-        })
-        .collect::<Vec<String>>()
-        .await;
-    mailboxes.sort();
+    // One for all of the account's connections.
+    let limits = throttle::Limits::new(account)?;
+    let mut session = Session::new(account, &limits).await?;
+    let mailboxes = list_mailboxes(&mut session, account, opts).await?;
     for mailbox in &mailboxes {
         let meta = session.examine(mailbox).await?;
         let exists = meta.exists;
         pb.inc_length(u64::from(exists));
     }
     let total_mailboxes = mailboxes.len();
-    for (mailbox_i, mailbox) in mailboxes.into_iter().enumerate() {
-        let status_mailbox = format!(
-            "{:?} ({mailbox_i} / {total_mailboxes})",
-            truncate(&mailbox, 25)
-        );
-        let status_account_mailbox = {
-            let mailbox_status = console::style(&status_mailbox).dim();
-            format!("{account_name:?} : {mailbox_status}")
-        };
-        pb.set_message(status_account_mailbox);
-        let last_seen_uid: u32 = db
-            .fetch_last_seen(account_name, &mailbox)
-            .await?
-            .unwrap_or(0);
-        let first_uid = if all { 1 } else { last_seen_uid + 1 };
-        match session.fetch_msgs_from(&mailbox, first_uid).await {
+    let connections = account
+        .connections
+        .unwrap_or(1)
+        .clamp(1, total_mailboxes.max(1));
+    let mut sessions = vec![session];
+    while sessions.len() < connections {
+        match Session::new(account, &limits).await {
+            Ok(session) => sessions.push(session),
             Err(error) => {
-                tracing::error!(
-                    ?mailbox,
+                // Likely over the provider's limit, which isn't fatal.
+                tracing::warn!(
                     ?error,
-                    "Failed to fetch mailbox. Skipping it."
+                    connections = sessions.len(),
+                    "Failed to open another connection. Carrying on with fewer."
                 );
+                break;
             }
-            Ok((_meta, mut msgs)) => {
-                let mut ord_prev: u32 = 0;
-                while let Some(imap::Msg {
-                    uid,
-                    ord: ord_curr,
-                    raw,
-                }) = msgs.next().await
-                {
-                    let subject = mail_parser::MessageParser::default()
-                        .parse(&raw[..])
-                        .and_then(|msg| {
-                            msg.subject().map(|subj| truncate(subj, 25))
-                        })
-                        .unwrap_or_default();
-                    let status_mailbox_msg =
-                        format!("{status_mailbox}: {subject:?}");
-                    let status_account_mailbox_msg = {
-                        let status_mailbox_msg =
-                            console::style(status_mailbox_msg).dim();
-                        format!("{account_name:?} : {status_mailbox_msg}")
-                    };
-                    pb.set_message(status_account_mailbox_msg);
-                    // TODO Batch insertions.
-                    db.store_msg(&raw[..]).await?;
-                    if uid > last_seen_uid {
-                        db.store_last_seen(account_name, &mailbox, uid)
-                            .await?;
-                    }
-                    pb.inc(u64::from(ord_curr - ord_prev));
-                    ord_prev = ord_curr;
+        }
+    }
+    let queue: Mutex<VecDeque<(usize, String)>> =
+        Mutex::new(mailboxes.into_iter().enumerate().collect());
+    let stop = AtomicBool::new(false);
+    let ctx = Account {
+        name: account_name,
+        cfg: account,
+        db,
+        opts,
+        pb: &pb,
+        total_mailboxes,
+    };
+    let workers = sessions
+        .into_iter()
+        .map(|session| fetch_queue(session, &ctx, &queue, &stop, runs));
+    futures::future::join_all(workers)
+        .await
+        .into_iter()
+        .collect()
+}
+
+/// The maildrop's run is added to the runs once it's finished, even if the
+/// account fails part way.
+#[tracing::instrument(name = "account", skip_all, fields(name = account_name, task_id = ?task_id))]
+async fn fetch_pop3_account(
+    task_id: task::Id,
+    account_name: &str,
+    account: &Pop3Account,
+    db: &data::Storage,
+    opts: &Opts,
+    pb: ProgressBar,
+    runs: &Runs,
+) -> anyhow::Result<()> {
+    if !selected(POP3_MAILBOX, opts) {
+        return Ok(());
+    }
+    if opts.since.is_some() || opts.before.is_some() {
+        tracing::warn!("--since and --before don't apply to POP3 accounts.");
+    }
+    tracing::info!(?account, "Fetching.");
+    let mut session = pop3::Session::new(account).await?;
+    let mut run = MailboxRun {
+        account: account_name.to_string(),
+        mailbox: Some(POP3_MAILBOX.to_string()),
+        ..MailboxRun::default()
+    };
+    let result =
+        fetch_maildrop(&mut session, account_name, db, opts, &pb, &mut run)
+            .await;
+    runs.lock().unwrap().push((0, run));
+    result?;
+    if let Err(error) = session.quit().await {
+        tracing::warn!(?error, "Failed to quit POP3 session.");
+    }
+    Ok(())
+}
+
+/// A failure of POP3 is recorded in the run, as of an IMAP mailbox, rather
+/// than returned, which is left to failures of the whole account.
+async fn fetch_maildrop(
+    session: &mut pop3::Session,
+    account_name: &str,
+    db: &data::Storage,
+    opts: &Opts,
+    pb: &ProgressBar,
+    run: &mut MailboxRun,
+) -> anyhow::Result<()> {
+    pb.set_message(format!("{account_name:?} : {POP3_MAILBOX}"));
+    let started = Instant::now();
+    if let Err(error) =
+        fetch_pop3_msgs(session, account_name, db, opts, pb, run).await?
+    {
+        tracing::error!(?error, "Failed to fetch POP3 messages.");
+        run.error = Some(error.to_string());
+    }
+    run.duration_ms = millis(started);
+    Ok(())
+}
+
+/// The outer error is of the account, like of storage, the inner of POP3.
+async fn fetch_pop3_msgs(
+    session: &mut pop3::Session,
+    account_name: &str,
+    db: &data::Storage,
+    opts: &Opts,
+    pb: &ProgressBar,
+    run: &mut MailboxRun,
+) -> anyhow::Result<pop3::Result<()>> {
+    let new = match new_pop3_msgs(session, account_name, db, opts).await? {
+        Ok(new) => new,
+        Err(error) => return Ok(Err(error)),
+    };
+    pb.inc_length(u64::try_from(new.len())?);
+    for (msg, uid) in new {
+        let raw = match session.retr(msg).await {
+            Ok(raw) => raw,
+            Err(error) => return Ok(Err(error)),
+        };
+        let hash = db.store_msg(&raw).await?;
+        db.store_pop3_uid(account_name, &uid, &hash).await?;
+        let new_msg = NewMsg {
+            hash: &hash,
+            account: account_name,
+            mailbox: POP3_MAILBOX,
+            uid: None,
+            uidl: Some(&uid),
+        };
+        run_msg_hooks(opts, &new_msg).await;
+        run.messages += 1;
+        run.bytes += i64::try_from(raw.len())?;
+        pb.inc(1);
+    }
+    Ok(Ok(()))
+}
+
+/// Numbers and unique-ids of the messages not fetched before, or of all,
+/// with `--all`.
+async fn new_pop3_msgs(
+    session: &mut pop3::Session,
+    account_name: &str,
+    db: &data::Storage,
+    opts: &Opts,
+) -> anyhow::Result<pop3::Result<Vec<(u32, String)>>> {
+    let seen = if opts.all {
+        HashSet::new()
+    } else {
+        db.fetch_pop3_uids(account_name).await?
+    };
+    Ok(session.uidl().await.map(|uids| {
+        uids.into_iter()
+            .filter(|(_, uid)| !seen.contains(uid))
+            .collect()
+    }))
+}
+
+/// An account being fetched, as shared by its connections.
+struct Account<'a> {
+    name: &'a str,
+    cfg: &'a ImapAccount,
+    db: &'a data::Storage,
+    opts: &'a Opts,
+    pb: &'a ProgressBar,
+    total_mailboxes: usize,
+}
+
+/// Fetch mailboxes off the queue, over one connection, until none are left,
+/// or until another connection fails with `--fail-fast`.
+async fn fetch_queue(
+    mut session: Session,
+    ctx: &Account<'_>,
+    queue: &Mutex<VecDeque<(usize, String)>>,
+    stop: &AtomicBool,
+    runs: &Runs,
+) -> anyhow::Result<()> {
+    while !stop.load(Ordering::Relaxed) {
+        let Some((mailbox_i, mailbox)) =
+            queue.lock().ok().and_then(|mut queue| queue.pop_front())
+        else {
+            break;
+        };
+        let mut run = MailboxRun {
+            account: ctx.name.to_string(),
+            mailbox: Some(mailbox.clone()),
+            ..MailboxRun::default()
+        };
+        let status_mailbox = format!(
+            "{:?} ({mailbox_i} / {})",
+            truncate(&mailbox, 25),
+            ctx.total_mailboxes
+        );
+        let result = fetch_mailbox(
+            &mut session,
+            ctx,
+            &mailbox,
+            &status_mailbox,
+            &mut run,
+        )
+        .await;
+        let failed = run.error.is_some();
+        runs.lock().unwrap().push((mailbox_i, run));
+        if let Err(error) = result {
+            stop.store(true, Ordering::Relaxed);
+            return Err(error);
+        }
+        if failed && ctx.opts.fail_fast {
+            stop.store(true, Ordering::Relaxed);
+        }
+    }
+    Ok(())
+}
+
+/// A failure to fetch the mailbox is recorded in its run, rather than
+/// returned, which is left to failures of the whole account, like storage.
+async fn fetch_mailbox(
+    session: &mut Session,
+    ctx: &Account<'_>,
+    mailbox: &str,
+    status_mailbox: &str,
+    run: &mut MailboxRun,
+) -> anyhow::Result<()> {
+    let status_account_mailbox = {
+        let mailbox_status = console::style(status_mailbox).dim();
+        format!("{:?} : {mailbox_status}", ctx.name)
+    };
+    ctx.pb.set_message(status_account_mailbox);
+    let mailbox_started = Instant::now();
+    if let Err(error) =
+        fetch_chunks(session, ctx, mailbox, status_mailbox, run).await?
+    {
+        tracing::error!(
+            ?mailbox,
+            ?error,
+            "Failed to fetch mailbox. Skipping it."
+        );
+        run.error = Some(error.to_string());
+    }
+    run.duration_ms = millis(mailbox_started);
+    Ok(())
+}
+
+/// Fetch the selected messages in chunks of UIDs, checkpointing the last
+/// seen UID after each, so that an interrupted fetch of a huge mailbox
+/// resumes from the last chunk, rather than from the start.
+///
+/// The outer error is of the account, like of storage, the inner of the
+/// mailbox, like of IMAP.
+async fn fetch_chunks(
+    session: &mut Session,
+    ctx: &Account<'_>,
+    mailbox: &str,
+    status_mailbox: &str,
+    run: &mut MailboxRun,
+) -> anyhow::Result<anyhow::Result<()>> {
+    let Account {
+        name: account_name,
+        cfg: account,
+        db,
+        opts,
+        pb,
+        total_mailboxes: _,
+    } = *ctx;
+    let exists = match session.examine(mailbox).await {
+        Ok(meta) => meta.exists,
+        Err(error) => return Ok(Err(error.into())),
+    };
+    let last_seen_uid: u32 = db
+        .fetch_last_seen(account_name, mailbox)
+        .await?
+        .unwrap_or(0);
+    let first_uid = if opts.all { 1 } else { last_seen_uid + 1 };
+    let pending = db.fetch_skipped(account_name, mailbox).await?;
+    let selection =
+        match select(session, account, mailbox, first_uid, &pending, opts)
+            .await
+        {
+            Ok(selection) => selection,
+            Err(error) => return Ok(Err(error)),
+        };
+    db.store_skipped(account_name, mailbox, &selection.skipped)
+        .await?;
+    run.skipped = u32::try_from(selection.skipped.len())?;
+    // The bar counts all messages, so count those not to be fetched too.
+    pb.inc(
+        u64::from(exists)
+            .saturating_sub(u64::try_from(selection.uids.len())?),
+    );
+    let pending: HashSet<u32> =
+        pending.into_iter().map(|skipped| skipped.uid).collect();
+    let chunk_size = account.chunk_size.unwrap_or(DEFAULT_CHUNK_SIZE).max(1);
+    let mut checkpoint = last_seen_uid;
+    for chunk in selection.uids.chunks(usize::try_from(chunk_size)?) {
+        if let Err(error) = fetch_chunk(
+            session,
+            ctx,
+            mailbox,
+            status_mailbox,
+            chunk,
+            &pending,
+            run,
+        )
+        .await?
+        {
+            return Ok(Err(error.into()));
+        }
+        if let Some(&newest) = chunk.last().filter(|uid| **uid > checkpoint) {
+            db.store_last_seen(account_name, mailbox, newest).await?;
+            checkpoint = newest;
+        }
+    }
+    // Skipped messages are recorded as such, so are seen too.
+    if let Some(newest) = selection.newest.filter(|uid| *uid > checkpoint) {
+        db.store_last_seen(account_name, mailbox, newest).await?;
+    }
+    Ok(Ok(()))
+}
+
+/// Messages larger than the part size are fetched part by part.
+async fn fetch_chunk(
+    session: &mut Session,
+    ctx: &Account<'_>,
+    mailbox: &str,
+    status_mailbox: &str,
+    uids: &[u32],
+    pending: &HashSet<u32>,
+    run: &mut MailboxRun,
+) -> anyhow::Result<imap::Result<()>> {
+    let part_size = ctx.cfg.part_size.unwrap_or(DEFAULT_PART_SIZE).max(1);
+    let attrs: HashMap<u32, imap::Attrs> =
+        match session.attrs(mailbox, uids).await {
+            Ok(attrs) => attrs.into_iter().map(|a| (a.uid, a)).collect(),
+            Err(error) => return Ok(Err(error)),
+        };
+    let mut large: Vec<(u32, u32)> = attrs
+        .values()
+        .filter_map(|a| a.size.map(|size| (a.uid, size)))
+        .filter(|(_, size)| *size > part_size)
+        .collect();
+    large.sort_unstable();
+    let mut small: Vec<u32> = uids
+        .iter()
+        .copied()
+        .filter(|uid| !large.iter().any(|(large, _)| large == uid))
+        .collect();
+    let mut backoff = session.backoff();
+    while !small.is_empty() {
+        let mut msgs = match session.fetch_msgs(mailbox, &small).await {
+            Ok(msgs) => msgs,
+            Err(error) => return Ok(Err(error)),
+        };
+        let mut fetched = HashSet::new();
+        let mut failed = None;
+        while let Some(msg) = msgs.next().await {
+            match msg {
+                Ok(msg) => {
+                    store(
+                        ctx,
+                        mailbox,
+                        status_mailbox,
+                        &msg,
+                        attrs.get(&msg.uid),
+                        pending,
+                        run,
+                    )
+                    .await?;
+                    fetched.insert(msg.uid);
                 }
+                Err(error) => failed = Some(error),
             }
         }
+        drop(msgs);
+        // Asked again, after a pause, for the rest, if the server was
+        // throttling us.
+        match failed {
+            None => break,
+            Some(error) if backoff.retry(&error).await => {
+                small.retain(|uid| !fetched.contains(uid));
+            }
+            Some(error) => return Ok(Err(error)),
+        }
+    }
+    for (uid, size) in large {
+        let raw =
+            match fetch_large(session, ctx, mailbox, uid, size, part_size)
+                .await?
+            {
+                Ok(raw) => raw,
+                Err(error) => return Ok(Err(error)),
+            };
+        let msg = imap::Msg { uid, raw };
+        store(
+            ctx,
+            mailbox,
+            status_mailbox,
+            &msg,
+            attrs.get(&uid),
+            pending,
+            run,
+        )
+        .await?;
+        ctx.db.delete_partial(ctx.name, mailbox, uid).await?;
+    }
+    Ok(Ok(()))
+}
+
+/// Fetch a message in parts, storing each as it arrives, and starting after
+/// those stored before, by an interrupted fetch.
+async fn fetch_large(
+    session: &mut Session,
+    ctx: &Account<'_>,
+    mailbox: &str,
+    uid: u32,
+    size: u32,
+    part_size: u32,
+) -> anyhow::Result<imap::Result<Vec<u8>>> {
+    let mut raw = ctx.db.fetch_partial(ctx.name, mailbox, uid).await?;
+    if !raw.is_empty() {
+        tracing::info!(
+            ?mailbox,
+            uid,
+            offset = raw.len(),
+            "Resuming message."
+        );
     }
+    while raw.len() < usize::try_from(size)? {
+        let part = match session
+            .fetch_part(mailbox, uid, raw.len(), part_size)
+            .await
+        {
+            Ok(part) => part,
+            Err(error) => return Ok(Err(error)),
+        };
+        // Shorter than its RFC822.SIZE, which is only supposed to be exact.
+        if part.is_empty() {
+            break;
+        }
+        ctx.db
+            .store_partial(ctx.name, mailbox, uid, raw.len(), &part)
+            .await?;
+        raw.extend_from_slice(&part);
+    }
+    Ok(Ok(raw))
+}
+
+async fn store(
+    ctx: &Account<'_>,
+    mailbox: &str,
+    status_mailbox: &str,
+    msg: &imap::Msg,
+    attrs: Option<&imap::Attrs>,
+    pending: &HashSet<u32>,
+    run: &mut MailboxRun,
+) -> anyhow::Result<()> {
+    let Account {
+        name: account_name,
+        db,
+        opts,
+        pb,
+        ..
+    } = *ctx;
+    let &imap::Msg { uid, ref raw } = msg;
+    let subject = mail_parser::MessageParser::default()
+        .parse(raw)
+        .and_then(|msg| msg.subject().map(|subj| truncate(subj, 25)))
+        .unwrap_or_default();
+    let status_mailbox_msg = format!("{status_mailbox}: {subject:?}");
+    let status_account_mailbox_msg = {
+        let status_mailbox_msg = console::style(status_mailbox_msg).dim();
+        format!("{account_name:?} : {status_mailbox_msg}")
+    };
+    pb.set_message(status_account_mailbox_msg);
+    // TODO Batch insertions.
+    let hash = db.store_msg(raw).await?;
+    db.store_location(&hash, account_name, mailbox, uid).await?;
+    if let Some(attrs) = attrs {
+        let attrs = MsgAttrs {
+            flags: attrs.flags.clone(),
+            internal_date: attrs.internal_date.clone(),
+        };
+        db.store_attrs(account_name, mailbox, uid, &attrs).await?;
+    }
+    if pending.contains(&uid) {
+        db.delete_skipped(account_name, mailbox, uid).await?;
+    }
+    let new_msg = NewMsg {
+        hash: &hash,
+        account: account_name,
+        mailbox,
+        uid: Some(uid),
+        uidl: None,
+    };
+    run_msg_hooks(opts, &new_msg).await;
+    run.messages += 1;
+    run.bytes += i64::try_from(raw.len())?;
+    pb.inc(1);
     Ok(())
 }
 
+const SKIP_DATE: &str = "date";
+const SKIP_SIZE: &str = "size";
+
+/// Which messages of a mailbox to fetch and which to skip, for now.
+struct Selection {
+    /// To fetch, in order.
+    uids: Vec<u32>,
+
+    skipped: Vec<SkippedMsg>,
+
+    /// Of the new messages, fetched or skipped.
+    newest: Option<u32>,
+}
+
+/// Of the new messages, and those skipped before, those within the date
+/// window and size limit.
+async fn select(
+    session: &mut Session,
+    account: &ImapAccount,
+    mailbox: &str,
+    first_uid: u32,
+    pending: &[SkippedMsg],
+    opts: &Opts,
+) -> anyhow::Result<Selection> {
+    let mut uids = session.uids_from(mailbox, first_uid).await?;
+    let newest = uids.last().copied();
+    uids.extend(pending.iter().map(|skipped| skipped.uid));
+    uids.sort_unstable();
+    uids.dedup();
+    let mut skipped = Vec::new();
+    if opts.since.is_some() || opts.before.is_some() {
+        let within: HashSet<u32> = session
+            .uids_within(mailbox, &uids, opts.since, opts.before)
+            .await?
+            .into_iter()
+            .collect();
+        uids.retain(|uid| {
+            let keep = within.contains(uid);
+            if !keep {
+                skipped.push(SkippedMsg {
+                    uid: *uid,
+                    reason: SKIP_DATE.to_string(),
+                    size: None,
+                });
+            }
+            keep
+        });
+    }
+    if let Some(max) = account.max_message_size {
+        let too_big: HashMap<u32, u32> = session
+            .sizes(mailbox, &uids)
+            .await?
+            .into_iter()
+            .filter(|(_, size)| *size > max)
+            .collect();
+        uids.retain(|uid| {
+            let size = too_big.get(uid).copied();
+            if size.is_some() {
+                skipped.push(SkippedMsg {
+                    uid: *uid,
+                    reason: SKIP_SIZE.to_string(),
+                    size,
+                });
+            }
+            size.is_none()
+        });
+    }
+    Ok(Selection {
+        uids,
+        skipped,
+        newest,
+    })
+}
+
+/// The account's mailboxes selected for fetching, sorted.
+async fn list_mailboxes(
+    session: &mut Session,
+    account: &ImapAccount,
+    opts: &Opts,
+) -> anyhow::Result<Vec<String>> {
+    let mut mailboxes = session
+        .list_mailboxes()
+        .await?
+        .filter(|mailbox| {
+            let selected = !account.ignore_mailboxes.contains(mailbox)
+                && selected(mailbox, opts);
+            async move { selected }
+        })
+        .collect::<Vec<String>>()
+        .await;
+    mailboxes.sort();
+    Ok(mailboxes)
+}
+
+/// Whether the mailbox is among those selected by `--mailbox` patterns.
+fn selected(mailbox: &str, opts: &Opts) -> bool {
+    opts.mailboxes.is_empty()
+        || opts
+            .mailboxes
+            .iter()
+            .any(|pattern| glob_matches(pattern, mailbox))
+}
+
+/// "*" matches any characters and "?" any one character.
+fn glob_matches(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    fn go(p: &[char], n: &[char]) -> bool {
+        match p.split_first() {
+            None => n.is_empty(),
+            Some(('*', rest)) => (0..=n.len()).any(|i| go(rest, &n[i..])),
+            Some(('?', rest)) => !n.is_empty() && go(rest, &n[1..]),
+            Some((c, rest)) => n.first() == Some(c) && go(rest, &n[1..]),
+        }
+    }
+    go(&pattern, &name)
+}
+
+fn millis(since: Instant) -> i64 {
+    i64::try_from(since.elapsed().as_millis()).unwrap_or(i64::MAX)
+}
+
 const MARK_OK: &str = "V";
 const MARK_ERR: &str = "X";
 
@@ -242,8 +1330,8 @@ fn prog_fin_err(account_name: &str, pb: &ProgressBar, err_msg: &str) {
     ));
 }
 
-fn prog_bar_spin(size: u64) -> anyhow::Result<ProgressBar> {
-    let bar = ProgressBar::new(size);
+fn prog_bar_spin(size: u64, label: &str) -> anyhow::Result<ProgressBar> {
+    let bar = progress::bar(size, label);
     let sty = prog_sty_spin()?;
     bar.set_style(sty);
     Ok(bar)
@@ -268,7 +1356,7 @@ fn prog_sty_spin_fin() -> anyhow::Result<ProgressStyle> {
     Ok(sty)
 }
 
-fn truncate<S: AsRef<str>>(s0: S, max: usize) -> String {
+pub(crate) fn truncate<S: AsRef<str>>(s0: S, max: usize) -> String {
     let mut s0 = s0.as_ref().chars().enumerate();
     let mut s1 = String::new();
     loop {
@@ -290,6 +1378,84 @@ fn truncate<S: AsRef<str>>(s0: S, max: usize) -> String {
 mod tests {
     use super::*;
 
+    #[test]
+    fn t_outcome() {
+        let ok = || MailboxRun::default();
+        let err = || MailboxRun {
+            error: Some("oops".to_string()),
+            ..MailboxRun::default()
+        };
+        assert_eq!(Outcome::Success, Fetched(vec![]).outcome());
+        assert_eq!(Outcome::Success, Fetched(vec![ok(), ok()]).outcome());
+        assert_eq!(
+            Outcome::PartialFailure,
+            Fetched(vec![ok(), err()]).outcome()
+        );
+        assert_eq!(
+            Outcome::TotalFailure,
+            Fetched(vec![err(), err()]).outcome()
+        );
+        // With --fail-fast.
+        let aborted = || MailboxRun {
+            aborted: true,
+            ..err()
+        };
+        assert_eq!(
+            Outcome::PartialFailure,
+            Fetched(vec![ok(), err(), aborted()]).outcome()
+        );
+        assert_eq!(
+            Outcome::TotalFailure,
+            Fetched(vec![err(), aborted(), aborted()]).outcome()
+        );
+    }
+
+    #[test]
+    fn t_env() {
+        let run = |account: &str, messages, error: Option<&str>| MailboxRun {
+            account: account.to_string(),
+            messages,
+            bytes: i64::from(messages) * 10,
+            error: error.map(ToString::to_string),
+            ..MailboxRun::default()
+        };
+        let fetched = Fetched(vec![
+            run("a.b", 1, None),
+            run("a.b", 2, Some("oops")),
+            run("c", 3, None),
+        ]);
+        let env: Vec<String> = fetched
+            .env()
+            .into_iter()
+            .map(|(k, v)| format!("{k}={v}"))
+            .collect();
+        assert_eq!(
+            vec![
+                "MA_MESSAGES=6",
+                "MA_BYTES=60",
+                "MA_ERRORS=1",
+                "MA_ACCOUNT_A_B_MESSAGES=3",
+                "MA_ACCOUNT_A_B_BYTES=30",
+                "MA_ACCOUNT_A_B_ERRORS=1",
+                "MA_ACCOUNT_C_MESSAGES=3",
+                "MA_ACCOUNT_C_BYTES=30",
+                "MA_ACCOUNT_C_ERRORS=0",
+            ],
+            env
+        );
+    }
+
+    #[test]
+    fn t_glob_matches() {
+        assert!(glob_matches("INBOX", "INBOX"));
+        assert!(!glob_matches("INBOX", "INBOX/a"));
+        assert!(glob_matches("INBOX*", "INBOX/a"));
+        assert!(glob_matches("*/Sent", "Archive/Sent"));
+        assert!(!glob_matches("*/Sent", "Sent"));
+        assert!(glob_matches("20??", "2024"));
+        assert!(!glob_matches("20??", "202"));
+    }
+
     #[test]
     fn t_truncate() {
         assert_eq!("...", truncate("abc", 0));
@@ -299,4 +1465,131 @@ mod tests {
         assert_eq!("abc", truncate("abc", 4));
         assert_eq!("abc", truncate("abc", 5));
     }
+
+    #[tokio::test]
+    async fn t_fetch_mailbox() {
+        use crate::{cfg, imap::stand_in};
+
+        let msgs: Vec<stand_in::Msg> = (1..=5)
+            .map(|uid| {
+                let body = "x".repeat(if uid == 3 { 100 } else { 1 });
+                let raw = format!("Subject: {uid}\r\n\r\n{body}\r\n");
+                stand_in::Msg::new(uid, raw.as_bytes())
+            })
+            .collect();
+        let server = stand_in::StandIn::start(
+            [("INBOX".to_string(), msgs.clone())].into(),
+        )
+        .await;
+        let account = ImapAccount {
+            chunk_size: Some(2),
+            part_size: Some(32),
+            ..server.account()
+        };
+        let dir = tempfile::tempdir().unwrap();
+        let db = data::Storage::connect(&cfg::Db {
+            file: dir.path().join("db"),
+        })
+        .await
+        .unwrap();
+        let opts = Opts {
+            all: false,
+            fail_fast: false,
+            mailboxes: Vec::new(),
+            since: None,
+            before: None,
+            msg_hooks: Vec::new(),
+        };
+        let pb = ProgressBar::hidden();
+        let ctx = Account {
+            name: "a",
+            cfg: &account,
+            db: &db,
+            opts: &opts,
+            pb: &pb,
+            total_mailboxes: 1,
+        };
+        let mut session = server.connect().await;
+        server.state().throttle = 3;
+        let mut run = MailboxRun::default();
+        fetch_mailbox(&mut session, &ctx, "INBOX", "INBOX", &mut run)
+            .await
+            .unwrap();
+
+        assert_eq!(None, run.error);
+        assert_eq!(5, run.messages);
+        let bytes: usize = msgs.iter().map(|msg| msg.raw.len()).sum();
+        assert_eq!(i64::try_from(bytes).unwrap(), run.bytes);
+        assert_eq!(Some(5), db.fetch_last_seen("a", "INBOX").await.unwrap());
+        assert!(db.fetch_partial("a", "INBOX", 3).await.unwrap().is_empty());
+        // Recorded for restoring, the large message's too.
+        for msg in &msgs {
+            let hash = crate::hash::sha256(&msg.raw);
+            let attrs = db
+                .fetch_attrs(&hash, Some(("a", "INBOX")))
+                .await
+                .unwrap()
+                .unwrap();
+            assert_eq!(Some(msg.internal_date.clone()), attrs.internal_date);
+        }
+        let state = server.state();
+        assert!(state.mailboxes["INBOX"].iter().all(|msg| !msg.seen()));
+        // The large message, in parts.
+        let parts = state
+            .commands
+            .iter()
+            .filter(|command| command.contains("BODY.PEEK[]<"))
+            .count();
+        assert_eq!(4, parts);
+    }
+
+    #[tokio::test]
+    async fn t_fetch_maildrop() {
+        use crate::{cfg, pop3::stand_in};
+
+        let raw = |i: u32| format!("Subject: {i}\r\n\r\nHi\r\n");
+        let server = stand_in::StandIn::start(vec![
+            stand_in::Msg::new("u1", raw(1).as_bytes()),
+            stand_in::Msg::new("u2", raw(2).as_bytes()),
+        ])
+        .await;
+        let dir = tempfile::tempdir().unwrap();
+        let db = data::Storage::connect(&cfg::Db {
+            file: dir.path().join("db"),
+        })
+        .await
+        .unwrap();
+        let opts = Opts {
+            all: false,
+            fail_fast: false,
+            mailboxes: Vec::new(),
+            since: None,
+            before: None,
+            msg_hooks: Vec::new(),
+        };
+        let pb = ProgressBar::hidden();
+        let fetch = || async {
+            let mut session = server.connect().await;
+            let mut run = MailboxRun::default();
+            fetch_maildrop(&mut session, "p", &db, &opts, &pb, &mut run)
+                .await
+                .unwrap();
+            session.quit().await.unwrap();
+            assert_eq!(None, run.error);
+            run.messages
+        };
+        assert_eq!(2, fetch().await);
+        server
+            .state()
+            .msgs
+            .push(stand_in::Msg::new("u3", raw(3).as_bytes()));
+        // Only the new one.
+        assert_eq!(1, fetch().await);
+        assert_eq!(0, fetch().await);
+        let retrs = server.commands().iter().filter(|c| *c == "RETR").count();
+        assert_eq!(3, retrs);
+        let hash = crate::hash::sha256(raw(3));
+        assert!(db.fetch_msg(&hash).await.unwrap().is_some());
+        assert_eq!(3, db.fetch_pop3_uids("p").await.unwrap().len());
+    }
 }
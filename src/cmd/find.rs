@@ -0,0 +1,39 @@
+use std::io::{self, Write};
+
+use crate::{
+    cfg::Cfg,
+    data::{self, Filter},
+    output::Output,
+    query::Query,
+};
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct Cmd {
+    /// Query, like: from:alice date:2021 has:attachment -mailbox:Spam
+    /// (see the query module docs for the full syntax).
+    #[clap(required = true)]
+    query: Vec<String>,
+}
+
+/// Hashes of the matching messages, in date order.
+#[derive(serde::Serialize, Debug)]
+#[serde(transparent)]
+pub struct Found(pub Vec<String>);
+
+impl Output for Found {
+    fn write_text(&self, w: &mut dyn Write) -> io::Result<()> {
+        for hash in &self.0 {
+            writeln!(w, "{hash}")?;
+        }
+        Ok(())
+    }
+}
+
+impl Cmd {
+    pub async fn run(&self, cfg: &Cfg) -> anyhow::Result<Found> {
+        let query = Query::parse(&self.query.join(" "))?;
+        let db = data::Storage::connect(&cfg.db).await?;
+        let hashes = db.find(&Filter::new(&query)).await?;
+        Ok(Found(hashes))
+    }
+}
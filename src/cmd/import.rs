@@ -1,16 +1,69 @@
-use std::path::PathBuf;
+use std::{
+    io::{self, Write},
+    path::PathBuf,
+};
 
-use crate::{cfg::Cfg, data};
+use crate::{cfg::Cfg, data, hook, output::Output};
 
 #[derive(clap::Args, Debug, Clone)]
 pub struct Cmd {
     obj_dir: PathBuf,
+
+    /// Only report how many messages are new, without importing any.
+    #[clap(long)]
+    dry_run: bool,
+}
+
+#[derive(serde::Serialize, Debug)]
+#[serde(untagged)]
+pub enum Report {
+    Imported(Imported),
+    Planned(Planned),
+}
+
+#[derive(serde::Serialize, Debug)]
+pub struct Imported {
+    /// Read from the file tree, including any already in the database.
+    pub messages: usize,
+}
+
+/// What a dry run found.
+#[derive(serde::Serialize, Debug)]
+pub struct Planned {
+    /// Not yet in the database.
+    pub new: usize,
+
+    /// Already in the database.
+    pub existing: usize,
+}
+
+impl Output for Report {
+    fn write_text(&self, w: &mut dyn Write) -> io::Result<()> {
+        match self {
+            Self::Imported(Imported { messages }) => {
+                writeln!(w, "Imported {messages} messages.")
+            }
+            Self::Planned(Planned { new, existing }) => writeln!(
+                w,
+                "Would import {new} new messages. {existing} are already \
+                stored."
+            ),
+        }
+    }
 }
 
 impl Cmd {
-    pub async fn run(&self, cfg: &Cfg) -> anyhow::Result<()> {
+    pub async fn run(&self, cfg: &Cfg) -> anyhow::Result<Report> {
+        if self.dry_run {
+            let db = data::Storage::connect_read_only(&cfg.db).await?;
+            let (new, existing) = db.import_plan(&self.obj_dir).await?;
+            return Ok(Report::Planned(Planned { new, existing }));
+        }
         let db = data::Storage::connect(&cfg.db).await?;
-        db.import(&self.obj_dir).await?;
-        Ok(())
+        let messages = db.import(&self.obj_dir).await?;
+        let imported = Imported { messages };
+        let env = [("MA_MESSAGES".to_string(), messages.to_string())];
+        hook::run_all(&cfg.hooks.import, "import", &imported, &env).await;
+        Ok(Report::Imported(imported))
     }
 }
@@ -0,0 +1,322 @@
+use std::{
+    collections::{BTreeMap, HashSet},
+    io::{self, Write},
+};
+
+use crate::{
+    cfg::Cfg,
+    cmd::restore::{self, restore, Restored},
+    data::{self, Located, MsgAttrs},
+    imap::{throttle::Limits, Session},
+    output::{Outcome, Output},
+};
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct Cmd {
+    /// Account, as fetched from, to migrate from. Needn't be in the cfg
+    /// anymore, as its messages are taken from the archive, if given its
+    /// `--from-delimiter`.
+    #[clap(long, value_name = "NAME")]
+    from: String,
+
+    /// Account, as named in the cfg, to migrate to.
+    #[clap(long, value_name = "NAME")]
+    to: String,
+
+    /// Hierarchy delimiter of the old account's mailbox names, as in
+    /// "Work/Old", to translate them to the new account's. Asked of the old
+    /// account, if not given, which must then still be in the cfg.
+    #[clap(long, value_name = "CHAR")]
+    from_delimiter: Option<String>,
+}
+
+#[derive(serde::Serialize, Debug)]
+#[serde(transparent)]
+pub struct Migrated(pub Vec<MigratedMailbox>);
+
+#[derive(serde::Serialize, Debug, PartialEq)]
+pub struct MigratedMailbox {
+    /// As named on the new account.
+    pub mailbox: String,
+
+    #[serde(flatten)]
+    pub restored: Restored,
+
+    /// Distinct messages fetched from the old mailbox.
+    pub archived: usize,
+
+    /// Messages in the new mailbox, once restored to.
+    pub on_server: usize,
+
+    /// Message-IDs of the archived messages the new mailbox lacks.
+    pub missing: Vec<String>,
+}
+
+impl MigratedMailbox {
+    #[must_use]
+    pub fn verified(&self) -> bool {
+        self.missing.is_empty() && self.on_server >= self.archived
+    }
+}
+
+impl Output for Migrated {
+    fn write_text(&self, w: &mut dyn Write) -> io::Result<()> {
+        for mailbox in &self.0 {
+            let MigratedMailbox {
+                mailbox: name,
+                restored:
+                    Restored {
+                        messages,
+                        present,
+                        earlier,
+                    },
+                archived,
+                on_server,
+                missing,
+            } = mailbox;
+            let verified = if mailbox.verified() {
+                "Verified"
+            } else {
+                "NOT verified"
+            };
+            writeln!(
+                w,
+                "{name:?}: restored {messages}, skipped {present} already \
+                there and {earlier} restored earlier. {verified}: \
+                {on_server} on the server, of {archived} archived, \
+                {} Message-IDs missing.",
+                missing.len()
+            )?;
+            for id in missing {
+                writeln!(w, "    missing: <{id}>")?;
+            }
+        }
+        Ok(())
+    }
+
+    fn outcome(&self) -> Outcome {
+        let unverified =
+            self.0.iter().filter(|mailbox| !mailbox.verified()).count();
+        match unverified {
+            0 => Outcome::Success,
+            n if n == self.0.len() => Outcome::TotalFailure,
+            _ => Outcome::PartialFailure,
+        }
+    }
+}
+
+impl Cmd {
+    pub async fn run(&self, cfg: &Cfg) -> anyhow::Result<Migrated> {
+        let account = restore::account(cfg, &self.to)?;
+        let from_delimiter = match &self.from_delimiter {
+            Some(delimiter) => Some(delimiter.clone()),
+            None => {
+                let from =
+                    restore::account(cfg, &self.from).map_err(|e| {
+                        e.context(
+                            "Can't ask the old account for its hierarchy \
+                            delimiter: give it with --from-delimiter.",
+                        )
+                    })?;
+                let mut session =
+                    Session::new(from, &Limits::new(from)?).await?;
+                let delimiter = session.delimiter().await?;
+                session.close().await?;
+                delimiter
+            }
+        };
+        let db = data::Storage::connect(&cfg.db).await?;
+        let mut session =
+            Session::new(account, &Limits::new(account)?).await?;
+        let from = (self.from.as_str(), from_delimiter.as_deref());
+        migrate(&mut session, &db, from, &self.to).await
+    }
+}
+
+/// Restore each mailbox the old account's messages were fetched from to the
+/// same-named mailbox of the new one, then, once all are, check that every
+/// mailbox has at least as many messages as were archived from it and all of
+/// their Message-IDs. The old account is given with its hierarchy delimiter,
+/// which, in the names, is replaced by the new account's.
+async fn migrate(
+    session: &mut Session,
+    db: &data::Storage,
+    (from, from_delimiter): (&str, Option<&str>),
+    to: &str,
+) -> anyhow::Result<Migrated> {
+    let to_delimiter = session.delimiter().await?;
+    let mut mailboxes: BTreeMap<String, Vec<Located>> = BTreeMap::new();
+    let mut seen = HashSet::new();
+    for msg in db.fetch_located(from).await? {
+        // Duplicates, under different UIDs, are restored once.
+        if seen.insert((msg.mailbox.clone(), msg.msg_hash.clone())) {
+            let mailbox = translate(
+                &msg.mailbox,
+                from_delimiter,
+                to_delimiter.as_deref(),
+            );
+            mailboxes.entry(mailbox).or_default().push(msg);
+        }
+    }
+    if mailboxes.is_empty() {
+        anyhow::bail!("No messages were fetched from account {from:?}.");
+    }
+    let mut restored = Vec::new();
+    for (mailbox, msgs) in &mailboxes {
+        tracing::info!(?mailbox, messages = msgs.len(), "Restoring mailbox.");
+        let msgs: Vec<(String, MsgAttrs)> = msgs
+            .iter()
+            .map(|msg| (msg.msg_hash.clone(), msg.attrs.clone()))
+            .collect();
+        restored.push(restore(session, db, to, mailbox, &msgs).await?);
+    }
+    let mut migrated = Vec::new();
+    for ((mailbox, msgs), restored) in mailboxes.into_iter().zip(restored) {
+        let ids = session.message_ids(&mailbox).await?;
+        let on_server = ids.len();
+        let ids: HashSet<String> = ids.into_iter().flatten().collect();
+        let missing: Vec<String> = msgs
+            .iter()
+            .filter_map(|msg| msg.message_id.clone())
+            .filter(|id| !ids.contains(id))
+            .collect();
+        migrated.push(MigratedMailbox {
+            mailbox,
+            restored,
+            archived: msgs.len(),
+            on_server,
+            missing,
+        });
+    }
+    Ok(Migrated(migrated))
+}
+
+/// The mailbox name with one hierarchy delimiter replaced by the other. Left
+/// as is when either names are flat, having no levels to separate.
+fn translate(mailbox: &str, from: Option<&str>, to: Option<&str>) -> String {
+    match (from, to) {
+        (Some(from), Some(to)) if from != to => mailbox.replace(from, to),
+        _ => mailbox.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        cfg,
+        imap::stand_in::{Msg, StandIn},
+    };
+
+    use super::*;
+
+    #[tokio::test]
+    async fn t_migrate() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = data::Storage::connect(&cfg::Db {
+            file: dir.path().join("db"),
+        })
+        .await
+        .unwrap();
+        let raw = |i: u32| format!("Message-ID: <{i}@x>\r\n\r\nHi {i}\r\n");
+        let fetched = [("INBOX", 1, 1), ("INBOX", 2, 2), ("Work/Old", 5, 3)];
+        for (mailbox, uid, i) in fetched {
+            let hash = db.store_msg(raw(i).as_bytes()).await.unwrap();
+            db.store_location(&hash, "old", mailbox, uid).await.unwrap();
+            let attrs = MsgAttrs {
+                flags: vec!["\\Flagged".to_string()],
+                internal_date: Some(format!("0{i}-Feb-2020 10:00:00 +0000")),
+            };
+            db.store_attrs("old", mailbox, uid, &attrs).await.unwrap();
+        }
+
+        // The new INBOX already has the 1st and one of its own.
+        let server = StandIn::start(BTreeMap::from([(
+            "INBOX".to_string(),
+            vec![
+                Msg::new(1, raw(1).as_bytes()),
+                Msg::new(2, raw(9).as_bytes()),
+            ],
+        )]))
+        .await;
+        let mut session = server.connect().await;
+        let migrated = migrate(&mut session, &db, ("old", Some("/")), "new")
+            .await
+            .unwrap();
+        assert_eq!(Outcome::Success, migrated.outcome());
+        let summary: Vec<(&str, usize, usize, usize, usize)> = migrated
+            .0
+            .iter()
+            .map(|m| {
+                let r = &m.restored;
+                (
+                    m.mailbox.as_str(),
+                    r.messages,
+                    r.present,
+                    m.archived,
+                    m.on_server,
+                )
+            })
+            .collect();
+        assert_eq!(
+            vec![("INBOX", 1, 1, 2, 3), ("Work/Old", 1, 0, 1, 1)],
+            summary
+        );
+        {
+            let state = server.state();
+            let work = &state.mailboxes["Work/Old"];
+            assert_eq!(vec!["\\Flagged".to_string()], work[0].flags);
+            assert_eq!("03-Feb-2020 10:00:00 +0000", work[0].internal_date);
+        }
+
+        // Rerun, after the new server lost a message.
+        server
+            .state()
+            .mailboxes
+            .get_mut("Work/Old")
+            .unwrap()
+            .clear();
+        let migrated = migrate(&mut session, &db, ("old", Some("/")), "new")
+            .await
+            .unwrap();
+        assert_eq!(Outcome::PartialFailure, migrated.outcome());
+        assert_eq!(1, migrated.0[1].restored.earlier);
+        assert_eq!(vec!["3@x".to_string()], migrated.0[1].missing);
+
+        assert!(migrate(&mut session, &db, ("nope", Some("/")), "new")
+            .await
+            .is_err());
+    }
+    #[tokio::test]
+    async fn t_migrate_delimiters() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = data::Storage::connect(&cfg::Db {
+            file: dir.path().join("db"),
+        })
+        .await
+        .unwrap();
+        let raw = "Message-ID: <1@x>\r\n\r\nHi\r\n";
+        let hash = db.store_msg(raw.as_bytes()).await.unwrap();
+        db.store_location(&hash, "old", "Work/Old", 1)
+            .await
+            .unwrap();
+        let server = StandIn::start(BTreeMap::new()).await;
+        server.state().delimiter = Some('.');
+        let mut session = server.connect().await;
+        let migrated = migrate(&mut session, &db, ("old", Some("/")), "new")
+            .await
+            .unwrap();
+        assert_eq!(Outcome::Success, migrated.outcome());
+        assert_eq!("Work.Old", migrated.0[0].mailbox);
+        assert_eq!(
+            vec!["Work.Old"],
+            server.state().mailboxes.keys().collect::<Vec<_>>()
+        );
+
+        // Flat names, with nothing to translate.
+        server.state().delimiter = None;
+        let migrated = migrate(&mut session, &db, ("old", Some("/")), "new")
+            .await
+            .unwrap();
+        assert_eq!("Work/Old", migrated.0[0].mailbox);
+    }
+}
@@ -1,4 +1,16 @@
 pub mod analyze;
+pub mod attachments;
+pub mod cat;
+pub mod dedup;
 pub mod export;
 pub mod fetch;
+pub mod find;
 pub mod import;
+pub mod migrate;
+pub mod reindex;
+pub mod restore;
+pub mod serve_http;
+pub mod serve_imap;
+pub mod show;
+pub mod status;
+pub mod thread;
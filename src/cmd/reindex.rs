@@ -0,0 +1,25 @@
+use std::io::{self, Write};
+
+use crate::{cfg::Cfg, data, output::Output};
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct Cmd {}
+
+#[derive(serde::Serialize, Debug)]
+pub struct Reindexed {
+    pub messages: usize,
+}
+
+impl Output for Reindexed {
+    fn write_text(&self, w: &mut dyn Write) -> io::Result<()> {
+        writeln!(w, "Reindexed {} messages.", self.messages)
+    }
+}
+
+impl Cmd {
+    pub async fn run(&self, cfg: &Cfg) -> anyhow::Result<Reindexed> {
+        let db = data::Storage::connect(&cfg.db).await?;
+        let messages = db.reindex().await?;
+        Ok(Reindexed { messages })
+    }
+}
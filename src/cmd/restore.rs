@@ -0,0 +1,245 @@
+use std::io::{self, Write};
+
+use crate::{
+    cfg::{Cfg, ImapAccount},
+    data::{self, Filter, MsgAttrs},
+    imap::{throttle::Limits, Session},
+    output::Output,
+    progress,
+    query::Query,
+};
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct Cmd {
+    /// Account, as named in the cfg, to upload to.
+    #[clap(long, value_name = "NAME")]
+    account: String,
+
+    /// Mailbox to upload to. Created if it doesn't exist.
+    #[clap(long, value_name = "MAILBOX")]
+    to_mailbox: String,
+
+    /// Restore only messages matching this query (see `ma find`). All, if
+    /// none.
+    query: Vec<String>,
+}
+
+#[derive(serde::Serialize, Debug, Default, PartialEq)]
+pub struct Restored {
+    /// Uploaded by this run.
+    pub messages: usize,
+
+    /// Not uploaded, as the mailbox already has a message with the same
+    /// Message-ID.
+    pub present: usize,
+
+    /// Not uploaded, nor looked for, as an earlier run already restored them.
+    pub earlier: usize,
+}
+
+impl Output for Restored {
+    fn write_text(&self, w: &mut dyn Write) -> io::Result<()> {
+        let Self {
+            messages,
+            present,
+            earlier,
+        } = self;
+        writeln!(
+            w,
+            "Restored {messages} messages. Skipped {present} already in the \
+            mailbox and {earlier} restored earlier."
+        )
+    }
+}
+
+impl Cmd {
+    pub async fn run(&self, cfg: &Cfg) -> anyhow::Result<Restored> {
+        let account = account(cfg, &self.account)?;
+        let filter = if self.query.is_empty() {
+            Filter::default()
+        } else {
+            Filter::new(&Query::parse(&self.query.join(" "))?)
+        };
+        let db = data::Storage::connect(&cfg.db).await?;
+        let mut msgs = Vec::new();
+        for hash in db.find(&filter).await? {
+            let attrs = db
+                .fetch_attrs(&hash, Some((&self.account, &self.to_mailbox)))
+                .await?
+                .unwrap_or_default();
+            msgs.push((hash, attrs));
+        }
+        let mut session =
+            Session::new(account, &Limits::new(account)?).await?;
+        restore(&mut session, &db, &self.account, &self.to_mailbox, &msgs)
+            .await
+    }
+}
+
+/// The account of the name, as configured.
+pub(crate) fn account<'a>(
+    cfg: &'a Cfg,
+    name: &str,
+) -> anyhow::Result<&'a ImapAccount> {
+    cfg.imap.accounts.get(name).ok_or_else(|| {
+        let mut known: Vec<&String> = cfg.imap.accounts.keys().collect();
+        known.sort();
+        anyhow::anyhow!("Unknown account: {name:?}. Known: {known:?}")
+    })
+}
+
+/// Upload the messages (by hash, with the attributes to give them), in
+/// order, recording each as restored once it's in the mailbox, for a rerun
+/// to pick up after the last one.
+pub(crate) async fn restore(
+    session: &mut Session,
+    db: &data::Storage,
+    account: &str,
+    mailbox: &str,
+    msgs: &[(String, MsgAttrs)],
+) -> anyhow::Result<Restored> {
+    let done = db.fetch_restored(account, mailbox).await?;
+    let todo: Vec<&(String, MsgAttrs)> = msgs
+        .iter()
+        .filter(|(hash, _)| !done.contains(hash))
+        .collect();
+    let mut restored = Restored {
+        earlier: msgs.len() - todo.len(),
+        ..Restored::default()
+    };
+    if todo.is_empty() {
+        return Ok(restored);
+    }
+    if let Err(error) = session.examine(mailbox).await {
+        tracing::info!(?mailbox, ?error, "Creating mailbox.");
+        session.create(mailbox).await?;
+        session.examine(mailbox).await?;
+    }
+    let pb = progress::bar(u64::try_from(todo.len())?, "restore");
+    for (hash, attrs) in todo {
+        let msg = db
+            .fetch_msg(hash)
+            .await?
+            .ok_or_else(|| anyhow::anyhow!("Message not found: {hash}"))?;
+        let message_id = mail_parser::MessageParser::default()
+            .parse_headers(&msg.raw)
+            .and_then(|parsed| parsed.message_id().map(str::to_string));
+        let present = match message_id {
+            None => false,
+            Some(id) => session.has_message_id(mailbox, &id).await?,
+        };
+        if present {
+            tracing::debug!(?hash, "Already in the mailbox.");
+            restored.present += 1;
+        } else {
+            session
+                .append(
+                    mailbox,
+                    &msg.raw,
+                    &attrs.flags,
+                    attrs.internal_date.as_deref(),
+                )
+                .await?;
+            restored.messages += 1;
+        }
+        db.store_restored(hash, account, mailbox).await?;
+        pb.inc(1);
+    }
+    pb.finish();
+    Ok(restored)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use crate::{
+        cfg,
+        imap::stand_in::{Msg, StandIn},
+    };
+
+    use super::*;
+
+    #[tokio::test]
+    async fn t_restore() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = data::Storage::connect(&cfg::Db {
+            file: dir.path().join("db"),
+        })
+        .await
+        .unwrap();
+        let raw = |i: u32| format!("Message-ID: <{i}@x>\r\n\r\nHi {i}\r\n");
+        let attrs = MsgAttrs {
+            flags: vec!["\\Seen".to_string(), "\\Recent".to_string()],
+            internal_date: Some("17-Jul-1996 02:44:25 -0700".to_string()),
+        };
+        let mut msgs = Vec::new();
+        for i in 1..=3 {
+            let hash = db.store_msg(raw(i).as_bytes()).await.unwrap();
+            let attrs = if i == 1 {
+                attrs.clone()
+            } else {
+                MsgAttrs::default()
+            };
+            msgs.push((hash, attrs));
+        }
+
+        // The 2nd is already there.
+        let server = StandIn::start(BTreeMap::from([(
+            "Restored".to_string(),
+            vec![Msg::new(1, raw(2).as_bytes())],
+        )]))
+        .await;
+        let mut session = server.connect().await;
+        let restored =
+            restore(&mut session, &db, "new", "Restored", &msgs[..1])
+                .await
+                .unwrap();
+        assert_eq!(
+            Restored {
+                messages: 1,
+                present: 0,
+                earlier: 0
+            },
+            restored
+        );
+        // As if interrupted after the 1st, then rerun for all.
+        let restored = restore(&mut session, &db, "new", "Restored", &msgs)
+            .await
+            .unwrap();
+        assert_eq!(
+            Restored {
+                messages: 1,
+                present: 1,
+                earlier: 1
+            },
+            restored
+        );
+        {
+            let state = server.state();
+            let msgs = &state.mailboxes["Restored"];
+            let raws: Vec<Vec<u8>> =
+                msgs.iter().map(|msg| msg.raw.clone()).collect();
+            assert_eq!(
+                vec![raw(2), raw(1), raw(3)]
+                    .into_iter()
+                    .map(String::into_bytes)
+                    .collect::<Vec<_>>(),
+                raws
+            );
+            assert_eq!(vec!["\\Seen".to_string()], msgs[1].flags);
+            assert_eq!(
+                attrs.internal_date.as_ref(),
+                Some(&msgs[1].internal_date)
+            );
+            assert!(msgs[2].flags.is_empty());
+        }
+
+        // To a new mailbox.
+        let restored = restore(&mut session, &db, "new", "New", &msgs)
+            .await
+            .unwrap();
+        assert_eq!(3, restored.messages);
+        assert_eq!(3, server.state().mailboxes["New"].len());
+    }
+}
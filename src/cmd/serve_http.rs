@@ -0,0 +1,21 @@
+use std::{net::IpAddr, sync::Arc};
+
+use crate::{cfg::Cfg, data, http_server};
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct Cmd {
+    /// Address to listen on. There is no TLS and no login, so think twice
+    /// before making it reachable from other hosts.
+    #[clap(short, long, default_value = "127.0.0.1")]
+    addr: IpAddr,
+
+    #[clap(short, long, default_value_t = 8080)]
+    port: u16,
+}
+
+impl Cmd {
+    pub async fn run(&self, cfg: &Cfg) -> anyhow::Result<()> {
+        let db = data::Storage::connect(&cfg.db).await?;
+        http_server::serve(Arc::new(db), (self.addr, self.port).into()).await
+    }
+}
@@ -0,0 +1,123 @@
+use std::io::{self, Write};
+
+use mail_parser::{Addr, Address, Group};
+
+use crate::{cfg::Cfg, data, output::Output};
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct Cmd {
+    /// Hash, unique hash prefix or Message-ID.
+    msg: String,
+}
+
+#[derive(serde::Serialize, Debug)]
+pub struct Shown {
+    pub hash: String,
+    /// RFC 822 formatted.
+    pub date: Option<String>,
+    pub from: Option<String>,
+    pub to: Option<String>,
+    pub cc: Option<String>,
+    pub reply_to: Option<String>,
+    pub subject: Option<String>,
+    /// Without the angle brackets.
+    pub message_id: Option<String>,
+    pub text: Option<String>,
+    pub attachments: Vec<data::Attachment>,
+}
+
+impl Output for Shown {
+    fn write_text(&self, w: &mut dyn Write) -> io::Result<()> {
+        writeln!(w, "Hash: {}", self.hash)?;
+        if let Some(date) = &self.date {
+            writeln!(w, "Date: {date}")?;
+        }
+        for (name, address) in [
+            ("From", &self.from),
+            ("To", &self.to),
+            ("Cc", &self.cc),
+            ("Reply-To", &self.reply_to),
+        ] {
+            if let Some(address) = address {
+                writeln!(w, "{name}: {address}")?;
+            }
+        }
+        if let Some(subject) = &self.subject {
+            writeln!(w, "Subject: {subject}")?;
+        }
+        if let Some(message_id) = &self.message_id {
+            writeln!(w, "Message-ID: <{message_id}>")?;
+        }
+        writeln!(w)?;
+        if let Some(text) = &self.text {
+            writeln!(w, "{}", text.trim_end())?;
+        }
+        if !self.attachments.is_empty() {
+            writeln!(w)?;
+            writeln!(w, "Attachments:")?;
+            for data::Attachment {
+                hash,
+                mime_type,
+                size,
+                name,
+                msgs: _,
+            } in &self.attachments
+            {
+                let name = name.as_deref().unwrap_or_default();
+                writeln!(w, "\t{hash}\t{mime_type}\t{size}\t{name:?}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Cmd {
+    pub async fn run(&self, cfg: &Cfg) -> anyhow::Result<Shown> {
+        let db = data::Storage::connect(&cfg.db).await?;
+        let data::Msg { hash, raw } =
+            super::cat::fetch(&db, &self.msg).await?;
+        let parsed = mail_parser::MessageParser::default()
+            .parse(&raw[..])
+            .unwrap_or_default();
+        let parts = db.fetch_parts(&hash).await?;
+        let text = parts
+            .iter()
+            .find(|p| p.content_type == "text/plain" && p.text.is_some())
+            .and_then(|p| p.text.clone())
+            .or_else(|| parts.iter().find_map(|p| p.text_plain.clone()));
+        let attachments = db.fetch_msg_attachments(&hash).await?;
+        Ok(Shown {
+            hash,
+            date: parsed.date().map(|date| date.to_rfc822()),
+            from: parsed.from().map(format_address),
+            to: parsed.to().map(format_address),
+            cc: parsed.cc().map(format_address),
+            reply_to: parsed.reply_to().map(format_address),
+            subject: parsed.subject().map(str::to_string),
+            message_id: parsed.message_id().map(str::to_string),
+            text,
+            attachments,
+        })
+    }
+}
+
+pub(crate) fn format_address(address: &Address) -> String {
+    let addrs: Vec<&Addr> = match address {
+        Address::List(addrs) => addrs.iter().collect(),
+        Address::Group(groups) => groups
+            .iter()
+            .flat_map(|Group { name: _, addresses }| addresses)
+            .collect(),
+    };
+    addrs
+        .into_iter()
+        .map(|Addr { name, address }| {
+            let address = address.as_deref().unwrap_or_default();
+            match name {
+                Some(name) => format!("{name} <{address}>"),
+                None => address.to_string(),
+            }
+        })
+        .collect::<Vec<String>>()
+        .join(", ")
+}
@@ -0,0 +1,96 @@
+use std::io::{self, Write};
+
+use crate::{
+    cfg::Cfg,
+    data::{self, FailingMailbox, RunSummary, SkippedSummary},
+    output::Output,
+};
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct Cmd {
+    /// How many of the latest fetch runs to summarize.
+    #[clap(short, long, default_value_t = 5)]
+    runs: u32,
+}
+
+#[derive(serde::Serialize, Debug)]
+pub struct Status {
+    /// Newest first.
+    pub runs: Vec<RunSummary>,
+    /// Mailboxes which failed in their latest runs, most-failing first.
+    pub failing: Vec<FailingMailbox>,
+    /// Messages left unfetched, by date window or size limit, per mailbox.
+    pub skipped: Vec<SkippedSummary>,
+}
+
+impl Output for Status {
+    fn write_text(&self, w: &mut dyn Write) -> io::Result<()> {
+        for RunSummary {
+            id,
+            started,
+            ended,
+            mailboxes,
+            messages,
+            bytes,
+            errors,
+        } in &self.runs
+        {
+            let started = format_time(*started);
+            let ended =
+                ended.map_or_else(|| "unfinished".into(), format_time);
+            writeln!(
+                w,
+                "run {id}\t{started}\t{ended}\t{mailboxes} mailboxes\t\
+                {messages} messages\t{bytes} bytes\t{errors} errors"
+            )?;
+        }
+        for FailingMailbox {
+            account,
+            mailbox,
+            failures,
+            since,
+            last_error,
+        } in &self.failing
+        {
+            let mailbox = mailbox.as_deref().unwrap_or("*");
+            let since = format_time(*since);
+            writeln!(
+                w,
+                "failing {account:?}\t{mailbox:?}\t\
+                {failures} runs since {since}\t{last_error:?}"
+            )?;
+        }
+        for SkippedSummary {
+            account,
+            mailbox,
+            reason,
+            messages,
+        } in &self.skipped
+        {
+            writeln!(
+                w,
+                "skipped {account:?}\t{mailbox:?}\t{messages} messages\t\
+                by {reason}"
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl Cmd {
+    pub async fn run(&self, cfg: &Cfg) -> anyhow::Result<Status> {
+        let db = data::Storage::connect(&cfg.db).await?;
+        let runs = db.fetch_runs(self.runs).await?;
+        let failing = db.fetch_failing().await?;
+        let skipped = db.fetch_skipped_summary().await?;
+        Ok(Status {
+            runs,
+            failing,
+            skipped,
+        })
+    }
+}
+
+fn format_time(timestamp: i64) -> String {
+    mail_parser::DateTime::from_timestamp(timestamp).to_rfc3339()
+}
@@ -0,0 +1,115 @@
+use std::{
+    collections::{HashMap, HashSet},
+    io::{self, Write},
+};
+
+use anyhow::anyhow;
+
+use crate::{
+    cfg::Cfg,
+    data::{self, ThreadMember},
+    output::Output,
+};
+
+const HASH_DISPLAY_LEN: usize = 12;
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct Cmd {
+    /// Message-ID or hash of any message in the thread.
+    msg: String,
+}
+
+/// Thread members in depth-first order.
+#[derive(serde::Serialize, Debug)]
+#[serde(transparent)]
+pub struct Thread(pub Vec<Line>);
+
+#[derive(serde::Serialize, Debug)]
+pub struct Line {
+    pub depth: usize,
+    /// Is this the message the thread was looked-up by?
+    pub selected: bool,
+    pub msg_hash: String,
+    /// RFC 3339 formatted, in UTC.
+    pub date: Option<String>,
+    pub from: Option<String>,
+    pub subject: Option<String>,
+}
+
+impl Output for Thread {
+    fn write_text(&self, w: &mut dyn Write) -> io::Result<()> {
+        for Line {
+            depth,
+            selected,
+            msg_hash,
+            date,
+            from,
+            subject,
+        } in &self.0
+        {
+            let indent = "\t".repeat(*depth);
+            let mark = if *selected { "*" } else { " " };
+            let hash = &msg_hash[..HASH_DISPLAY_LEN];
+            let date = date.as_deref().unwrap_or_default();
+            let from = from.as_deref().unwrap_or_default();
+            let subject = subject.as_deref().unwrap_or_default();
+            writeln!(w, "{indent}{mark} {hash} {date} {from} {subject:?}")?;
+        }
+        Ok(())
+    }
+}
+
+impl Cmd {
+    pub async fn run(&self, cfg: &Cfg) -> anyhow::Result<Thread> {
+        let db = data::Storage::connect(&cfg.db).await?;
+        let msg_hash = db
+            .resolve_msg(&self.msg)
+            .await?
+            .ok_or_else(|| anyhow!("Message not found: {:?}", self.msg))?;
+        let thread_id = db
+            .fetch_thread_id(&msg_hash)
+            .await?
+            .ok_or_else(|| anyhow!("Message not threaded: {msg_hash:?}"))?;
+        let members = db.fetch_thread(&thread_id).await?;
+        let lines = tree(&members)
+            .into_iter()
+            .map(|(depth, member)| Line {
+                depth,
+                selected: member.msg_hash == msg_hash,
+                msg_hash: member.msg_hash.clone(),
+                date: member.date.map(|date| {
+                    mail_parser::DateTime::from_timestamp(date).to_rfc3339()
+                }),
+                from: member.from.clone(),
+                subject: member.subject.clone(),
+            })
+            .collect();
+        Ok(Thread(lines))
+    }
+}
+
+/// Members in depth-first order, each with its depth.
+fn tree(members: &[ThreadMember]) -> Vec<(usize, &ThreadMember)> {
+    let hashes: HashSet<&str> =
+        members.iter().map(|m| m.msg_hash.as_str()).collect();
+    let mut roots = Vec::new();
+    let mut children: HashMap<&str, Vec<&ThreadMember>> = HashMap::new();
+    for member in members {
+        match member.parent_hash.as_deref() {
+            Some(parent) if hashes.contains(parent) => {
+                children.entry(parent).or_default().push(member);
+            }
+            _ => roots.push(member),
+        }
+    }
+    let mut ordered = Vec::new();
+    let mut frontier: Vec<(usize, &ThreadMember)> =
+        roots.into_iter().rev().map(|m| (0, m)).collect();
+    while let Some((depth, member)) = frontier.pop() {
+        ordered.push((depth, member));
+        if let Some(children) = children.get(member.msg_hash.as_str()) {
+            frontier.extend(children.iter().rev().map(|c| (depth + 1, *c)));
+        }
+    }
+    ordered
+}
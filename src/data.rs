@@ -1,17 +1,43 @@
 use std::{
+    collections::{HashMap, HashSet},
     path::{Path, PathBuf},
     pin::Pin,
+    sync::Arc,
     time::Duration,
 };
 
 use anyhow::bail;
-use futures::{Stream, StreamExt};
+use futures::Stream;
 use sqlx::Executor;
 use tokio::fs;
 
-use crate::{cfg, file, hash};
+use crate::{
+    cfg, file, hash, progress,
+    query::{Query, Term},
+    thread,
+};
+
+/// As which a POP3 account's maildrop is reported and matched against
+/// mailbox patterns, as POP3 has no mailboxes.
+pub const POP3_MAILBOX: &str = "INBOX";
 
-const MIGRATIONS: [&str; 1] = [include_str!("../migrations/0_data.sql")];
+const MIGRATIONS: [&str; 15] = [
+    include_str!("../migrations/0_data.sql"),
+    include_str!("../migrations/1_parts.sql"),
+    include_str!("../migrations/2_attachments.sql"),
+    include_str!("../migrations/3_addresses.sql"),
+    include_str!("../migrations/4_message_meta.sql"),
+    include_str!("../migrations/5_threads.sql"),
+    include_str!("../migrations/6_duplicates.sql"),
+    include_str!("../migrations/7_locations.sql"),
+    include_str!("../migrations/8_runs.sql"),
+    include_str!("../migrations/9_skipped.sql"),
+    include_str!("../migrations/10_partial_msgs.sql"),
+    include_str!("../migrations/11_location_attrs.sql"),
+    include_str!("../migrations/12_pop3_uids.sql"),
+    include_str!("../migrations/13_imap_uids.sql"),
+    include_str!("../migrations/14_attachment_refs.sql"),
+];
 
 #[derive(sqlx::FromRow, Debug, Eq, PartialEq, Ord, PartialOrd)]
 pub struct Msg {
@@ -32,6 +58,503 @@ pub struct Body {
     pub text: Option<String>,
 }
 
+/// A text body, with what sets apart the messages which merely happen to
+/// share one.
+#[derive(sqlx::FromRow, Debug, PartialEq)]
+pub struct SentBody {
+    pub msg_hash: String,
+    pub text: Option<String>,
+    pub subject: Option<String>,
+    pub date: Option<i64>,
+    /// The From addresses, sorted and comma-separated.
+    pub sender: Option<String>,
+}
+
+#[derive(sqlx::FromRow, Debug, PartialEq)]
+pub struct Part {
+    pub msg_hash: String,
+    pub idx: u32,
+    pub content_type: String,
+    pub charset: Option<String>,
+    pub disposition: Option<String>,
+    pub filename: Option<String>,
+    pub size: u32,
+    pub text: Option<String>,
+    pub text_plain: Option<String>,
+}
+
+impl Part {
+    fn from_parsed(
+        msg_hash: &str,
+        idx: usize,
+        part: &mail_parser::MessagePart,
+    ) -> anyhow::Result<Self> {
+        use mail_parser::{MimeHeaders, PartType};
+
+        let content_type = part_content_type(part);
+        let charset = part
+            .content_type()
+            .and_then(|ct| ct.attribute("charset"))
+            .map(|c| c.to_lowercase());
+        let disposition = part
+            .content_disposition()
+            .map(|cd| cd.ctype().to_lowercase());
+        let (text, text_plain) = match &part.body {
+            PartType::Text(text) => (Some(text.to_string()), None),
+            PartType::Html(html) => (
+                Some(html.to_string()),
+                Some(mail_parser::decoders::html::html_to_text(html)),
+            ),
+            PartType::Binary(_)
+            | PartType::InlineBinary(_)
+            | PartType::Message(_)
+            | PartType::Multipart(_) => (None, None),
+        };
+        let selph = Self {
+            msg_hash: msg_hash.to_string(),
+            idx: u32::try_from(idx)?,
+            content_type,
+            charset,
+            disposition,
+            filename: part.attachment_name().map(|name| name.to_string()),
+            size: u32::try_from(part.len())?,
+            text,
+            text_plain,
+        };
+        Ok(selph)
+    }
+}
+
+pub(crate) fn part_content_type(part: &mail_parser::MessagePart) -> String {
+    use mail_parser::{MimeHeaders, PartType};
+
+    match part.content_type() {
+        Some(ct) => match ct.subtype() {
+            Some(subtype) => format!("{}/{}", ct.ctype(), subtype),
+            None => ct.ctype().to_string(),
+        },
+        // RFC 2045 default, unless mail_parser decided otherwise:
+        None => match &part.body {
+            PartType::Html(_) => "text/html".to_string(),
+            PartType::Text(_) => "text/plain".to_string(),
+            PartType::Message(_) => "message/rfc822".to_string(),
+            PartType::Multipart(_) => "multipart/mixed".to_string(),
+            PartType::Binary(_) | PartType::InlineBinary(_) => {
+                "application/octet-stream".to_string()
+            }
+        },
+    }
+    .to_lowercase()
+}
+
+/// Attachment metadata, aggregated over all messages that carry it.
+#[derive(sqlx::FromRow, serde::Serialize, Debug, PartialEq)]
+pub struct Attachment {
+    pub hash: String,
+    pub mime_type: String,
+    pub size: u32,
+    /// One of the names it was sent under, if it was named at all.
+    pub name: Option<String>,
+    pub msgs: u32,
+}
+
+#[derive(sqlx::FromRow, Debug, Eq, PartialEq, Ord, PartialOrd)]
+pub struct Address {
+    pub msg_hash: String,
+    pub role: String,
+    pub addr: String,
+    pub name: Option<String>,
+}
+
+impl Address {
+    fn from_parsed(msg_hash: &str, msg: &mail_parser::Message) -> Vec<Self> {
+        use mail_parser::{Addr, Address, Group};
+
+        let roles = [
+            ("from", msg.from()),
+            ("to", msg.to()),
+            ("cc", msg.cc()),
+            ("bcc", msg.bcc()),
+            ("reply-to", msg.reply_to()),
+        ];
+        let mut addresses = Vec::new();
+        for (role, address) in roles {
+            let addrs: Vec<&Addr> = match address {
+                None => Vec::new(),
+                Some(Address::List(addrs)) => addrs.iter().collect(),
+                Some(Address::Group(groups)) => groups
+                    .iter()
+                    .flat_map(|Group { name: _, addresses }| addresses)
+                    .collect(),
+            };
+            for Addr { name, address } in addrs {
+                let Some(addr) = address else {
+                    continue;
+                };
+                let addr = addr.trim().to_lowercase();
+                if addr.is_empty() {
+                    continue;
+                }
+                let name = name
+                    .as_ref()
+                    .map(|name| name.trim())
+                    .filter(|name| !name.is_empty())
+                    .map(|name| name.to_string());
+                addresses.push(Self {
+                    msg_hash: msg_hash.to_string(),
+                    role: role.to_string(),
+                    addr,
+                    name,
+                });
+            }
+        }
+        addresses
+    }
+}
+
+#[derive(sqlx::FromRow, Debug, PartialEq)]
+pub struct MsgMeta {
+    pub msg_hash: String,
+    /// Unix time, UTC.
+    pub date: Option<i64>,
+    /// Minutes east of UTC, as originally written in the "Date:" header.
+    pub date_offset: Option<i32>,
+    pub subject: Option<String>,
+    pub message_id: Option<String>,
+    pub size: u32,
+    pub part_count: u32,
+    pub has_attachments: bool,
+}
+
+impl MsgMeta {
+    fn from_parsed(
+        msg_hash: &str,
+        size: usize,
+        msg: &mail_parser::Message,
+    ) -> anyhow::Result<Self> {
+        let date = msg.date().filter(|date| date.is_valid());
+        let date_offset = date.map(|date| {
+            let minutes =
+                i32::from(date.tz_hour) * 60 + i32::from(date.tz_minute);
+            if date.tz_before_gmt {
+                -minutes
+            } else {
+                minutes
+            }
+        });
+        let selph = Self {
+            msg_hash: msg_hash.to_string(),
+            date: date.map(|date| date.to_timestamp()),
+            date_offset,
+            subject: msg.subject().map(|subject| subject.to_string()),
+            message_id: msg.message_id().map(|id| id.to_string()),
+            size: u32::try_from(size)?,
+            part_count: u32::try_from(msg.parts.len())?,
+            has_attachments: !msg.attachments.is_empty(),
+        };
+        Ok(selph)
+    }
+}
+
+/// A message in a thread, with enough to display it in a tree.
+#[derive(sqlx::FromRow, Debug, PartialEq)]
+pub struct ThreadMember {
+    pub msg_hash: String,
+    pub parent_hash: Option<String>,
+    pub date: Option<i64>,
+    pub subject: Option<String>,
+    pub from: Option<String>,
+}
+
+/// A message as a line in a list.
+#[derive(sqlx::FromRow, Debug, PartialEq)]
+pub struct MsgSummary {
+    pub msg_hash: String,
+    pub date: Option<i64>,
+    pub subject: Option<String>,
+    pub from: Option<String>,
+    pub has_attachments: Option<bool>,
+}
+
+/// What one fetch run got from one mailbox.
+#[derive(sqlx::FromRow, serde::Serialize, Debug, Default, PartialEq)]
+pub struct MailboxRun {
+    pub account: String,
+    /// None when the account as a whole failed, rather than a mailbox.
+    pub mailbox: Option<String>,
+    pub messages: u32,
+    pub bytes: i64,
+    pub error: Option<String>,
+    pub duration_ms: i64,
+    /// Left for later, as per the date window or size limit. Not recorded
+    /// with the run, since the skipped messages themselves are.
+    #[sqlx(skip)]
+    pub skipped: u32,
+
+    /// Cut short, with `--fail-fast`, by another account's failure. Not
+    /// recorded with the run, whose error says as much.
+    #[sqlx(skip)]
+    pub aborted: bool,
+}
+
+impl MailboxRun {
+    /// Failed of itself, rather than was aborted after another failure.
+    #[must_use]
+    pub fn failed(&self) -> bool {
+        self.error.is_some() && !self.aborted
+    }
+}
+
+/// Totals of a fetch run.
+#[derive(sqlx::FromRow, serde::Serialize, Debug, PartialEq)]
+pub struct RunSummary {
+    pub id: i64,
+    /// Unix time.
+    pub started: i64,
+    /// Unix time. None if still running or interrupted.
+    pub ended: Option<i64>,
+    pub mailboxes: u32,
+    pub messages: i64,
+    pub bytes: i64,
+    pub errors: u32,
+}
+
+/// A mailbox (or account) which failed in its latest runs.
+#[derive(sqlx::FromRow, serde::Serialize, Debug, PartialEq)]
+pub struct FailingMailbox {
+    pub account: String,
+    pub mailbox: Option<String>,
+    /// Consecutive failed runs, up to the latest.
+    pub failures: u32,
+    /// Unix time the first of the consecutive failed runs started.
+    pub since: i64,
+    pub last_error: String,
+}
+
+/// A message left unfetched, for now.
+#[derive(sqlx::FromRow, serde::Serialize, Debug, PartialEq)]
+pub struct SkippedMsg {
+    pub uid: u32,
+    pub reason: String,
+    pub size: Option<u32>,
+}
+
+/// What the server had to say about a message besides its content, as of
+/// fetching it.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct MsgAttrs {
+    pub flags: Vec<String>,
+
+    /// As IMAP has it: "17-Jul-1996 02:44:25 -0700".
+    pub internal_date: Option<String>,
+}
+
+impl MsgAttrs {
+    /// From flags as stored: space-separated.
+    fn from_stored(flags: &str, internal_date: Option<String>) -> Self {
+        Self {
+            flags: flags.split_whitespace().map(str::to_string).collect(),
+            internal_date,
+        }
+    }
+}
+
+/// A message as fetched from a mailbox.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Located {
+    pub mailbox: String,
+    pub msg_hash: String,
+    pub message_id: Option<String>,
+    pub attrs: MsgAttrs,
+}
+
+#[derive(sqlx::FromRow)]
+struct LocatedRow {
+    mailbox: String,
+    msg_hash: String,
+    message_id: Option<String>,
+    flags: Option<String>,
+    internal_date: Option<String>,
+}
+
+/// How many messages of a mailbox were skipped and why.
+#[derive(sqlx::FromRow, serde::Serialize, Debug, PartialEq)]
+pub struct SkippedSummary {
+    pub account: String,
+    pub mailbox: String,
+    pub reason: String,
+    pub messages: u32,
+}
+
+/// A [`Query`] compiled to an SQL condition on messages.
+#[derive(Debug, Clone, Default)]
+pub struct Filter {
+    /// None selects all messages.
+    condition: Option<String>,
+    params: Vec<Param>,
+}
+
+#[derive(Debug, Clone)]
+enum Param {
+    Text(String),
+    Int(i64),
+}
+
+impl Filter {
+    #[must_use]
+    pub fn new(query: &Query) -> Self {
+        let mut params = Vec::new();
+        let condition = Some(Self::compile(query, &mut params));
+        Self { condition, params }
+    }
+
+    /// SQL condition on "messages m LEFT JOIN message_meta mm".
+    fn compile(query: &Query, params: &mut Vec<Param>) -> String {
+        let like = |s: &str| {
+            let s = s
+                .replace('\\', "\\\\")
+                .replace('%', "\\%")
+                .replace('_', "\\_");
+            Param::Text(format!("%{s}%"))
+        };
+        let addr = |roles: &str, s: &str, params: &mut Vec<Param>| {
+            params.push(like(s));
+            params.push(like(s));
+            format!(
+                "EXISTS (\
+                    SELECT 1 FROM addresses a \
+                    WHERE a.msg_hash = m.hash \
+                    AND a.role IN ({roles}) \
+                    AND (a.addr LIKE ? ESCAPE '\\' OR a.name LIKE ? ESCAPE '\\')\
+                )"
+            )
+        };
+        let int = |n: u64| Param::Int(i64::try_from(n).unwrap_or(i64::MAX));
+        let combine = |queries: &[Query],
+                       op: &str,
+                       params: &mut Vec<Param>| {
+            let conditions: Vec<String> =
+                queries.iter().map(|q| Self::compile(q, params)).collect();
+            format!("({})", conditions.join(op))
+        };
+        match query {
+            Query::And(queries) => combine(queries, " AND ", params),
+            Query::Or(queries) => combine(queries, " OR ", params),
+            // Missing values, like an unparsable date, are unknown in SQL,
+            // but should be false here, so that negation makes them true:
+            Query::Not(query) => {
+                format!("NOT coalesce({}, 0)", Self::compile(query, params))
+            }
+            Query::Term(term) => match term {
+                Term::From(s) => addr("'from'", s, params),
+                Term::To(s) => addr("'to', 'cc', 'bcc'", s, params),
+                Term::Cc(s) => addr("'cc'", s, params),
+                Term::Bcc(s) => addr("'bcc'", s, params),
+                Term::Subject(s) => {
+                    params.push(like(s));
+                    "mm.subject LIKE ? ESCAPE '\\'".to_string()
+                }
+                Term::Text(s) => {
+                    params.push(like(s));
+                    params.push(like(s));
+                    "(mm.subject LIKE ? ESCAPE '\\' OR EXISTS (\
+                        SELECT 1 FROM bodies b \
+                        WHERE b.msg_hash = m.hash \
+                        AND b.text LIKE ? ESCAPE '\\'\
+                    ))"
+                    .to_string()
+                }
+                // POP3 msgs have no locations, only their accounts' UIDLs.
+                Term::Account(s) => {
+                    params.push(Param::Text(s.clone()));
+                    params.push(Param::Text(s.clone()));
+                    "(EXISTS (\
+                        SELECT 1 FROM locations l \
+                        WHERE l.msg_hash = m.hash AND l.account GLOB ?\
+                    ) OR EXISTS (\
+                        SELECT 1 FROM pop3_uids p \
+                        WHERE p.msg_hash = m.hash AND p.account GLOB ?\
+                    ))"
+                    .to_string()
+                }
+                Term::Mailbox(s) => {
+                    params.push(Param::Text(s.clone()));
+                    params.push(Param::Text(POP3_MAILBOX.to_string()));
+                    params.push(Param::Text(s.clone()));
+                    "(EXISTS (\
+                        SELECT 1 FROM locations l \
+                        WHERE l.msg_hash = m.hash AND l.mailbox GLOB ?\
+                    ) OR EXISTS (\
+                        SELECT 1 FROM pop3_uids p \
+                        WHERE p.msg_hash = m.hash AND ? GLOB ?\
+                    ))"
+                    .to_string()
+                }
+                Term::HasAttachment => "EXISTS (\
+                        SELECT 1 FROM attachment_links al \
+                        WHERE al.msg_hash = m.hash\
+                    )"
+                .to_string(),
+                Term::SizeAbove(n) => {
+                    params.push(int(*n));
+                    "length(m.raw) > ?".to_string()
+                }
+                Term::SizeBelow(n) => {
+                    params.push(int(*n));
+                    "length(m.raw) < ?".to_string()
+                }
+                Term::DateFrom(t) => {
+                    params.push(Param::Int(*t));
+                    "mm.date >= ?".to_string()
+                }
+                Term::DateUntil(t) => {
+                    params.push(Param::Int(*t));
+                    "mm.date < ?".to_string()
+                }
+            },
+        }
+    }
+
+    /// Hashes of the selected messages, in date order.
+    fn select_hashes(&self) -> String {
+        self.select("m.hash", "mm.date, m.rowid")
+    }
+
+    fn select(&self, columns: &str, order: &str) -> String {
+        let condition = self.condition.as_deref().unwrap_or("1");
+        format!(
+            "SELECT {columns} \
+            FROM messages m \
+            LEFT JOIN message_meta mm ON mm.msg_hash = m.hash \
+            WHERE coalesce({condition}, 0) \
+            ORDER BY {order}"
+        )
+    }
+
+    fn bind<'q, O>(
+        &'q self,
+        mut query: sqlx::query::QueryAs<
+            'q,
+            sqlx::Sqlite,
+            O,
+            sqlx::sqlite::SqliteArguments<'q>,
+        >,
+    ) -> sqlx::query::QueryAs<
+        'q,
+        sqlx::Sqlite,
+        O,
+        sqlx::sqlite::SqliteArguments<'q>,
+    > {
+        for param in &self.params {
+            query = match param {
+                Param::Text(s) => query.bind(s),
+                Param::Int(n) => query.bind(n),
+            };
+        }
+        query
+    }
+}
+
 #[derive(Debug, sqlx::FromRow)]
 pub struct LastSeenMsg {
     pub account: String,
@@ -62,6 +585,70 @@ impl Storage {
         Ok(selph)
     }
 
+    /// For looking without touching, as in dry runs: neither creates the
+    /// database, nor migrates it. A missing database is as good as empty,
+    /// and so is a table missing from one not yet migrated.
+    pub async fn connect_read_only(cfg: &cfg::Db) -> anyhow::Result<Self> {
+        let empty = Self::connect_empty().await?;
+        if !fs::try_exists(&cfg.file).await? {
+            return Ok(empty);
+        }
+        let tables: Vec<(String, String)> = sqlx::query_as(
+            "SELECT name, sql FROM sqlite_master \
+            WHERE type = 'table' AND name NOT LIKE 'sqlite_%'",
+        )
+        .fetch_all(&empty.pool)
+        .await?;
+        let tables = Arc::new(tables);
+        let options = sqlx::sqlite::SqliteConnectOptions::new()
+            .filename(&cfg.file)
+            .read_only(true)
+            .busy_timeout(Duration::from_secs(60));
+        // Temporary tables, which are per connection, stand in for the
+        // missing ones, as they would be once migrated, but empty.
+        let pool = sqlx::sqlite::SqlitePoolOptions::new()
+            .after_connect(move |conn, _| {
+                let tables = Arc::clone(&tables);
+                Box::pin(async move {
+                    for (name, sql) in tables.iter() {
+                        let (missing,): (bool,) = sqlx::query_as(
+                            "SELECT NOT EXISTS (SELECT 1 FROM sqlite_master \
+                            WHERE type = 'table' AND name = ?)",
+                        )
+                        .bind(name)
+                        .fetch_one(&mut *conn)
+                        .await?;
+                        if missing {
+                            let sql = sql.replacen(
+                                "CREATE TABLE",
+                                "CREATE TEMP TABLE",
+                                1,
+                            );
+                            conn.execute(sql.as_str()).await?;
+                        }
+                    }
+                    Ok(())
+                })
+            })
+            .connect_with(options)
+            .await?;
+        Ok(Self { pool })
+    }
+
+    /// Migrated, but in memory, so always empty.
+    async fn connect_empty() -> anyhow::Result<Self> {
+        // Each in-memory connection is a database of its own.
+        let pool = sqlx::sqlite::SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await?;
+        let selph = Self { pool };
+        for migration in MIGRATIONS {
+            selph.pool.execute(migration).await?;
+        }
+        Ok(selph)
+    }
+
     pub async fn store_last_seen(
         &self,
         account: &str,
@@ -95,7 +682,8 @@ impl Storage {
         }
     }
 
-    pub async fn store_msg(&self, raw: &[u8]) -> anyhow::Result<()> {
+    /// Returns the hash under which the message was stored.
+    pub async fn store_msg(&self, raw: &[u8]) -> anyhow::Result<String> {
         let hash = hash::sha256(raw);
         let msg = Msg {
             hash,
@@ -104,9 +692,76 @@ impl Storage {
         let mut tx = self.pool.begin().await?;
         tx = tx_insert_msg(tx, &msg).await?;
         tx.commit().await?;
+        Ok(msg.hash)
+    }
+
+    pub async fn store_location(
+        &self,
+        msg_hash: &str,
+        account: &str,
+        mailbox: &str,
+        uid: u32,
+    ) -> anyhow::Result<()> {
+        sqlx::query(
+            "INSERT OR REPLACE INTO locations (msg_hash, account, mailbox, uid) \
+            VALUES (?, ?, ?, ?)",
+        )
+        .bind(msg_hash)
+        .bind(account)
+        .bind(mailbox)
+        .bind(uid)
+        .execute(&self.pool)
+        .await?;
         Ok(())
     }
 
+    /// Hashes of the messages selected by the filter, in date order.
+    pub async fn find(&self, filter: &Filter) -> sqlx::Result<Vec<String>> {
+        let sql = filter.select_hashes();
+        let hashes: Vec<(String,)> = filter
+            .bind(sqlx::query_as(&sql))
+            .fetch_all(&self.pool)
+            .await?;
+        Ok(hashes.into_iter().map(|(hash,)| hash).collect())
+    }
+
+    /// One page of the messages selected by the filter, newest first.
+    pub async fn fetch_summaries(
+        &self,
+        filter: &Filter,
+        limit: u32,
+        offset: u32,
+    ) -> sqlx::Result<Vec<MsgSummary>> {
+        let sql = filter.select(
+            "m.hash AS msg_hash, \
+            mm.date AS date, \
+            mm.subject AS subject, \
+            ( \
+                SELECT coalesce(a.name, a.addr) FROM addresses a \
+                WHERE a.msg_hash = m.hash AND a.role = 'from' \
+                LIMIT 1 \
+            ) AS \"from\", \
+            mm.has_attachments AS has_attachments",
+            &format!(
+                "mm.date DESC, m.rowid DESC LIMIT {limit} OFFSET {offset}"
+            ),
+        );
+        filter
+            .bind(sqlx::query_as(&sql))
+            .fetch_all(&self.pool)
+            .await
+    }
+
+    pub async fn count(&self, filter: &Filter) -> anyhow::Result<u64> {
+        let sql = filter.select("count(*)", "1");
+        let (count,): (i64,) = filter
+            .bind(sqlx::query_as(&sql))
+            .fetch_one(&self.pool)
+            .await?;
+        let count = u64::try_from(count)?;
+        Ok(count)
+    }
+
     pub async fn count_messages(&self) -> anyhow::Result<u64> {
         let (count,): (i64,) =
             sqlx::query_as("SELECT count(*) FROM messages")
@@ -146,124 +801,1310 @@ impl Storage {
         Ok(bodies.pop())
     }
 
-    pub async fn import(&self, obj_dir: &Path) -> anyhow::Result<()> {
-        let mut tx = self.pool.begin().await?;
-        // TODO Parallelize:
-        //      - task to par_iter (rayon) from fs and write to channel
-        //      - task to read from channel and write to db
-        let (msgs_count, msgs) = exported(obj_dir);
-        let progress_bar =
-            indicatif::ProgressBar::new(u64::try_from(msgs_count)?);
-        let progress_style = indicatif::ProgressStyle::with_template(
-            "{bar:100.green} {pos:>7} / {len:7}",
-        )?;
-        progress_bar.set_style(progress_style);
-        progress_bar.tick();
-        for msg in msgs {
-            tx = tx_insert_msg(tx, &msg).await?;
-            progress_bar.inc(1);
-        }
-        tx.commit().await?;
-        progress_bar.finish();
-        Ok(())
+    pub async fn fetch_parts(
+        &self,
+        msg_hash: &str,
+    ) -> sqlx::Result<Vec<Part>> {
+        sqlx::query_as("SELECT * FROM parts WHERE msg_hash = ? ORDER BY idx")
+            .bind(msg_hash)
+            .fetch_all(&self.pool)
+            .await
     }
 
-    pub async fn export(&self, obj_dir: &Path) -> anyhow::Result<()> {
-        if fs::try_exists(obj_dir).await? {
-            if !fs::metadata(obj_dir).await?.is_dir() {
-                bail!("Not a directory: {obj_dir:?}");
-            }
-        } else {
-            fs::create_dir_all(obj_dir).await?;
+    pub async fn fetch_attachments(
+        &self,
+        mime_type: Option<&str>,
+    ) -> sqlx::Result<Vec<Attachment>> {
+        sqlx::query_as(
+            "SELECT \
+                a.hash AS hash, \
+                a.mime_type AS mime_type, \
+                a.size AS size, \
+                min(l.name) AS name, \
+                count(DISTINCT l.msg_hash) AS msgs \
+            FROM attachments a \
+            JOIN attachment_links l ON l.attachment_hash = a.hash \
+            WHERE ?1 IS NULL OR a.mime_type = ?1 \
+            GROUP BY a.hash \
+            ORDER BY a.size DESC, a.hash",
+        )
+        .bind(mime_type)
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    /// Decoded from the first of the messages it's in, as attachments
+    /// aren't stored apart from their messages.
+    pub async fn fetch_attachment_data(
+        &self,
+        hash: &str,
+    ) -> anyhow::Result<Option<Vec<u8>>> {
+        let links: Vec<(String, u32)> = sqlx::query_as(
+            "SELECT msg_hash, part_idx FROM attachment_links \
+            WHERE attachment_hash = ? \
+            ORDER BY msg_hash, part_idx",
+        )
+        .bind(hash)
+        .fetch_all(&self.pool)
+        .await?;
+        if links.is_empty() {
+            return Ok(None);
         }
-        let msgs_count = self.count_messages().await?;
-        let mut msgs = self.fetch_messages();
-        let progress_bar = indicatif::ProgressBar::new(msgs_count);
-        let progress_style = indicatif::ProgressStyle::with_template(
-            "{bar:100.green} {pos:>7} / {len:7}",
-        )?;
-        progress_bar.set_style(progress_style);
-        progress_bar.tick();
-        // TODO Parallelize.
-        while let Some(msg_result) = msgs.next().await {
-            let Msg { hash, raw } = msg_result?;
-            file::write_as_gz(
-                obj_dir.join(&hash[..2]).join(&hash).with_extension("eml"),
-                raw,
-            )?;
-            progress_bar.inc(1);
+        for (msg_hash, part_idx) in &links {
+            let Some(msg) = self.fetch_msg(msg_hash).await? else {
+                continue;
+            };
+            let idx = usize::try_from(*part_idx)?;
+            let parsed =
+                mail_parser::MessageParser::default().parse(&msg.raw);
+            let part =
+                parsed.as_ref().and_then(|parsed| parsed.parts.get(idx));
+            if let Some(data) = part.map(|part| part.contents()) {
+                // As stored, unless parsing has changed since.
+                if hash::sha256(data) == hash {
+                    return Ok(Some(data.to_vec()));
+                }
+            }
         }
-        progress_bar.finish();
-        Ok(())
+        bail!("Attachment {hash} doesn't decode from its messages anymore.")
     }
-}
 
-async fn tx_insert_msg<'tx>(
-    mut tx: sqlx::Transaction<'tx, sqlx::Sqlite>,
-    msg: &Msg,
-) -> anyhow::Result<sqlx::Transaction<'tx, sqlx::Sqlite>> {
-    tx = tx_insert_msg_(tx, msg).await?;
-    let (headers, _) = mailparse::parse_headers(&msg.raw[..])?;
-    for header in headers {
-        let name = header.get_key();
-        let value = header.get_value();
-        tx = tx_insert_header(tx, &msg.hash, &name, &value).await?;
-    }
-    if let Some(body_text) = mail_parser::MessageParser::default()
-        .parse(&msg.raw[..])
-        .and_then(|m| m.body_text(0).map(|b| b.to_string()))
-    {
-        tx = tx_insert_body(tx, &msg.hash, &body_text).await?;
+    pub async fn fetch_meta(
+        &self,
+        msg_hash: &str,
+    ) -> sqlx::Result<Option<MsgMeta>> {
+        sqlx::query_as("SELECT * FROM message_meta WHERE msg_hash = ?")
+            .bind(msg_hash)
+            .fetch_optional(&self.pool)
+            .await
     }
-    Ok(tx)
-}
 
-async fn tx_insert_msg_<'tx>(
-    mut tx: sqlx::Transaction<'tx, sqlx::Sqlite>,
-    msg: &Msg,
-) -> anyhow::Result<sqlx::Transaction<'tx, sqlx::Sqlite>> {
-    sqlx::query("INSERT OR IGNORE INTO messages (hash, raw) VALUES (?, ?)")
-        .bind(&msg.hash)
-        .bind(&msg.raw[..])
-        .execute(&mut *tx)
+    /// Find a message by its hash, a unique prefix of its hash (like git)
+    /// or its Message-ID (with or without the angle brackets).
+    pub async fn resolve_msg(
+        &self,
+        spec: &str,
+    ) -> anyhow::Result<Option<String>> {
+        const MIN_PREFIX_LEN: usize = 4;
+
+        let spec = spec.trim();
+        let message_id = spec
+            .strip_prefix('<')
+            .and_then(|s| s.strip_suffix('>'))
+            .unwrap_or(spec);
+        let hash: Option<(String,)> =
+            sqlx::query_as("SELECT hash FROM messages WHERE hash = ?")
+                .bind(spec)
+                .fetch_optional(&self.pool)
+                .await?;
+        if let Some((hash,)) = hash {
+            return Ok(Some(hash));
+        }
+        let mut hashes: Vec<(String,)> = sqlx::query_as(
+            "SELECT msg_hash FROM message_meta WHERE message_id = ? \
+            ORDER BY msg_hash",
+        )
+        .bind(message_id)
+        .fetch_all(&self.pool)
         .await?;
-    Ok(tx)
-}
+        if hashes.len() > 1 {
+            bail!(
+                "Ambiguous Message-ID: {spec:?}. Candidates: {:?}",
+                candidates(hashes)
+            );
+        }
+        if let Some((hash,)) = hashes.pop() {
+            return Ok(Some(hash));
+        }
+        let is_prefix = spec.len() >= MIN_PREFIX_LEN
+            && spec.chars().all(|c| c.is_ascii_hexdigit());
+        if !is_prefix {
+            return Ok(None);
+        }
+        let mut hashes: Vec<(String,)> = sqlx::query_as(
+            "SELECT hash FROM messages WHERE substr(hash, 1, ?) = ? \
+            ORDER BY hash",
+        )
+        .bind(u32::try_from(spec.len())?)
+        .bind(spec.to_lowercase())
+        .fetch_all(&self.pool)
+        .await?;
+        if hashes.len() > 1 {
+            bail!(
+                "Ambiguous hash prefix: {spec:?}. Candidates: {:?}",
+                candidates(hashes)
+            );
+        }
+        Ok(hashes.pop().map(|(hash,)| hash))
+    }
 
-async fn tx_insert_header<'tx>(
-    mut tx: sqlx::Transaction<'tx, sqlx::Sqlite>,
-    msg_hash: &str,
-    name: &str,
-    value: &str,
-) -> anyhow::Result<sqlx::Transaction<'tx, sqlx::Sqlite>> {
-    sqlx::query(
-        "INSERT OR IGNORE INTO headers (msg_hash, name, value) VALUES (?, ?, ?)"
-    )
+    pub async fn fetch_msg(&self, hash: &str) -> sqlx::Result<Option<Msg>> {
+        sqlx::query_as("SELECT * FROM messages WHERE hash = ?")
+            .bind(hash)
+            .fetch_optional(&self.pool)
+            .await
+    }
+
+    pub async fn fetch_msg_attachments(
+        &self,
+        msg_hash: &str,
+    ) -> sqlx::Result<Vec<Attachment>> {
+        sqlx::query_as(
+            "SELECT \
+                a.hash AS hash, \
+                a.mime_type AS mime_type, \
+                a.size AS size, \
+                l.name AS name, \
+                1 AS msgs \
+            FROM attachment_links l \
+            JOIN attachments a ON a.hash = l.attachment_hash \
+            WHERE l.msg_hash = ? \
+            ORDER BY l.part_idx",
+        )
         .bind(msg_hash)
-        .bind(name)
-        .bind(value)
-        .execute(&mut *tx)
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    pub async fn fetch_thread_id(
+        &self,
+        msg_hash: &str,
+    ) -> sqlx::Result<Option<String>> {
+        let thread_id: Option<(String,)> = sqlx::query_as(
+            "SELECT thread_id FROM threads WHERE msg_hash = ?",
+        )
+        .bind(msg_hash)
+        .fetch_optional(&self.pool)
         .await?;
-    Ok(tx)
-}
+        Ok(thread_id.map(|(thread_id,)| thread_id))
+    }
+
+    pub async fn fetch_thread(
+        &self,
+        thread_id: &str,
+    ) -> sqlx::Result<Vec<ThreadMember>> {
+        sqlx::query_as(
+            "SELECT \
+                t.msg_hash AS msg_hash, \
+                t.parent_hash AS parent_hash, \
+                m.date AS date, \
+                m.subject AS subject, \
+                ( \
+                    SELECT addr FROM addresses a \
+                    WHERE a.msg_hash = t.msg_hash AND a.role = 'from' \
+                    LIMIT 1 \
+                ) AS \"from\" \
+            FROM threads t \
+            LEFT JOIN message_meta m ON m.msg_hash = t.msg_hash \
+            WHERE t.thread_id = ? \
+            ORDER BY m.date, t.msg_hash",
+        )
+        .bind(thread_id)
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    /// Re-thread the whole archive from scratch, which can find links that
+    /// incremental threading on insertion missed, like when a reply arrives
+    /// before the message it replies to.
+    pub async fn rebuild_threads(&self) -> anyhow::Result<()> {
+        let metas: Vec<(String, Option<String>, Option<String>)> =
+            sqlx::query_as(
+                "SELECT msg_hash, message_id, subject FROM message_meta \
+                ORDER BY date, msg_hash",
+            )
+            .fetch_all(&self.pool)
+            .await?;
+        let headers: Vec<(String, String, String)> = sqlx::query_as(
+            "SELECT msg_hash, lower(name), value FROM headers \
+            WHERE lower(name) IN ('references', 'in-reply-to') \
+            ORDER BY rowid",
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        let mut refs: HashMap<String, (Vec<String>, Vec<String>)> =
+            HashMap::new();
+        for (msg_hash, name, value) in headers {
+            let (references, in_reply_to) = refs.entry(msg_hash).or_default();
+            if name == "references" {
+                references.extend(thread::msg_ids(&value));
+            } else {
+                in_reply_to.extend(thread::msg_ids(&value));
+            }
+        }
+        let msgs: Vec<thread::Msg> = metas
+            .into_iter()
+            .map(|(hash, message_id, subject)| {
+                let references = refs
+                    .get(&hash)
+                    .map(|(references, in_reply_to)| {
+                        thread::references(references, in_reply_to)
+                    })
+                    .unwrap_or_default();
+                thread::Msg {
+                    hash,
+                    message_id,
+                    references,
+                    subject,
+                }
+            })
+            .collect();
+        let msgs_by_hash: HashMap<&str, &thread::Msg> =
+            msgs.iter().map(|msg| (msg.hash.as_str(), msg)).collect();
+        let mut tx = self.pool.begin().await?;
+        sqlx::query("DELETE FROM threads").execute(&mut *tx).await?;
+        for link in thread::jwz(&msgs) {
+            let msg = msgs_by_hash
+                .get(link.hash.as_str())
+                .unwrap_or_else(|| unreachable!());
+            tx = tx_insert_thread_row(tx, msg, &link).await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// Messages sharing a Message-ID with at least one other, as
+    /// (Message-ID, hash), grouped by Message-ID and oldest-stored first.
+    pub async fn fetch_message_id_duplicates(
+        &self,
+    ) -> sqlx::Result<Vec<(String, String)>> {
+        sqlx::query_as(
+            "SELECT m.message_id, m.msg_hash \
+            FROM message_meta m \
+            JOIN messages ms ON ms.hash = m.msg_hash \
+            WHERE m.message_id IN ( \
+                SELECT message_id FROM message_meta \
+                WHERE message_id IS NOT NULL \
+                GROUP BY message_id \
+                HAVING count(*) > 1 \
+            ) \
+            ORDER BY m.message_id, ms.rowid",
+        )
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    /// All text bodies, oldest-stored first.
+    #[must_use]
+    pub fn fetch_bodies(
+        &self,
+    ) -> Pin<Box<dyn Stream<Item = sqlx::Result<SentBody>> + '_>> {
+        sqlx::query_as(
+            "SELECT b.msg_hash, b.text, m.subject, m.date, ( \
+                SELECT group_concat(addr, ',') FROM ( \
+                    SELECT addr FROM addresses a \
+                    WHERE a.msg_hash = b.msg_hash AND a.role = 'from' \
+                    ORDER BY addr \
+                ) \
+            ) AS sender \
+            FROM bodies b \
+            JOIN messages ms ON ms.hash = b.msg_hash \
+            LEFT JOIN message_meta m ON m.msg_hash = b.msg_hash \
+            ORDER BY ms.rowid",
+        )
+        .fetch(&self.pool)
+    }
+
+    pub async fn mark_duplicates(
+        &self,
+        canonical_hash: &str,
+        msg_hashes: &[String],
+        reason: &str,
+    ) -> anyhow::Result<()> {
+        let mut tx = self.pool.begin().await?;
+        for msg_hash in msg_hashes {
+            sqlx::query(
+                "INSERT OR REPLACE INTO duplicates \
+                (msg_hash, canonical_hash, reason) VALUES (?, ?, ?)",
+            )
+            .bind(msg_hash)
+            .bind(canonical_hash)
+            .bind(reason)
+            .execute(&mut *tx)
+            .await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// Marked duplicates, as (hash, canonical hash, reason).
+    pub async fn fetch_duplicates(
+        &self,
+    ) -> sqlx::Result<Vec<(String, String, String)>> {
+        sqlx::query_as(
+            "SELECT msg_hash, canonical_hash, reason FROM duplicates \
+            ORDER BY msg_hash, reason",
+        )
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    /// Returns the new run's ID.
+    pub async fn start_run(&self) -> anyhow::Result<i64> {
+        let id =
+            sqlx::query("INSERT INTO runs (started) VALUES (unixepoch())")
+                .execute(&self.pool)
+                .await?
+                .last_insert_rowid();
+        Ok(id)
+    }
+
+    pub async fn end_run(
+        &self,
+        run_id: i64,
+        mailboxes: &[MailboxRun],
+    ) -> anyhow::Result<()> {
+        let mut tx = self.pool.begin().await?;
+        for MailboxRun {
+            account,
+            mailbox,
+            messages,
+            bytes,
+            error,
+            duration_ms,
+            skipped: _,
+            aborted: _,
+        } in mailboxes
+        {
+            sqlx::query(
+                "INSERT INTO run_mailboxes \
+                (run_id, account, mailbox, messages, bytes, error, duration_ms) \
+                VALUES (?, ?, ?, ?, ?, ?, ?)",
+            )
+            .bind(run_id)
+            .bind(account)
+            .bind(mailbox)
+            .bind(messages)
+            .bind(bytes)
+            .bind(error)
+            .bind(duration_ms)
+            .execute(&mut *tx)
+            .await?;
+        }
+        sqlx::query("UPDATE runs SET ended = unixepoch() WHERE id = ?")
+            .bind(run_id)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    pub async fn store_skipped(
+        &self,
+        account: &str,
+        mailbox: &str,
+        skipped: &[SkippedMsg],
+    ) -> anyhow::Result<()> {
+        let mut tx = self.pool.begin().await?;
+        for SkippedMsg { uid, reason, size } in skipped {
+            sqlx::query(
+                "INSERT OR REPLACE INTO skipped_msgs \
+                (account, mailbox, uid, reason, size) VALUES (?, ?, ?, ?, ?)",
+            )
+            .bind(account)
+            .bind(mailbox)
+            .bind(uid)
+            .bind(reason)
+            .bind(size)
+            .execute(&mut *tx)
+            .await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// Skipped messages of the mailbox, by UID.
+    pub async fn fetch_skipped(
+        &self,
+        account: &str,
+        mailbox: &str,
+    ) -> sqlx::Result<Vec<SkippedMsg>> {
+        sqlx::query_as(
+            "SELECT uid, reason, size FROM skipped_msgs \
+            WHERE account = ? AND mailbox = ? ORDER BY uid",
+        )
+        .bind(account)
+        .bind(mailbox)
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    /// Forget a skipped message, once it is fetched after all.
+    pub async fn delete_skipped(
+        &self,
+        account: &str,
+        mailbox: &str,
+        uid: u32,
+    ) -> sqlx::Result<()> {
+        sqlx::query(
+            "DELETE FROM skipped_msgs \
+            WHERE account = ? AND mailbox = ? AND uid = ?",
+        )
+        .bind(account)
+        .bind(mailbox)
+        .bind(uid)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn store_partial(
+        &self,
+        account: &str,
+        mailbox: &str,
+        uid: u32,
+        offset: usize,
+        data: &[u8],
+    ) -> anyhow::Result<()> {
+        sqlx::query(
+            "INSERT OR REPLACE INTO partial_msgs \
+            (account, mailbox, uid, offset, data) VALUES (?, ?, ?, ?, ?)",
+        )
+        .bind(account)
+        .bind(mailbox)
+        .bind(uid)
+        .bind(i64::try_from(offset)?)
+        .bind(data)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    /// The parts of the message fetched so far, joined.
+    pub async fn fetch_partial(
+        &self,
+        account: &str,
+        mailbox: &str,
+        uid: u32,
+    ) -> sqlx::Result<Vec<u8>> {
+        let parts: Vec<(Vec<u8>,)> = sqlx::query_as(
+            "SELECT data FROM partial_msgs \
+            WHERE account = ? AND mailbox = ? AND uid = ? ORDER BY offset",
+        )
+        .bind(account)
+        .bind(mailbox)
+        .bind(uid)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(parts.into_iter().flat_map(|(data,)| data).collect())
+    }
+
+    pub async fn delete_partial(
+        &self,
+        account: &str,
+        mailbox: &str,
+        uid: u32,
+    ) -> sqlx::Result<()> {
+        sqlx::query(
+            "DELETE FROM partial_msgs \
+            WHERE account = ? AND mailbox = ? AND uid = ?",
+        )
+        .bind(account)
+        .bind(mailbox)
+        .bind(uid)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn store_attrs(
+        &self,
+        account: &str,
+        mailbox: &str,
+        uid: u32,
+        attrs: &MsgAttrs,
+    ) -> sqlx::Result<()> {
+        sqlx::query(
+            "INSERT OR REPLACE INTO location_attrs \
+            (account, mailbox, uid, flags, internal_date) \
+            VALUES (?, ?, ?, ?, ?)",
+        )
+        .bind(account)
+        .bind(mailbox)
+        .bind(uid)
+        .bind(attrs.flags.join(" "))
+        .bind(&attrs.internal_date)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    /// Attributes of the message as of its location in the mailbox, given
+    /// as (account, mailbox), if it was fetched from there, or else as of
+    /// its first location, by account, mailbox and UID.
+    pub async fn fetch_attrs(
+        &self,
+        msg_hash: &str,
+        mailbox: Option<(&str, &str)>,
+    ) -> sqlx::Result<Option<MsgAttrs>> {
+        let (account, mailbox) = mailbox.unzip();
+        let attrs: Option<(String, Option<String>)> = sqlx::query_as(
+            "SELECT a.flags, a.internal_date \
+            FROM locations l \
+            JOIN location_attrs a \
+                ON a.account = l.account \
+                AND a.mailbox = l.mailbox \
+                AND a.uid = l.uid \
+            WHERE l.msg_hash = ? \
+            ORDER BY coalesce(l.account = ? AND l.mailbox = ?, 0) DESC, \
+                l.account, l.mailbox, l.uid \
+            LIMIT 1",
+        )
+        .bind(msg_hash)
+        .bind(account)
+        .bind(mailbox)
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(attrs.map(|(flags, internal_date)| {
+            MsgAttrs::from_stored(&flags, internal_date)
+        }))
+    }
+
+    /// Where the account's messages were fetched from, by mailbox, in UID
+    /// order.
+    pub async fn fetch_located(
+        &self,
+        account: &str,
+    ) -> sqlx::Result<Vec<Located>> {
+        let rows: Vec<LocatedRow> = sqlx::query_as(
+            "SELECT l.mailbox, l.msg_hash, m.message_id, a.flags, \
+                a.internal_date \
+            FROM locations l \
+            LEFT JOIN message_meta m ON m.msg_hash = l.msg_hash \
+            LEFT JOIN location_attrs a \
+                ON a.account = l.account \
+                AND a.mailbox = l.mailbox \
+                AND a.uid = l.uid \
+            WHERE l.account = ? \
+            ORDER BY l.mailbox, l.uid",
+        )
+        .bind(account)
+        .fetch_all(&self.pool)
+        .await?;
+        let located = rows
+            .into_iter()
+            .map(|row| Located {
+                mailbox: row.mailbox,
+                msg_hash: row.msg_hash,
+                message_id: row.message_id,
+                attrs: MsgAttrs::from_stored(
+                    &row.flags.unwrap_or_default(),
+                    row.internal_date,
+                ),
+            })
+            .collect();
+        Ok(located)
+    }
+
+    /// Record the message as uploaded to the mailbox by restore.
+    pub async fn store_restored(
+        &self,
+        msg_hash: &str,
+        account: &str,
+        mailbox: &str,
+    ) -> sqlx::Result<()> {
+        sqlx::query(
+            "INSERT OR IGNORE INTO restored_msgs (msg_hash, account, mailbox) \
+            VALUES (?, ?, ?)",
+        )
+        .bind(msg_hash)
+        .bind(account)
+        .bind(mailbox)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    /// Hashes of the messages restore already uploaded to the mailbox.
+    pub async fn fetch_restored(
+        &self,
+        account: &str,
+        mailbox: &str,
+    ) -> sqlx::Result<HashSet<String>> {
+        let hashes: Vec<(String,)> = sqlx::query_as(
+            "SELECT msg_hash FROM restored_msgs \
+            WHERE account = ? AND mailbox = ?",
+        )
+        .bind(account)
+        .bind(mailbox)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(hashes.into_iter().map(|(hash,)| hash).collect())
+    }
+
+    pub async fn store_pop3_uid(
+        &self,
+        account: &str,
+        uid: &str,
+        msg_hash: &str,
+    ) -> sqlx::Result<()> {
+        sqlx::query(
+            "INSERT OR REPLACE INTO pop3_uids (account, uid, msg_hash) \
+            VALUES (?, ?, ?)",
+        )
+        .bind(account)
+        .bind(uid)
+        .bind(msg_hash)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    /// UIDL unique-ids of the messages fetched from the POP3 account.
+    pub async fn fetch_pop3_uids(
+        &self,
+        account: &str,
+    ) -> sqlx::Result<HashSet<String>> {
+        let uids: Vec<(String,)> =
+            sqlx::query_as("SELECT uid FROM pop3_uids WHERE account = ?")
+                .bind(account)
+                .fetch_all(&self.pool)
+                .await?;
+        Ok(uids.into_iter().map(|(uid,)| uid).collect())
+    }
+
+    pub async fn fetch_skipped_summary(
+        &self,
+    ) -> sqlx::Result<Vec<SkippedSummary>> {
+        sqlx::query_as(
+            "SELECT account, mailbox, reason, count(*) AS messages \
+            FROM skipped_msgs \
+            GROUP BY account, mailbox, reason \
+            ORDER BY account, mailbox, reason",
+        )
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    /// The latest runs, newest first.
+    pub async fn fetch_runs(
+        &self,
+        limit: u32,
+    ) -> sqlx::Result<Vec<RunSummary>> {
+        sqlx::query_as(
+            "SELECT \
+                r.id AS id, \
+                r.started AS started, \
+                r.ended AS ended, \
+                count(m.run_id) AS mailboxes, \
+                coalesce(sum(m.messages), 0) AS messages, \
+                coalesce(sum(m.bytes), 0) AS bytes, \
+                count(m.error) AS errors \
+            FROM runs r \
+            LEFT JOIN run_mailboxes m ON m.run_id = r.id \
+            GROUP BY r.id \
+            ORDER BY r.id DESC \
+            LIMIT ?",
+        )
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    /// Mailboxes whose latest run failed, most-failing first. An account
+    /// failure counts as fixed by any later mailbox success.
+    pub async fn fetch_failing(&self) -> sqlx::Result<Vec<FailingMailbox>> {
+        sqlx::query_as(
+            "SELECT \
+                f.account AS account, \
+                f.mailbox AS mailbox, \
+                count(*) AS failures, \
+                min(r.started) AS since, \
+                ( \
+                    SELECT l.error FROM run_mailboxes l \
+                    WHERE l.account = f.account AND l.mailbox IS f.mailbox \
+                    ORDER BY l.run_id DESC \
+                    LIMIT 1 \
+                ) AS last_error \
+            FROM run_mailboxes f \
+            JOIN runs r ON r.id = f.run_id \
+            WHERE f.error IS NOT NULL \
+            AND f.run_id > coalesce( \
+                ( \
+                    SELECT max(ok.run_id) FROM run_mailboxes ok \
+                    WHERE ok.account = f.account \
+                    AND (f.mailbox IS NULL OR ok.mailbox IS f.mailbox) \
+                    AND ok.error IS NULL \
+                ), \
+                0 \
+            ) \
+            GROUP BY f.account, f.mailbox \
+            ORDER BY failures DESC, f.account, f.mailbox",
+        )
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    /// (IMAP UID, hash) of the selected messages, in UID order. Messages
+    /// without a UID yet are first given one, in the order they were stored.
+    pub async fn find_uids(
+        &self,
+        filter: &Filter,
+    ) -> anyhow::Result<Vec<(u32, String)>> {
+        sqlx::query(
+            "INSERT OR IGNORE INTO imap_uids (msg_hash) \
+            SELECT hash FROM messages m \
+            WHERE NOT EXISTS \
+                (SELECT 1 FROM imap_uids u WHERE u.msg_hash = m.hash) \
+            ORDER BY rowid",
+        )
+        .execute(&self.pool)
+        .await?;
+        let sql = filter.select(
+            "(SELECT uid FROM imap_uids u WHERE u.msg_hash = m.hash) AS uid, \
+            m.hash",
+            "uid",
+        );
+        let rows: Vec<(Option<i64>, String)> = filter
+            .bind(sqlx::query_as(&sql))
+            .fetch_all(&self.pool)
+            .await?;
+        // Any stored since UIDs were handed out are left for the next time.
+        rows.into_iter()
+            .filter_map(|(uid, hash)| Some((uid?, hash)))
+            .map(|(uid, hash)| Ok((u32::try_from(uid)?, hash)))
+            .collect()
+    }
+
+    /// Hashes of messages with a header of the given name (in any case)
+    /// with a value containing the given string (in any ASCII case).
+    pub async fn find_by_header(
+        &self,
+        name: &str,
+        value: &str,
+    ) -> sqlx::Result<Vec<String>> {
+        let hashes: Vec<(String,)> = sqlx::query_as(
+            "SELECT DISTINCT msg_hash FROM headers \
+            WHERE lower(name) = lower(?) AND instr(lower(value), lower(?)) > 0",
+        )
+        .bind(name)
+        .bind(value)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(hashes.into_iter().map(|(hash,)| hash).collect())
+    }
+
+    /// Distinct (account, mailbox) pairs messages were fetched from.
+    pub async fn fetch_mailboxes(
+        &self,
+    ) -> sqlx::Result<Vec<(String, String)>> {
+        sqlx::query_as(
+            "SELECT DISTINCT account, mailbox FROM locations \
+            ORDER BY account, mailbox",
+        )
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    /// Distinct years (UTC) of message dates.
+    pub async fn fetch_years(&self) -> sqlx::Result<Vec<i64>> {
+        let years: Vec<(i64,)> = sqlx::query_as(
+            "SELECT DISTINCT CAST(strftime('%Y', date, 'unixepoch') AS INTEGER) AS year \
+            FROM message_meta \
+            WHERE date IS NOT NULL \
+            ORDER BY year",
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(years.into_iter().map(|(year,)| year).collect())
+    }
+
+    pub async fn fetch_addresses(
+        &self,
+        role: &str,
+        filter: &Filter,
+    ) -> sqlx::Result<Vec<Address>> {
+        let sql = format!(
+            "SELECT * FROM addresses WHERE role = ? AND msg_hash IN ({})",
+            filter.select_hashes()
+        );
+        filter
+            .bind(sqlx::query_as(&sql).bind(role))
+            .fetch_all(&self.pool)
+            .await
+    }
+
+    /// Re-derive all parsed tables from the raw messages, filling-in any
+    /// that were introduced after the messages were stored. Returns the
+    /// number of messages reindexed.
+    pub async fn reindex(&self) -> anyhow::Result<usize> {
+        let hashes: Vec<(String,)> =
+            sqlx::query_as("SELECT hash FROM messages")
+                .fetch_all(&self.pool)
+                .await?;
+        let progress_bar =
+            progress::bar(u64::try_from(hashes.len())?, "reindex");
+        let progress_style = indicatif::ProgressStyle::with_template(
+            "{bar:100.green} {pos:>7} / {len:7}",
+        )?;
+        progress_bar.set_style(progress_style);
+        progress_bar.tick();
+        let count = hashes.len();
+        let mut tx = self.pool.begin().await?;
+        for (hash,) in hashes {
+            let msg: Msg =
+                sqlx::query_as("SELECT * FROM messages WHERE hash = ?")
+                    .bind(&hash)
+                    .fetch_one(&mut *tx)
+                    .await?;
+            tx = tx_insert_derived(tx, &msg).await?;
+            progress_bar.inc(1);
+        }
+        tx.commit().await?;
+        progress_bar.finish();
+        self.rebuild_threads().await?;
+        Ok(count)
+    }
+
+    /// Returns the number of messages read, including those already stored.
+    pub async fn import(&self, obj_dir: &Path) -> anyhow::Result<usize> {
+        let mut tx = self.pool.begin().await?;
+        // TODO Parallelize:
+        //      - task to par_iter (rayon) from fs and write to channel
+        //      - task to read from channel and write to db
+        let (msgs_count, msgs) = exported(obj_dir);
+        let progress_bar =
+            progress::bar(u64::try_from(msgs_count)?, "import");
+        let progress_style = indicatif::ProgressStyle::with_template(
+            "{bar:100.green} {pos:>7} / {len:7}",
+        )?;
+        progress_bar.set_style(progress_style);
+        progress_bar.tick();
+        for msg in msgs {
+            tx = tx_insert_msg(tx, &msg).await?;
+            progress_bar.inc(1);
+        }
+        tx.commit().await?;
+        progress_bar.finish();
+        Ok(msgs_count)
+    }
+
+    /// How many of the messages in the file tree are new and how many are
+    /// already stored, without reading them.
+    pub async fn import_plan(
+        &self,
+        obj_dir: &Path,
+    ) -> anyhow::Result<(usize, usize)> {
+        let stored: HashSet<String> =
+            self.find(&Filter::default()).await?.into_iter().collect();
+        let (existing, new): (Vec<String>, Vec<String>) =
+            exported_paths(obj_dir)
+                .into_iter()
+                .map(|(_, hash)| hash)
+                .partition(|hash| stored.contains(hash));
+        Ok((new.len(), existing.len()))
+    }
+
+    /// How many messages would be written and how many of those would
+    /// overwrite files already in the tree.
+    pub async fn export_plan(
+        &self,
+        obj_dir: &Path,
+        filter: &Filter,
+    ) -> anyhow::Result<(usize, usize)> {
+        let hashes = self.find(filter).await?;
+        let mut existing = 0;
+        for hash in &hashes {
+            let path = export_path(obj_dir, hash).with_extension("eml.gz");
+            if fs::try_exists(path).await? {
+                existing += 1;
+            }
+        }
+        Ok((hashes.len(), existing))
+    }
+
+    /// Returns the number of messages written.
+    pub async fn export(
+        &self,
+        obj_dir: &Path,
+        filter: &Filter,
+    ) -> anyhow::Result<usize> {
+        if fs::try_exists(obj_dir).await? {
+            if !fs::metadata(obj_dir).await?.is_dir() {
+                bail!("Not a directory: {obj_dir:?}");
+            }
+        } else {
+            fs::create_dir_all(obj_dir).await?;
+        }
+        let hashes = self.find(filter).await?;
+        let progress_bar =
+            progress::bar(u64::try_from(hashes.len())?, "export");
+        let progress_style = indicatif::ProgressStyle::with_template(
+            "{bar:100.green} {pos:>7} / {len:7}",
+        )?;
+        progress_bar.set_style(progress_style);
+        progress_bar.tick();
+        let mut count = 0;
+        // TODO Parallelize.
+        for hash in hashes {
+            let Some(Msg { hash, raw }) = self.fetch_msg(&hash).await? else {
+                continue;
+            };
+            file::write_as_gz(export_path(obj_dir, &hash), raw)?;
+            count += 1;
+            progress_bar.inc(1);
+        }
+        progress_bar.finish();
+        Ok(count)
+    }
+}
+
+async fn tx_insert_msg<'tx>(
+    mut tx: sqlx::Transaction<'tx, sqlx::Sqlite>,
+    msg: &Msg,
+) -> anyhow::Result<sqlx::Transaction<'tx, sqlx::Sqlite>> {
+    tx = tx_insert_msg_(tx, msg).await?;
+    tx = tx_insert_derived(tx, msg).await?;
+    Ok(tx)
+}
+
+async fn tx_insert_derived<'tx>(
+    mut tx: sqlx::Transaction<'tx, sqlx::Sqlite>,
+    msg: &Msg,
+) -> anyhow::Result<sqlx::Transaction<'tx, sqlx::Sqlite>> {
+    let (headers, _) = mailparse::parse_headers(&msg.raw[..])?;
+    let mut references = Vec::new();
+    let mut in_reply_to = Vec::new();
+    for header in headers {
+        let name = header.get_key();
+        let value = header.get_value();
+        if name.eq_ignore_ascii_case("references") {
+            references.extend(thread::msg_ids(&value));
+        } else if name.eq_ignore_ascii_case("in-reply-to") {
+            in_reply_to.extend(thread::msg_ids(&value));
+        }
+        tx = tx_insert_header(tx, &msg.hash, &name, &value).await?;
+    }
+    if let Some(parsed) =
+        mail_parser::MessageParser::default().parse(&msg.raw[..])
+    {
+        if let Some(body_text) = parsed.body_text(0) {
+            tx = tx_insert_body(tx, &msg.hash, &body_text).await?;
+        }
+        for (idx, part) in parsed.parts.iter().enumerate() {
+            let part = Part::from_parsed(&msg.hash, idx, part)?;
+            tx = tx_insert_part(tx, &part).await?;
+        }
+        for &idx in &parsed.attachments {
+            if let Some(part) = parsed.parts.get(idx) {
+                tx = tx_insert_attachment(tx, &msg.hash, idx, part).await?;
+            }
+        }
+        for address in Address::from_parsed(&msg.hash, &parsed) {
+            tx = tx_insert_address(tx, &address).await?;
+        }
+        let meta = MsgMeta::from_parsed(&msg.hash, msg.raw.len(), &parsed)?;
+        tx = tx_insert_meta(tx, &meta).await?;
+        let thread_msg = thread::Msg {
+            hash: msg.hash.clone(),
+            message_id: meta.message_id,
+            references: thread::references(&references, &in_reply_to),
+            subject: meta.subject,
+        };
+        tx = tx_insert_thread(tx, &thread_msg).await?;
+    }
+    Ok(tx)
+}
+
+async fn tx_insert_msg_<'tx>(
+    mut tx: sqlx::Transaction<'tx, sqlx::Sqlite>,
+    msg: &Msg,
+) -> anyhow::Result<sqlx::Transaction<'tx, sqlx::Sqlite>> {
+    sqlx::query("INSERT OR IGNORE INTO messages (hash, raw) VALUES (?, ?)")
+        .bind(&msg.hash)
+        .bind(&msg.raw[..])
+        .execute(&mut *tx)
+        .await?;
+    Ok(tx)
+}
+
+async fn tx_insert_header<'tx>(
+    mut tx: sqlx::Transaction<'tx, sqlx::Sqlite>,
+    msg_hash: &str,
+    name: &str,
+    value: &str,
+) -> anyhow::Result<sqlx::Transaction<'tx, sqlx::Sqlite>> {
+    sqlx::query(
+        "INSERT OR IGNORE INTO headers (msg_hash, name, value) VALUES (?, ?, ?)"
+    )
+        .bind(msg_hash)
+        .bind(name)
+        .bind(value)
+        .execute(&mut *tx)
+        .await?;
+    Ok(tx)
+}
 
 async fn tx_insert_body<'tx>(
     mut tx: sqlx::Transaction<'tx, sqlx::Sqlite>,
-    msg_hash: &str,
-    text: &str,
+    msg_hash: &str,
+    text: &str,
+) -> anyhow::Result<sqlx::Transaction<'tx, sqlx::Sqlite>> {
+    sqlx::query(
+        "INSERT OR IGNORE INTO bodies (msg_hash, text) VALUES (?, ?)",
+    )
+    .bind(msg_hash)
+    .bind(text)
+    .execute(&mut *tx)
+    .await?;
+    Ok(tx)
+}
+
+async fn tx_insert_part<'tx>(
+    mut tx: sqlx::Transaction<'tx, sqlx::Sqlite>,
+    part: &Part,
+) -> anyhow::Result<sqlx::Transaction<'tx, sqlx::Sqlite>> {
+    sqlx::query(
+        "INSERT OR IGNORE INTO parts \
+        (msg_hash, idx, content_type, charset, disposition, filename, size, text, text_plain) \
+        VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)",
+    )
+    .bind(&part.msg_hash)
+    .bind(part.idx)
+    .bind(&part.content_type)
+    .bind(&part.charset)
+    .bind(&part.disposition)
+    .bind(&part.filename)
+    .bind(part.size)
+    .bind(&part.text)
+    .bind(&part.text_plain)
+    .execute(&mut *tx)
+    .await?;
+    Ok(tx)
+}
+
+async fn tx_insert_attachment<'tx>(
+    mut tx: sqlx::Transaction<'tx, sqlx::Sqlite>,
+    msg_hash: &str,
+    part_idx: usize,
+    part: &mail_parser::MessagePart<'_>,
+) -> anyhow::Result<sqlx::Transaction<'tx, sqlx::Sqlite>> {
+    use mail_parser::MimeHeaders;
+
+    // Only what's known of the data, which is decoded from the message
+    // again when needed, rather than stored a second time.
+    let data = part.contents();
+    let hash = hash::sha256(data);
+    sqlx::query(
+        "INSERT OR IGNORE INTO attachments (hash, mime_type, size, data) \
+        VALUES (?, ?, ?, x'')",
+    )
+    .bind(&hash)
+    .bind(part_content_type(part))
+    .bind(u32::try_from(data.len())?)
+    .execute(&mut *tx)
+    .await?;
+    sqlx::query(
+        "INSERT OR IGNORE INTO attachment_links \
+        (attachment_hash, msg_hash, part_idx, name) VALUES (?, ?, ?, ?)",
+    )
+    .bind(&hash)
+    .bind(msg_hash)
+    .bind(u32::try_from(part_idx)?)
+    .bind(part.attachment_name())
+    .execute(&mut *tx)
+    .await?;
+    Ok(tx)
+}
+
+async fn tx_insert_address<'tx>(
+    mut tx: sqlx::Transaction<'tx, sqlx::Sqlite>,
+    address: &Address,
+) -> anyhow::Result<sqlx::Transaction<'tx, sqlx::Sqlite>> {
+    sqlx::query(
+        "INSERT OR IGNORE INTO addresses (msg_hash, role, addr, name) \
+        VALUES (?, ?, ?, ?)",
+    )
+    .bind(&address.msg_hash)
+    .bind(&address.role)
+    .bind(&address.addr)
+    .bind(&address.name)
+    .execute(&mut *tx)
+    .await?;
+    Ok(tx)
+}
+
+async fn tx_insert_meta<'tx>(
+    mut tx: sqlx::Transaction<'tx, sqlx::Sqlite>,
+    meta: &MsgMeta,
+) -> anyhow::Result<sqlx::Transaction<'tx, sqlx::Sqlite>> {
+    sqlx::query(
+        "INSERT OR REPLACE INTO message_meta \
+        (msg_hash, date, date_offset, subject, message_id, size, part_count, has_attachments) \
+        VALUES (?, ?, ?, ?, ?, ?, ?, ?)",
+    )
+    .bind(&meta.msg_hash)
+    .bind(meta.date)
+    .bind(meta.date_offset)
+    .bind(&meta.subject)
+    .bind(&meta.message_id)
+    .bind(meta.size)
+    .bind(meta.part_count)
+    .bind(meta.has_attachments)
+    .execute(&mut *tx)
+    .await?;
+    Ok(tx)
+}
+
+/// Thread a single new message into what is already stored.
+async fn tx_insert_thread<'tx>(
+    mut tx: sqlx::Transaction<'tx, sqlx::Sqlite>,
+    msg: &thread::Msg,
+) -> anyhow::Result<sqlx::Transaction<'tx, sqlx::Sqlite>> {
+    let threaded: Option<(String,)> =
+        sqlx::query_as("SELECT msg_hash FROM threads WHERE msg_hash = ?")
+            .bind(&msg.hash)
+            .fetch_optional(&mut *tx)
+            .await?;
+    if threaded.is_some() {
+        return Ok(tx);
+    }
+
+    // (hash, thread_id)
+    let mut parent: Option<(String, String)> = None;
+    for id in msg.references.iter().rev() {
+        parent = sqlx::query_as(
+            "SELECT t.msg_hash, t.thread_id \
+            FROM message_meta m \
+            JOIN threads t ON t.msg_hash = m.msg_hash \
+            WHERE m.message_id = ? AND m.msg_hash != ? \
+            LIMIT 1",
+        )
+        .bind(id)
+        .bind(&msg.hash)
+        .fetch_optional(&mut *tx)
+        .await?;
+        if parent.is_some() {
+            break;
+        }
+    }
+    if parent.is_none() && msg.references.is_empty() {
+        if let Some((base, true)) =
+            msg.subject.as_deref().map(thread::subject_base)
+        {
+            if !base.is_empty() {
+                parent = sqlx::query_as(
+                    "SELECT msg_hash, thread_id FROM threads \
+                    WHERE subject_base = ? \
+                    ORDER BY rowid \
+                    LIMIT 1",
+                )
+                .bind(base)
+                .fetch_optional(&mut *tx)
+                .await?;
+            }
+        }
+    }
+    let link = match parent {
+        Some((parent_hash, thread_id)) => thread::Link {
+            hash: msg.hash.clone(),
+            thread_id,
+            parent_hash: Some(parent_hash),
+        },
+        None => thread::Link {
+            hash: msg.hash.clone(),
+            thread_id: msg
+                .references
+                .first()
+                .or(msg.message_id.as_ref())
+                .unwrap_or(&msg.hash)
+                .clone(),
+            parent_hash: None,
+        },
+    };
+    tx = tx_insert_thread_row(tx, msg, &link).await?;
+
+    // Adopt replies which arrived before this message did, other than its
+    // own ancestors, as in mutual references, which would make a loop:
+    if let Some(message_id) = &msg.message_id {
+        sqlx::query(
+            "WITH RECURSIVE ancestors(hash) AS ( \
+                SELECT ?1 \
+                UNION \
+                SELECT t.parent_hash FROM threads t \
+                JOIN ancestors a ON t.msg_hash = a.hash \
+                WHERE t.parent_hash IS NOT NULL \
+            ) \
+            UPDATE threads SET parent_hash = ?1 \
+            WHERE parent_message_id = ?2 \
+            AND msg_hash NOT IN (SELECT hash FROM ancestors) \
+            AND ( \
+                parent_hash IS NULL \
+                OR parent_hash NOT IN \
+                    (SELECT msg_hash FROM message_meta WHERE message_id = ?2) \
+            )",
+        )
+        .bind(&msg.hash)
+        .bind(message_id)
+        .execute(&mut *tx)
+        .await?;
+        if &link.thread_id != message_id {
+            sqlx::query(
+                "UPDATE threads SET thread_id = ? WHERE thread_id = ?",
+            )
+            .bind(&link.thread_id)
+            .bind(message_id)
+            .execute(&mut *tx)
+            .await?;
+        }
+    }
+    Ok(tx)
+}
+
+async fn tx_insert_thread_row<'tx>(
+    mut tx: sqlx::Transaction<'tx, sqlx::Sqlite>,
+    msg: &thread::Msg,
+    link: &thread::Link,
 ) -> anyhow::Result<sqlx::Transaction<'tx, sqlx::Sqlite>> {
     sqlx::query(
-        "INSERT OR IGNORE INTO bodies (msg_hash, text) VALUES (?, ?)",
+        "INSERT OR REPLACE INTO threads \
+        (msg_hash, thread_id, parent_hash, parent_message_id, subject_base) \
+        VALUES (?, ?, ?, ?, ?)",
+    )
+    .bind(&link.hash)
+    .bind(&link.thread_id)
+    .bind(&link.parent_hash)
+    .bind(msg.references.last())
+    .bind(
+        msg.subject
+            .as_deref()
+            .map(|subject| thread::subject_base(subject).0),
     )
-    .bind(msg_hash)
-    .bind(text)
     .execute(&mut *tx)
     .await?;
     Ok(tx)
 }
 
+/// Hashes, for an error message, up to a screenful of them.
+fn candidates(hashes: Vec<(String,)>) -> Vec<String> {
+    const MAX: usize = 10;
+
+    let mut candidates: Vec<String> =
+        hashes.into_iter().take(MAX).map(|(hash,)| hash).collect();
+    if candidates.len() == MAX {
+        candidates.push("...".to_string());
+    }
+    candidates
+}
+
+fn export_path(obj_dir: &Path, hash: &str) -> PathBuf {
+    obj_dir.join(&hash[..2]).join(hash).with_extension("eml")
+}
+
 fn exported(path: &Path) -> (usize, impl Iterator<Item = Msg>) {
-    let paths_and_stems: Vec<(PathBuf, String)> = crate::fs::find_files(path)
+    let paths_and_stems = exported_paths(path);
+    let n = paths_and_stems.len();
+    let msgs = paths_and_stems.into_iter().filter_map(|(path, stem)| {
+        crate::file::read_gz(&path)
+            .ok()
+            .map(|raw| Msg { hash: stem, raw })
+    });
+    (n, msgs)
+}
+
+/// Paths of the exported messages along with their hashes.
+fn exported_paths(path: &Path) -> Vec<(PathBuf, String)> {
+    crate::fs::find_files(path)
         .filter(|p| p.to_string_lossy().ends_with(".eml.gz"))
         .filter_map(|path| {
             let stem = path.file_stem().and_then(|s| {
@@ -273,14 +2114,7 @@ fn exported(path: &Path) -> (usize, impl Iterator<Item = Msg>) {
             });
             stem.map(|s| (path, s))
         })
-        .collect();
-    let n = paths_and_stems.len();
-    let msgs = paths_and_stems.into_iter().filter_map(|(path, stem)| {
-        crate::file::read_gz(&path)
-            .ok()
-            .map(|raw| Msg { hash: stem, raw })
-    });
-    (n, msgs)
+        .collect()
 }
 
 #[cfg(test)]
@@ -342,11 +2176,25 @@ mod tests {
                 .await
         );
 
-        db.export(&obj_dir).await.unwrap();
+        let plan = db.export_plan(&obj_dir, &Filter::default()).await;
+        assert_eq!((1, 0), plan.unwrap());
+        db.export(&obj_dir, &Filter::default()).await.unwrap();
+        let plan = db.export_plan(&obj_dir, &Filter::default()).await;
+        assert_eq!((1, 1), plan.unwrap());
+        assert_eq!((0, 1), db.import_plan(&obj_dir).await.unwrap());
+        let nothing = Storage::connect_read_only(&cfg::Db {
+            file: cfg.file.with_extension("missing"),
+        })
+        .await
+        .unwrap();
+        assert_eq!((1, 0), nothing.import_plan(&obj_dir).await.unwrap());
+        assert!(!fs::try_exists(cfg.file.with_extension("missing"))
+            .await
+            .unwrap());
         let obj_file = format!(
             "{}.eml.gz",
             obj_dir
-                .join(msg_hash[..2].to_string())
+                .join(&msg_hash[..2])
                 .join(msg_hash)
                 .to_string_lossy()
         );
@@ -364,4 +2212,661 @@ mod tests {
             db.fetch_last_seen(account, mailbox).await.unwrap().unwrap()
         );
     }
+
+    #[tokio::test]
+    async fn read_only_unmigrated() {
+        let cfg = cfg::Db {
+            file: tempfile::tempdir().unwrap().path().join("db"),
+        };
+        let db = Storage::connect(&cfg).await.unwrap();
+        let msg_hash = db.store_msg(b"From: a@b\r\n\r\nHi").await.unwrap();
+        // As if made before these tables were.
+        for table in
+            ["skipped_msgs", "pop3_uids", "addresses", "message_meta"]
+        {
+            db.pool
+                .execute(format!("DROP TABLE {table}").as_str())
+                .await
+                .unwrap();
+        }
+        db.pool.close().await;
+
+        let db = Storage::connect_read_only(&cfg).await.unwrap();
+        assert!(db.fetch_msg(&msg_hash).await.unwrap().is_some());
+        assert!(db.fetch_skipped("a", "INBOX").await.unwrap().is_empty());
+        assert!(db.fetch_pop3_uids("a").await.unwrap().is_empty());
+        let filter = Filter::new(&"from:a@b".parse().unwrap());
+        let obj_dir = tempfile::tempdir().unwrap();
+        assert_eq!(
+            (0, 0),
+            db.export_plan(obj_dir.path(), &filter).await.unwrap()
+        );
+        db.pool.close().await;
+        // Left untouched.
+        let db = Storage::connect_read_only(&cfg).await.unwrap();
+        let (tables,): (i64,) = sqlx::query_as(
+            "SELECT count(*) FROM sqlite_master WHERE name = 'pop3_uids'",
+        )
+        .fetch_one(&db.pool)
+        .await
+        .unwrap();
+        assert_eq!(0, tables);
+    }
+
+    #[tokio::test]
+    async fn parts() {
+        let cfg = cfg::Db {
+            file: tempfile::tempdir().unwrap().path().join("db"),
+        };
+        let db = Storage::connect(&cfg).await.unwrap();
+        let msg: &str = "\
+From: A <A@Example.com>
+To: b@example.com, \"C\" <c@example.com>
+Cc: Friends: d@example.com;
+Date: Tue, 2 Mar 2021 09:30:00 -0500
+Subject: Deck
+Message-ID: <deck@example.com>
+Content-Type: multipart/alternative; boundary=\"b\"
+
+--b
+Content-Type: text/plain; charset=utf-8
+
+Hi plain
+--b
+Content-Type: text/html; charset=utf-8
+
+<p>Hi <b>html</b></p>
+--b
+Content-Type: application/pdf
+Content-Disposition: attachment; filename=\"deck.pdf\"
+
+%PDF
+--b--
+";
+        let msg_hash = hash::sha256(msg);
+        db.store_msg(msg.as_bytes()).await.unwrap();
+        let parts = db.fetch_parts(&msg_hash).await.unwrap();
+        let summary: Vec<(u32, &str, Option<&str>, Option<&str>)> = parts
+            .iter()
+            .map(|p| {
+                (
+                    p.idx,
+                    p.content_type.as_str(),
+                    p.disposition.as_deref(),
+                    p.filename.as_deref(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            vec![
+                (0, "multipart/alternative", None, None),
+                (1, "text/plain", None, None),
+                (2, "text/html", None, None),
+                (3, "application/pdf", Some("attachment"), Some("deck.pdf")),
+            ],
+            summary
+        );
+        assert_eq!(Some("Hi plain"), parts[1].text.as_deref());
+        assert_eq!(Some("utf-8"), parts[1].charset.as_deref());
+        assert_eq!(Some("<p>Hi <b>html</b></p>"), parts[2].text.as_deref());
+        assert_eq!(Some("Hi html\n"), parts[2].text_plain.as_deref());
+        assert_eq!(None, parts[3].text);
+        assert_eq!(4, parts[3].size);
+
+        let pdf_hash = hash::sha256("%PDF");
+        assert_eq!(
+            vec![Attachment {
+                hash: pdf_hash.clone(),
+                mime_type: "application/pdf".to_string(),
+                size: 4,
+                name: Some("deck.pdf".to_string()),
+                msgs: 1,
+            }],
+            db.fetch_attachments(None).await.unwrap()
+        );
+        assert!(db
+            .fetch_attachments(Some("image/png"))
+            .await
+            .unwrap()
+            .is_empty());
+        assert_eq!(
+            Some(b"%PDF".to_vec()),
+            db.fetch_attachment_data(&pdf_hash).await.unwrap()
+        );
+        // Decoded from the message, not stored next to it.
+        let stored = "SELECT sum(length(data)) FROM attachments";
+        let (bytes,): (i64,) =
+            sqlx::query_as(stored).fetch_one(&db.pool).await.unwrap();
+        assert_eq!(0, bytes);
+        // Nor kept, when stored before.
+        sqlx::query("UPDATE attachments SET data = x'00'")
+            .execute(&db.pool)
+            .await
+            .unwrap();
+        let db = Storage::connect(&cfg).await.unwrap();
+        let (bytes,): (i64,) =
+            sqlx::query_as(stored).fetch_one(&db.pool).await.unwrap();
+        assert_eq!(0, bytes);
+        assert_eq!(
+            Some(b"%PDF".to_vec()),
+            db.fetch_attachment_data(&pdf_hash).await.unwrap()
+        );
+        assert_eq!(None, db.fetch_attachment_data("nope").await.unwrap());
+
+        let address = |role: &str, addr: &str, name: Option<&str>| Address {
+            msg_hash: msg_hash.clone(),
+            role: role.to_string(),
+            addr: addr.to_string(),
+            name: name.map(|n| n.to_string()),
+        };
+        assert_eq!(
+            vec![address("from", "a@example.com", Some("A"))],
+            db.fetch_addresses("from", &Filter::default())
+                .await
+                .unwrap()
+        );
+        let mut to_actual: Vec<Address> =
+            db.fetch_addresses("to", &Filter::default()).await.unwrap();
+        to_actual.sort();
+        assert_eq!(
+            vec![
+                address("to", "b@example.com", None),
+                address("to", "c@example.com", Some("C")),
+            ],
+            to_actual
+        );
+        assert_eq!(
+            vec![address("cc", "d@example.com", None)],
+            db.fetch_addresses("cc", &Filter::default()).await.unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn meta() {
+        let cfg = cfg::Db {
+            file: tempfile::tempdir().unwrap().path().join("db"),
+        };
+        let db = Storage::connect(&cfg).await.unwrap();
+        let msg: &str = "\
+Date: Tue, 2 Mar 2021 09:30:00 -0500
+Subject: Hello
+Message-ID: <hello@example.com>
+
+Hi";
+        let msg_hash = hash::sha256(msg);
+        db.store_msg(msg.as_bytes()).await.unwrap();
+        assert_eq!(
+            Some(MsgMeta {
+                msg_hash: msg_hash.clone(),
+                date: Some(1_614_695_400), // 2021-03-02T14:30:00Z
+                date_offset: Some(-300),
+                subject: Some("Hello".to_string()),
+                message_id: Some("hello@example.com".to_string()),
+                size: u32::try_from(msg.len()).unwrap(),
+                part_count: 1,
+                has_attachments: false,
+            }),
+            db.fetch_meta(&msg_hash).await.unwrap()
+        );
+
+        let msg: &str = "Date: garbage\n\nHi";
+        let msg_hash = hash::sha256(msg);
+        db.store_msg(msg.as_bytes()).await.unwrap();
+        let meta = db.fetch_meta(&msg_hash).await.unwrap().unwrap();
+        assert_eq!(None, meta.date);
+        assert_eq!(None, meta.date_offset);
+    }
+
+    #[tokio::test]
+    async fn reindex() {
+        let cfg = cfg::Db {
+            file: tempfile::tempdir().unwrap().path().join("db"),
+        };
+        let db = Storage::connect(&cfg).await.unwrap();
+        let msg: &str = "From: a@example.com\nSubject: hi\n\nHi";
+        db.store_msg(msg.as_bytes()).await.unwrap();
+        db.pool.execute("DELETE FROM addresses").await.unwrap();
+        db.pool.execute("DELETE FROM parts").await.unwrap();
+        assert_eq!(
+            0,
+            db.fetch_addresses("from", &Filter::default())
+                .await
+                .unwrap()
+                .len()
+        );
+
+        db.reindex().await.unwrap();
+        assert_eq!(
+            1,
+            db.fetch_addresses("from", &Filter::default())
+                .await
+                .unwrap()
+                .len()
+        );
+        assert_eq!(
+            1,
+            db.fetch_parts(&hash::sha256(msg)).await.unwrap().len()
+        );
+    }
+
+    #[tokio::test]
+    async fn reindex_twice() {
+        let cfg = cfg::Db {
+            file: tempfile::tempdir().unwrap().path().join("db"),
+        };
+        let db = Storage::connect(&cfg).await.unwrap();
+        // Unnamed, as well as named, addresses.
+        let msg: &str =
+            "From: a@example.com\nTo: B <b@example.com>\nSubject: hi\n\nHi";
+        db.store_msg(msg.as_bytes()).await.unwrap();
+        let count = || async {
+            let (count,): (i64,) =
+                sqlx::query_as("SELECT count(*) FROM addresses")
+                    .fetch_one(&db.pool)
+                    .await
+                    .unwrap();
+            count
+        };
+        assert_eq!(2, count().await);
+        db.store_msg(msg.as_bytes()).await.unwrap();
+        db.reindex().await.unwrap();
+        db.reindex().await.unwrap();
+        assert_eq!(2, count().await);
+    }
+
+    #[tokio::test]
+    async fn threads_loop() {
+        let cfg = cfg::Db {
+            file: tempfile::tempdir().unwrap().path().join("db"),
+        };
+        let db = Storage::connect(&cfg).await.unwrap();
+        // Each replying to the one before it, and the 1st to the last.
+        let msg = |id: &str, parent: &str| {
+            format!("Message-ID: <{id}@x>\nIn-Reply-To: <{parent}@x>\n\nHi")
+        };
+        for msgs in [
+            vec![("a", "b"), ("b", "a")],
+            vec![("c", "e"), ("d", "c"), ("e", "d")],
+        ] {
+            for (id, parent) in &msgs {
+                db.store_msg(msg(id, parent).as_bytes()).await.unwrap();
+            }
+            let (first, parent) = msgs[0];
+            let thread_id = format!("{parent}@x");
+            let members = db.fetch_thread(&thread_id).await.unwrap();
+            assert_eq!(msgs.len(), members.len());
+            let roots: Vec<&str> = members
+                .iter()
+                .filter(|m| m.parent_hash.is_none())
+                .map(|m| m.msg_hash.as_str())
+                .collect();
+            assert_eq!(vec![hash::sha256(msg(first, parent))], roots);
+        }
+    }
+
+    #[tokio::test]
+    async fn resolve_ambiguous() {
+        let cfg = cfg::Db {
+            file: tempfile::tempdir().unwrap().path().join("db"),
+        };
+        let db = Storage::connect(&cfg).await.unwrap();
+        let copy = "Message-ID: <a@x>\nSubject: Hi\n\nHi";
+        let relayed =
+            "Received: by relay\nMessage-ID: <a@x>\nSubject: Hi\n\nHi";
+        let copy = db.store_msg(copy.as_bytes()).await.unwrap();
+        let relayed = db.store_msg(relayed.as_bytes()).await.unwrap();
+        let error = db.resolve_msg("<a@x>").await.unwrap_err().to_string();
+        assert!(error.starts_with("Ambiguous Message-ID"), "{error}");
+        assert!(error.contains(&copy) && error.contains(&relayed), "{error}");
+        assert_eq!(Some(copy.clone()), db.resolve_msg(&copy).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn threads() {
+        let cfg = cfg::Db {
+            file: tempfile::tempdir().unwrap().path().join("db"),
+        };
+        let db = Storage::connect(&cfg).await.unwrap();
+        let reply = "\
+Message-ID: <b@x>
+In-Reply-To: <a@x>
+References: <a@x>
+Subject: Re: Hi
+
+Yo";
+        let root = "Message-ID: <a@x>\nSubject: Hi\n\nHi";
+        let reply_hash = hash::sha256(reply);
+        let root_hash = hash::sha256(root);
+
+        // Reply first, so that the root has to adopt it:
+        db.store_msg(reply.as_bytes()).await.unwrap();
+        assert_eq!(
+            Some("a@x".to_string()),
+            db.fetch_thread_id(&reply_hash).await.unwrap()
+        );
+        db.store_msg(root.as_bytes()).await.unwrap();
+        assert_eq!(
+            Some(root_hash.clone()),
+            db.resolve_msg("<a@x>").await.unwrap()
+        );
+        assert_eq!(
+            Some(root_hash.clone()),
+            db.resolve_msg(&root_hash).await.unwrap()
+        );
+        assert_eq!(None, db.resolve_msg("nope").await.unwrap());
+        assert_eq!(
+            Some(root_hash.clone()),
+            db.resolve_msg(&root_hash[..8].to_uppercase())
+                .await
+                .unwrap()
+        );
+        assert_eq!(None, db.resolve_msg(&root_hash[..3]).await.unwrap());
+
+        let parents =
+            |members: Vec<ThreadMember>| -> Vec<(String, Option<String>)> {
+                members
+                    .into_iter()
+                    .map(|m| (m.msg_hash, m.parent_hash))
+                    .collect::<std::collections::BTreeSet<_>>()
+                    .into_iter()
+                    .collect()
+            };
+        let expected = parents(vec![
+            ThreadMember {
+                msg_hash: root_hash.clone(),
+                parent_hash: None,
+                date: None,
+                subject: None,
+                from: None,
+            },
+            ThreadMember {
+                msg_hash: reply_hash.clone(),
+                parent_hash: Some(root_hash.clone()),
+                date: None,
+                subject: None,
+                from: None,
+            },
+        ]);
+        assert_eq!(expected, parents(db.fetch_thread("a@x").await.unwrap()));
+
+        db.rebuild_threads().await.unwrap();
+        assert_eq!(expected, parents(db.fetch_thread("a@x").await.unwrap()));
+    }
+
+    #[tokio::test]
+    async fn find() {
+        let cfg = cfg::Db {
+            file: tempfile::tempdir().unwrap().path().join("db"),
+        };
+        let db = Storage::connect(&cfg).await.unwrap();
+        let old = "\
+From: Alice <alice@example.com>
+To: bob@example.com
+Date: Sat, 1 Jun 2019 12:00:00 +0000
+Subject: 100% old
+
+Old news";
+        let new = "\
+From: carol@example.com
+Cc: Bob <bob@example.com>
+Date: Mon, 1 Mar 2021 12:00:00 +0000
+Subject: New
+
+Fresh news";
+        let undated = "From: dave@example.com\nSubject: Whenever\n\nSome day";
+        let old = db.store_msg(old.as_bytes()).await.unwrap();
+        let new = db.store_msg(new.as_bytes()).await.unwrap();
+        let undated = db.store_msg(undated.as_bytes()).await.unwrap();
+        db.store_location(&new, "work", "INBOX", 7).await.unwrap();
+        db.store_pop3_uid("home", "u1", &undated).await.unwrap();
+
+        let find = |q: &str| {
+            let filter = Filter::new(&q.parse().unwrap());
+            let db = &db;
+            async move { db.find(&filter).await.unwrap() }
+        };
+        assert_eq!(vec![old.clone()], find("from:alice").await);
+        assert_eq!(vec![old.clone()], find("from:ALICE@").await);
+        assert_eq!(vec![old.clone(), new.clone()], find("to:bob").await);
+        assert_eq!(vec![new.clone()], find("cc:bob").await);
+        assert_eq!(vec![old.clone()], find("subject:100%").await);
+        assert_eq!(Vec::<String>::new(), find("subject:1_0").await);
+        assert_eq!(vec![new.clone()], find("fresh").await);
+        assert_eq!(vec![new.clone()], find("date:2021").await);
+        assert_eq!(vec![old.clone()], find("before:2020").await);
+        assert_eq!(
+            vec![undated.clone(), old.clone()],
+            find("-date:2021").await
+        );
+        assert_eq!(vec![new.clone()], find("account:work mailbox:IN*").await);
+        assert_eq!(
+            vec![old.clone(), new.clone()],
+            find("from:alice OR account:work").await
+        );
+        assert_eq!(vec![undated.clone()], find("account:home").await);
+        assert_eq!(
+            vec![undated.clone(), new.clone()],
+            find("mailbox:INBOX").await
+        );
+        assert_eq!(Vec::<String>::new(), find("mailbox:Sent").await);
+        assert_eq!(Vec::<String>::new(), find("has:attachment").await);
+        assert_eq!(3, find("size>10").await.len());
+        assert_eq!(Vec::<String>::new(), find("size>1K").await);
+        assert_eq!(3, db.find(&Filter::default()).await.unwrap().len());
+
+        let filter = Filter::new(&"to:bob OR from:dave".parse().unwrap());
+        assert_eq!(3, db.count(&filter).await.unwrap());
+        let page: Vec<(String, Option<String>)> = db
+            .fetch_summaries(&filter, 2, 1)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|s| (s.msg_hash, s.from))
+            .collect();
+        assert_eq!(
+            vec![
+                (old, Some("Alice".to_string())),
+                (undated, Some("dave@example.com".to_string())),
+            ],
+            page
+        );
+    }
+
+    #[tokio::test]
+    async fn runs() {
+        let cfg = cfg::Db {
+            file: tempfile::tempdir().unwrap().path().join("db"),
+        };
+        let db = Storage::connect(&cfg).await.unwrap();
+        let mailbox =
+            |account: &str, mailbox: Option<&str>, error: bool| MailboxRun {
+                account: account.to_string(),
+                mailbox: mailbox.map(str::to_string),
+                messages: 2,
+                bytes: 100,
+                error: error.then(|| "boom".to_string()),
+                duration_ms: 5,
+                skipped: 0,
+                aborted: false,
+            };
+
+        let run_1 = db.start_run().await.unwrap();
+        db.end_run(
+            run_1,
+            &[
+                mailbox("a", Some("INBOX"), false),
+                mailbox("a", Some("Spam"), true),
+                mailbox("b", None, true),
+            ],
+        )
+        .await
+        .unwrap();
+        let run_2 = db.start_run().await.unwrap();
+        db.end_run(
+            run_2,
+            &[
+                mailbox("a", Some("INBOX"), true),
+                mailbox("a", Some("Spam"), true),
+                mailbox("b", Some("INBOX"), false),
+            ],
+        )
+        .await
+        .unwrap();
+        let run_3 = db.start_run().await.unwrap();
+
+        let runs = db.fetch_runs(2).await.unwrap();
+        assert_eq!(
+            vec![(run_3, 0, 0, 0), (run_2, 3, 6, 2)],
+            runs.iter()
+                .map(|r| (r.id, r.mailboxes, r.messages, r.errors))
+                .collect::<Vec<_>>()
+        );
+        assert!(runs[0].ended.is_none());
+        assert!(runs[1].ended.is_some());
+
+        let failing: Vec<(String, Option<String>, u32)> = db
+            .fetch_failing()
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|f| (f.account, f.mailbox, f.failures))
+            .collect();
+        assert_eq!(
+            vec![
+                ("a".to_string(), Some("Spam".to_string()), 2),
+                ("a".to_string(), Some("INBOX".to_string()), 1),
+            ],
+            failing
+        );
+    }
+
+    #[tokio::test]
+    async fn skipped() {
+        let cfg = cfg::Db {
+            file: tempfile::tempdir().unwrap().path().join("db"),
+        };
+        let db = Storage::connect(&cfg).await.unwrap();
+        let skip = |uid, reason: &str, size| SkippedMsg {
+            uid,
+            reason: reason.to_string(),
+            size,
+        };
+        db.store_skipped(
+            "a",
+            "INBOX",
+            &[skip(3, "size", Some(9000)), skip(1, "date", None)],
+        )
+        .await
+        .unwrap();
+        db.store_skipped("a", "Sent", &[skip(1, "date", None)])
+            .await
+            .unwrap();
+        assert_eq!(
+            vec![skip(1, "date", None), skip(3, "size", Some(9000))],
+            db.fetch_skipped("a", "INBOX").await.unwrap()
+        );
+
+        db.delete_skipped("a", "INBOX", 1).await.unwrap();
+        assert_eq!(
+            vec![skip(3, "size", Some(9000))],
+            db.fetch_skipped("a", "INBOX").await.unwrap()
+        );
+        assert_eq!(
+            vec![("INBOX", "size", 1), ("Sent", "date", 1)],
+            db.fetch_skipped_summary()
+                .await
+                .unwrap()
+                .iter()
+                .map(|s| (s.mailbox.as_str(), s.reason.as_str(), s.messages))
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[tokio::test]
+    async fn attrs_and_restored() {
+        let cfg = cfg::Db {
+            file: tempfile::tempdir().unwrap().path().join("db"),
+        };
+        let db = Storage::connect(&cfg).await.unwrap();
+        let msg = "Message-ID: <1@x>\n\nHi";
+        let msg_hash = hash::sha256(msg);
+        db.store_msg(msg.as_bytes()).await.unwrap();
+        assert_eq!(None, db.fetch_attrs(&msg_hash, None).await.unwrap());
+
+        let attrs = MsgAttrs {
+            flags: vec!["\\Seen".to_string(), "$Label1".to_string()],
+            internal_date: Some("17-Jul-1996 02:44:25 -0700".to_string()),
+        };
+        db.store_location(&msg_hash, "a", "INBOX", 3).await.unwrap();
+        db.store_attrs("a", "INBOX", 3, &attrs).await.unwrap();
+        db.store_location(&msg_hash, "a", "Archive", 1)
+            .await
+            .unwrap();
+        db.store_attrs("a", "Archive", 1, &MsgAttrs::default())
+            .await
+            .unwrap();
+        assert_eq!(
+            Some(attrs.clone()),
+            db.fetch_attrs(&msg_hash, Some(("a", "INBOX")))
+                .await
+                .unwrap()
+        );
+        assert_eq!(
+            Some(MsgAttrs::default()),
+            db.fetch_attrs(&msg_hash, Some(("a", "Archive")))
+                .await
+                .unwrap()
+        );
+        // Not fetched from there, nor stored first, but first by name.
+        for mailbox in [None, Some(("b", "INBOX"))] {
+            assert_eq!(
+                Some(MsgAttrs::default()),
+                db.fetch_attrs(&msg_hash, mailbox).await.unwrap()
+            );
+        }
+        let located = |mailbox: &str, attrs| Located {
+            mailbox: mailbox.to_string(),
+            msg_hash: msg_hash.clone(),
+            message_id: Some("1@x".to_string()),
+            attrs,
+        };
+        assert_eq!(
+            vec![
+                located("Archive", MsgAttrs::default()),
+                located("INBOX", attrs)
+            ],
+            db.fetch_located("a").await.unwrap()
+        );
+
+        assert!(db.fetch_restored("b", "INBOX").await.unwrap().is_empty());
+        db.store_restored(&msg_hash, "b", "INBOX").await.unwrap();
+        db.store_restored(&msg_hash, "b", "INBOX").await.unwrap();
+        assert_eq!(
+            HashSet::from([msg_hash.clone()]),
+            db.fetch_restored("b", "INBOX").await.unwrap()
+        );
+        assert!(db.fetch_restored("b", "Sent").await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn partial() {
+        let cfg = cfg::Db {
+            file: tempfile::tempdir().unwrap().path().join("db"),
+        };
+        let db = Storage::connect(&cfg).await.unwrap();
+        assert!(db.fetch_partial("a", "INBOX", 7).await.unwrap().is_empty());
+        db.store_partial("a", "INBOX", 7, 3, b"def").await.unwrap();
+        db.store_partial("a", "INBOX", 7, 0, b"abc").await.unwrap();
+        db.store_partial("a", "INBOX", 8, 0, b"xyz").await.unwrap();
+        assert_eq!(
+            b"abcdef",
+            &db.fetch_partial("a", "INBOX", 7).await.unwrap()[..]
+        );
+        db.delete_partial("a", "INBOX", 7).await.unwrap();
+        assert!(db.fetch_partial("a", "INBOX", 7).await.unwrap().is_empty());
+        assert_eq!(
+            b"xyz",
+            &db.fetch_partial("a", "INBOX", 8).await.unwrap()[..]
+        );
+    }
 }
@@ -31,6 +31,31 @@ pub fn write_as_gz<P: AsRef<Path>, D: AsRef<[u8]>>(
     Ok(())
 }
 
+/// Make a name safe to use as a single path component: no separators,
+/// no control or otherwise troublesome characters, no leading dots (so no
+/// hidden files and no "..") and not longer than most file systems allow.
+#[must_use]
+pub fn safe_name(name: &str) -> String {
+    const MAX_LEN: usize = 255;
+    let name: String = name
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect();
+    let name = name.trim().trim_start_matches('.');
+    let mut safe = String::new();
+    for c in name.chars() {
+        if safe.len() + c.len_utf8() > MAX_LEN {
+            break;
+        }
+        safe.push(c);
+    }
+    safe
+}
+
 // Because I want to be able to rename:
 //     foo.txt --> foo.txt.gz
 // instead of just:
@@ -49,3 +74,21 @@ fn path_extension_append<P: AsRef<Path>>(path: P, ext: &str) -> PathBuf {
     };
     path.with_file_name(name)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn t_safe_name() {
+        assert_eq!("deck.pdf", safe_name("deck.pdf"));
+        assert_eq!("_etc_passwd", safe_name("../etc/passwd"));
+        assert_eq!("_etc_passwd", safe_name("/etc/passwd"));
+        assert_eq!("a_b_c", safe_name("a\\b:c"));
+        assert_eq!("bashrc", safe_name(".bashrc"));
+        assert_eq!("", safe_name(".."));
+        assert_eq!("x_y", safe_name("x\ny"));
+        assert_eq!(255, safe_name(&"a".repeat(1000)).len());
+        assert!(safe_name(&"é".repeat(200)).len() <= 255);
+    }
+}
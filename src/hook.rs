@@ -0,0 +1,181 @@
+//! User commands run after `fetch`, `import` and `export`, for
+//! notifications, re-running aggregate queries and the like.
+//!
+//! Each hook gets the command's JSON result (same as `--output json`) on
+//! stdin and a few `MA_*` environment variables. A hook failing, or taking
+//! too long, is logged, but does not fail the command that triggered it.
+
+use std::{process::Stdio, time::Duration};
+
+use tokio::{io::AsyncWriteExt, process::Command};
+
+const DEFAULT_TIMEOUT_SECS: u64 = 60;
+
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug, Default)]
+pub struct Hooks {
+    /// After each `fetch`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub fetch: Vec<Hook>,
+
+    /// After each `import`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub import: Vec<Hook>,
+
+    /// After each `export`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub export: Vec<Hook>,
+
+    /// After each message stored by `fetch`. Run serially, as messages come
+    /// in, so should be quick.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub message: Vec<Hook>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
+pub struct Hook {
+    /// Program followed by its arguments. Not interpreted by a shell, so
+    /// use `["sh", "-c", "..."]` for pipes and such.
+    pub command: Vec<String>,
+
+    /// Seconds after which the hook is killed.
+    #[serde(default = "default_timeout_secs")]
+    pub timeout_secs: u64,
+}
+
+fn default_timeout_secs() -> u64 {
+    DEFAULT_TIMEOUT_SECS
+}
+
+/// Run each hook in turn, logging, rather than returning, their failures.
+pub async fn run_all<T: serde::Serialize>(
+    hooks: &[Hook],
+    event: &str,
+    input: &T,
+    env: &[(String, String)],
+) {
+    if hooks.is_empty() {
+        return;
+    }
+    let input = match serde_json::to_vec(input) {
+        Ok(input) => input,
+        Err(error) => {
+            tracing::error!(
+                ?event,
+                ?error,
+                "Failed to serialize hook input."
+            );
+            return;
+        }
+    };
+    for hook in hooks {
+        match run(hook, event, &input, env).await {
+            Ok(status) if status.success() => {
+                tracing::info!(?event, ?hook, ?status, "Hook succeeded.");
+            }
+            Ok(status) => {
+                tracing::error!(?event, ?hook, ?status, "Hook failed.");
+            }
+            Err(error) => {
+                tracing::error!(?event, ?hook, ?error, "Hook failed.");
+            }
+        }
+    }
+}
+
+async fn run(
+    hook: &Hook,
+    event: &str,
+    input: &[u8],
+    env: &[(String, String)],
+) -> anyhow::Result<std::process::ExitStatus> {
+    let Some((program, args)) = hook.command.split_first() else {
+        anyhow::bail!("Empty command.");
+    };
+    let mut child = Command::new(program)
+        .args(args)
+        .env("MA_EVENT", event)
+        .envs(env.iter().map(|(k, v)| (k, v)))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .kill_on_drop(true)
+        .spawn()?;
+    let timeout = Duration::from_secs(hook.timeout_secs);
+    let wait = async {
+        if let Some(mut stdin) = child.stdin.take() {
+            // The hook is free to ignore its input and exit early.
+            if let Err(error) = stdin.write_all(input).await {
+                tracing::debug!(?error, "Hook did not read its input.");
+            }
+        }
+        child.wait().await
+    };
+    match tokio::time::timeout(timeout, wait).await {
+        Ok(status) => Ok(status?),
+        Err(_) => {
+            child.kill().await?;
+            anyhow::bail!("Timed out after {timeout:?}.")
+        }
+    }
+}
+
+/// Environment variable name component from an arbitrary name, like that of
+/// an account: "work.example" -> "WORK_EXAMPLE".
+#[must_use]
+pub fn env_name(name: &str) -> String {
+    name.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() {
+                c.to_ascii_uppercase()
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hook(script: &str, timeout_secs: u64) -> Hook {
+        Hook {
+            command: vec!["sh".into(), "-c".into(), script.into()],
+            timeout_secs,
+        }
+    }
+
+    #[tokio::test]
+    async fn t_run() {
+        let dir = tempfile::tempdir().unwrap();
+        let out = dir.path().join("out");
+        let script =
+            format!("cat > {out:?}; echo $MA_EVENT $MA_X >> {out:?}");
+        let env = [("MA_X".to_string(), "42".to_string())];
+        let status =
+            run(&hook(&script, 5), "fetch", b"{}", &env).await.unwrap();
+        assert!(status.success());
+        assert_eq!("{}fetch 42\n", std::fs::read_to_string(&out).unwrap());
+
+        let status =
+            run(&hook("exit 7", 5), "fetch", b"", &[]).await.unwrap();
+        assert_eq!(Some(7), status.code());
+
+        assert!(run(&hook("sleep 10", 0), "fetch", b"", &[]).await.is_err());
+        assert!(run(
+            &Hook {
+                command: vec![],
+                timeout_secs: 1
+            },
+            "",
+            b"",
+            &[]
+        )
+        .await
+        .is_err());
+    }
+
+    #[test]
+    fn t_env_name() {
+        assert_eq!("WORK_EXAMPLE_1", env_name("work.example-1"));
+    }
+}
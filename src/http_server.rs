@@ -0,0 +1,766 @@
+//! Local web UI for browsing and searching the archive, without needing to
+//! know SQL. Unencrypted and unauthenticated, so meant to listen on
+//! localhost only.
+//!
+//! Pages:
+//! - / -- messages, newest first, optionally filtered by a query
+//! - /msg/<hash, hash prefix or Message-ID> -- a message
+//! - /msg/<hash>/raw -- a message as stored
+//! - /attachment/<hash> -- attachment download
+//! - /analyze/routes -- the routes graph, as SVG when Graphviz is installed
+//! - /analyze/senders -- the senders tables
+
+use std::{fmt::Write, net::SocketAddr, process::Stdio, sync::Arc};
+
+use anyhow::anyhow;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::{TcpListener, TcpStream},
+};
+
+use crate::{
+    cmd::analyze::{routes, senders},
+    data::{Attachment, Filter, MsgSummary, Part, Storage},
+    query::Query,
+};
+
+const PAGE_SIZE: u32 = 50;
+const MAX_HEAD_LEN: usize = 64 * 1024;
+const DEFAULT_NOISE_THRESHOLD: usize = 10;
+
+/// No scripts at all and nothing loaded from elsewhere, so that messages
+/// can neither run code nor phone home.
+const CONTENT_SECURITY_POLICY: &str = "default-src 'none'; \
+    style-src 'unsafe-inline'; \
+    img-src 'self' data:; \
+    form-action 'self'";
+
+const STYLE: &str = "\
+body { font-family: sans-serif; margin: 1em 2em; }
+nav a { margin-right: 1em; }
+table { border-collapse: collapse; }
+td, th { padding: 0.2em 0.6em; text-align: left; vertical-align: top; }
+tr:nth-child(even) { background: #f4f4f4; }
+pre { white-space: pre-wrap; }
+.error { color: #b00; }
+.headers th { color: #666; }
+.body { border-top: 1px solid #ccc; margin-top: 1em; padding-top: 1em; }
+";
+
+pub async fn serve(db: Arc<Storage>, addr: SocketAddr) -> anyhow::Result<()> {
+    let listener = TcpListener::bind(addr).await?;
+    tracing::info!(addr = ?listener.local_addr()?, "Listening.");
+    serve_on(db, listener).await
+}
+
+pub async fn serve_on(
+    db: Arc<Storage>,
+    listener: TcpListener,
+) -> anyhow::Result<()> {
+    loop {
+        let (stream, peer) = listener.accept().await?;
+        tracing::debug!(?peer, "Accepted.");
+        let db = Arc::clone(&db);
+        tokio::spawn(async move {
+            if let Err(error) = connection(&db, stream).await {
+                tracing::error!(?peer, ?error, "Connection failed.");
+            }
+        });
+    }
+}
+
+/// One request per connection, which is plenty for a single local user.
+async fn connection(db: &Storage, stream: TcpStream) -> anyhow::Result<()> {
+    let (reader, mut writer) = stream.into_split();
+    let mut reader = BufReader::new(reader);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line).await?;
+    let mut head_len = request_line.len();
+    loop {
+        let mut line = String::new();
+        let n = reader.read_line(&mut line).await?;
+        head_len += n;
+        if n == 0 || line.trim_end().is_empty() {
+            break;
+        }
+        if head_len > MAX_HEAD_LEN {
+            return Err(anyhow!("Request head too long"));
+        }
+    }
+    let mut fields = request_line.split_whitespace();
+    let method = fields.next().unwrap_or_default();
+    let target = fields.next().unwrap_or_default();
+    tracing::info!(?method, ?target, "Request.");
+    let response = match method {
+        "GET" | "HEAD" => route(db, target).await.unwrap_or_else(|error| {
+            tracing::error!(?target, ?error, "Request failed.");
+            Response::page(
+                500,
+                "Error",
+                &format!("<p class=error>{}</p>", esc(&error.to_string())),
+            )
+        }),
+        _ => Response::text(405, "Method not allowed"),
+    };
+    writer.write_all(&response.head()).await?;
+    if method != "HEAD" {
+        writer.write_all(&response.body).await?;
+    }
+    writer.shutdown().await?;
+    Ok(())
+}
+
+struct Response {
+    status: u16,
+    content_type: String,
+    disposition: Option<String>,
+    body: Vec<u8>,
+}
+
+impl Response {
+    fn page(status: u16, title: &str, content: &str) -> Self {
+        let title = esc(title);
+        let body = format!(
+            "<!DOCTYPE html>\n\
+            <html><head><meta charset=utf-8><title>{title} - ma</title>\
+            <style>{STYLE}</style></head><body>\
+            <nav><a href=/>Messages</a>\
+            <a href=/analyze/routes>Routes</a>\
+            <a href=/analyze/senders>Senders</a></nav>\
+            <h1>{title}</h1>{content}</body></html>\n"
+        );
+        Self {
+            status,
+            content_type: "text/html; charset=utf-8".to_string(),
+            disposition: None,
+            body: body.into_bytes(),
+        }
+    }
+
+    fn text(status: u16, text: &str) -> Self {
+        Self {
+            status,
+            content_type: "text/plain; charset=utf-8".to_string(),
+            disposition: None,
+            body: text.as_bytes().to_vec(),
+        }
+    }
+
+    fn not_found() -> Self {
+        Self::page(404, "Not found", "")
+    }
+
+    fn head(&self) -> Vec<u8> {
+        let reason = match self.status {
+            200 => "OK",
+            400 => "Bad Request",
+            404 => "Not Found",
+            405 => "Method Not Allowed",
+            _ => "Internal Server Error",
+        };
+        let mut head = format!(
+            "HTTP/1.1 {} {reason}\r\n\
+            Content-Type: {}\r\n\
+            Content-Length: {}\r\n\
+            Content-Security-Policy: {CONTENT_SECURITY_POLICY}\r\n\
+            X-Content-Type-Options: nosniff\r\n\
+            Connection: close\r\n",
+            self.status,
+            self.content_type,
+            self.body.len(),
+        );
+        if let Some(disposition) = &self.disposition {
+            head.push_str(&format!("Content-Disposition: {disposition}\r\n"));
+        }
+        head.push_str("\r\n");
+        head.into_bytes()
+    }
+}
+
+async fn route(db: &Storage, target: &str) -> anyhow::Result<Response> {
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    let params = Params::parse(query);
+    let segments: Vec<String> = path
+        .split('/')
+        .filter(|s| !s.is_empty())
+        .map(decode_path)
+        .collect();
+    let segments: Vec<&str> = segments.iter().map(String::as_str).collect();
+    let response = match &segments[..] {
+        [] => messages(db, &params).await?,
+        ["msg", spec] => message(db, spec, &params).await?,
+        ["msg", hash, "raw"] => match db.fetch_msg(hash).await? {
+            None => Response::not_found(),
+            Some(msg) => Response {
+                status: 200,
+                content_type: "text/plain; charset=utf-8".to_string(),
+                disposition: None,
+                body: msg.raw,
+            },
+        },
+        ["attachment", hash] => attachment(db, hash, &params).await?,
+        ["analyze", "routes"] => analyze_routes(db, &params).await?,
+        ["analyze", "senders"] => analyze_senders(db, &params).await?,
+        _ => Response::not_found(),
+    };
+    Ok(response)
+}
+
+/// The query param, parsed into a filter, or the page explaining why not.
+fn filter(params: &Params, path: &str) -> Result<Filter, Response> {
+    let q = params.get("q").unwrap_or_default();
+    let form = query_form(path, q);
+    if q.trim().is_empty() {
+        return Ok(Filter::default());
+    }
+    match q.parse::<Query>() {
+        Ok(query) => Ok(Filter::new(&query)),
+        Err(error) => Err(Response::page(
+            400,
+            "Invalid query",
+            &format!("{form}<p class=error>{}</p>", esc(&error.to_string())),
+        )),
+    }
+}
+
+fn query_form(path: &str, q: &str) -> String {
+    format!(
+        "<form action={path}>\
+        <input name=q size=60 value=\"{}\" \
+        placeholder=\"from:alice date:2021 -has:attachment\">\
+        <button>Search</button></form>",
+        esc(q)
+    )
+}
+
+async fn messages(db: &Storage, params: &Params) -> anyhow::Result<Response> {
+    let filter = match filter(params, "/") {
+        Ok(filter) => filter,
+        Err(response) => return Ok(response),
+    };
+    let q = params.get("q").unwrap_or_default();
+    let page: u32 =
+        params.get("page").and_then(|p| p.parse().ok()).unwrap_or(0);
+    let total = db.count(&filter).await?;
+    let summaries = db
+        .fetch_summaries(&filter, PAGE_SIZE, page.saturating_mul(PAGE_SIZE))
+        .await?;
+    let mut html = query_form("/", q);
+    let first = u64::from(page) * u64::from(PAGE_SIZE);
+    write!(
+        html,
+        "<p>{} - {} of {total}</p>",
+        (first + 1).min(total),
+        (first + summaries.len() as u64).min(total),
+    )?;
+    html.push_str(
+        "<table><tr><th>Date</th><th>From</th><th>Subject</th><th></th></tr>",
+    );
+    for MsgSummary {
+        msg_hash,
+        date,
+        subject,
+        from,
+        has_attachments,
+    } in summaries
+    {
+        write!(
+            html,
+            "<tr><td>{}</td><td>{}</td><td><a href=/msg/{msg_hash}>{}</a></td>\
+            <td>{}</td></tr>",
+            date.map(format_date).unwrap_or_default(),
+            esc(from.as_deref().unwrap_or_default()),
+            esc(subject.as_deref().unwrap_or("(no subject)")),
+            if has_attachments.unwrap_or(false) {
+                "&#128206;"
+            } else {
+                ""
+            },
+        )?;
+    }
+    html.push_str("</table><p>");
+    let q = encode(q);
+    if page > 0 {
+        write!(html, "<a href=\"/?q={q}&page={}\">Newer</a> ", page - 1)?;
+    }
+    if first + u64::from(PAGE_SIZE) < total {
+        write!(html, "<a href=\"/?q={q}&page={}\">Older</a>", page + 1)?;
+    }
+    html.push_str("</p>");
+    Ok(Response::page(200, "Messages", &html))
+}
+
+async fn message(
+    db: &Storage,
+    spec: &str,
+    params: &Params,
+) -> anyhow::Result<Response> {
+    let Some(hash) = db.resolve_msg(spec).await? else {
+        return Ok(Response::not_found());
+    };
+    let Some(msg) = db.fetch_msg(&hash).await? else {
+        return Ok(Response::not_found());
+    };
+    let parsed = mail_parser::MessageParser::default()
+        .parse(&msg.raw[..])
+        .unwrap_or_default();
+    let subject = parsed.subject().unwrap_or("(no subject)").to_string();
+    let mut html = String::from("<table class=headers>");
+    let mut header = |name: &str, value: &str| {
+        write!(html, "<tr><th>{name}</th><td>{}</td></tr>", esc(value))
+    };
+    if let Some(date) = parsed.date() {
+        header("Date", &date.to_rfc822())?;
+    }
+    for name in ["From", "To", "Cc", "Reply-To"] {
+        if let Some(value) = parsed.header(name).and_then(|v| v.as_address())
+        {
+            header(name, &crate::cmd::show::format_address(value))?;
+        }
+    }
+    if let Some(message_id) = parsed.message_id() {
+        header("Message-ID", &format!("<{message_id}>"))?;
+    }
+    header("Hash", &hash)?;
+    html.push_str("</table>");
+
+    let parts = db.fetch_parts(&hash).await?;
+    let html_body = parts
+        .iter()
+        .find(|p| p.content_type == "text/html" && p.text.is_some())
+        .and_then(|p| p.text.as_deref());
+    let text_body = parts
+        .iter()
+        .find(|p| p.content_type == "text/plain" && p.text.is_some())
+        .and_then(|p| p.text.as_deref())
+        .or_else(|| parts.iter().find_map(|p| p.text_plain.as_deref()));
+    let view = params.get("view").unwrap_or("html");
+    write!(html, "<p><a href=/msg/{hash}/raw>Raw</a>")?;
+    if html_body.is_some() && text_body.is_some() {
+        let other = if view == "text" { "html" } else { "text" };
+        write!(
+            html,
+            " <a href=\"/msg/{hash}?view={other}\">{}</a>",
+            other.to_uppercase()
+        )?;
+    }
+    html.push_str("</p>");
+
+    let attachments = db.fetch_msg_attachments(&hash).await?;
+    if !attachments.is_empty() {
+        html.push_str("<ul>");
+        for Attachment {
+            hash,
+            mime_type,
+            size,
+            name,
+            msgs: _,
+        } in attachments
+        {
+            let name = name.unwrap_or_else(|| hash.clone());
+            write!(
+                html,
+                "<li><a href=\"/attachment/{hash}?name={}\">{}</a> \
+                ({}, {size} bytes)</li>",
+                encode(&name),
+                esc(&name),
+                esc(&mime_type),
+            )?;
+        }
+        html.push_str("</ul>");
+    }
+
+    html.push_str("<div class=body>");
+    match (html_body, text_body) {
+        (Some(body), _) if view != "text" => {
+            html.push_str(&sanitize(body));
+        }
+        (_, Some(body)) => {
+            write!(html, "<pre>{}</pre>", esc(body.trim_end()))?;
+        }
+        (_, None) => {
+            html.push_str(&parts_summary(&parts));
+        }
+    }
+    html.push_str("</div>");
+    Ok(Response::page(200, &subject, &html))
+}
+
+fn parts_summary(parts: &[Part]) -> String {
+    let mut html = String::from("<p>No text. Parts:</p><ul>");
+    for part in parts {
+        html.push_str(&format!(
+            "<li>{} {} ({} bytes)</li>",
+            part.idx,
+            esc(&part.content_type),
+            part.size
+        ));
+    }
+    html.push_str("</ul>");
+    html
+}
+
+/// Message HTML without anything that could run or load on its own. Remote
+/// images are left in, but the content security policy keeps them from
+/// loading.
+fn sanitize(html: &str) -> String {
+    ammonia::Builder::default()
+        .link_rel(Some("noopener noreferrer nofollow"))
+        .clean(html)
+        .to_string()
+}
+
+async fn attachment(
+    db: &Storage,
+    hash: &str,
+    params: &Params,
+) -> anyhow::Result<Response> {
+    let Some(data) = db.fetch_attachment_data(hash).await? else {
+        return Ok(Response::not_found());
+    };
+    let name = crate::file::safe_name(params.get("name").unwrap_or(hash));
+    Ok(Response {
+        status: 200,
+        // Always a download, never rendered, since an attachment could just as
+        // well be a page full of scripts.
+        content_type: "application/octet-stream".to_string(),
+        disposition: Some(format!("attachment; filename=\"{name}\"")),
+        body: data,
+    })
+}
+
+async fn analyze_routes(
+    db: &Storage,
+    params: &Params,
+) -> anyhow::Result<Response> {
+    let path = "/analyze/routes";
+    let filter = match filter(params, path) {
+        Ok(filter) => filter,
+        Err(response) => return Ok(response),
+    };
+    let q = params.get("q").unwrap_or_default();
+    let reduce = params.get("reduce").is_some();
+    let dot = routes::graph(db, reduce, &filter).await?.dot();
+    let mut html = format!(
+        "<form action={path}>\
+        <input name=q size=60 value=\"{}\">\
+        <label><input type=checkbox name=reduce{}> Reduce</label>\
+        <button>Trace</button></form>",
+        esc(q),
+        if reduce { " checked" } else { "" },
+    );
+    match render_svg(&dot).await {
+        Ok(svg) => html.push_str(&svg),
+        Err(error) => {
+            tracing::warn!(?error, "Failed to render SVG.");
+            write!(
+                html,
+                "<p class=error>Graphviz's \"dot\" is needed to draw this \
+                ({}), so here is the source:</p><pre>{}</pre>",
+                esc(&error.to_string()),
+                esc(&dot)
+            )?;
+        }
+    }
+    Ok(Response::page(200, "Routes", &html))
+}
+
+/// Render a DOT graph with the locally installed Graphviz.
+async fn render_svg(dot: &str) -> anyhow::Result<String> {
+    let mut child = tokio::process::Command::new("dot")
+        .arg("-Tsvg")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    let mut stdin = child.stdin.take().ok_or_else(|| anyhow!("No stdin"))?;
+    stdin.write_all(dot.as_bytes()).await?;
+    drop(stdin);
+    let output = child.wait_with_output().await?;
+    if !output.status.success() {
+        return Err(anyhow!(
+            "dot failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    let svg = String::from_utf8(output.stdout)?;
+    // Drop the XML prolog and doctype, which don't belong inside HTML.
+    let svg = svg.find("<svg").map_or(svg.as_str(), |i| &svg[i..]);
+    Ok(svg.to_string())
+}
+
+async fn analyze_senders(
+    db: &Storage,
+    params: &Params,
+) -> anyhow::Result<Response> {
+    let path = "/analyze/senders";
+    let filter = match filter(params, path) {
+        Ok(filter) => filter,
+        Err(response) => return Ok(response),
+    };
+    let q = params.get("q").unwrap_or_default();
+    let noise_threshold = params
+        .get("noise_threshold")
+        .and_then(|n| n.parse().ok())
+        .unwrap_or(DEFAULT_NOISE_THRESHOLD);
+    let senders::Report { by_name, by_addr } =
+        senders::report(db, noise_threshold, &filter).await?;
+    let mut html = format!(
+        "<form action={path}>\
+        <input name=q size=60 value=\"{}\">\
+        <label>Noise threshold \
+        <input name=noise_threshold size=4 value={noise_threshold}></label>\
+        <button>Analyze</button></form>",
+        esc(q),
+    );
+    for (title, entries, key_kind, value_kind) in [
+        ("By name", by_name, "Name", "Address"),
+        ("By address", by_addr, "Address", "Name"),
+    ] {
+        write!(
+            html,
+            "<h2>{title}</h2><table><tr><th>{key_kind}</th>\
+            <th>{value_kind}</th><th>Count</th>\
+            <th>Other {}s</th></tr>",
+            key_kind.to_lowercase()
+        )?;
+        for senders::Entry { key, links } in entries {
+            for senders::Link {
+                value,
+                count,
+                others,
+            } in links
+            {
+                let others: Vec<String> = others
+                    .iter()
+                    .map(|(other, count)| format!("{} ({count})", esc(other)))
+                    .collect();
+                write!(
+                    html,
+                    "<tr><td>{}</td><td>{}</td><td>{count}</td>\
+                    <td>{}</td></tr>",
+                    esc(&key),
+                    esc(&value),
+                    others.join("<br>")
+                )?;
+            }
+        }
+        html.push_str("</table>");
+    }
+    Ok(Response::page(200, "Senders", &html))
+}
+
+fn format_date(timestamp: i64) -> String {
+    // "YYYY-MM-DDTHH:MM:SSZ" -> "YYYY-MM-DD HH:MM"
+    let date = mail_parser::DateTime::from_timestamp(timestamp).to_rfc3339();
+    date.get(..16).unwrap_or(&date).replacen('T', " ", 1)
+}
+
+fn esc(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Percent-encode for use in a URL query value.
+fn encode(s: &str) -> String {
+    let mut encoded = String::with_capacity(s.len());
+    for b in s.bytes() {
+        if b.is_ascii_alphanumeric() || matches!(b, b'-' | b'_' | b'.' | b'~')
+        {
+            encoded.push(char::from(b));
+        } else {
+            encoded.push_str(&format!("%{b:02X}"));
+        }
+    }
+    encoded
+}
+
+/// Percent-decode, with "+" as space, as submitted by forms.
+fn decode(s: &str) -> String {
+    percent_decode(s, true)
+}
+
+/// Percent-decode a path segment, in which "+" is just that, as in the
+/// Message-IDs of many mailers.
+fn decode_path(s: &str) -> String {
+    percent_decode(s, false)
+}
+
+fn percent_decode(s: &str, plus_as_space: bool) -> String {
+    let bytes = s.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' if plus_as_space => decoded.push(b' '),
+            b'%' => {
+                let hex = bytes
+                    .get(i + 1..i + 3)
+                    .and_then(|hex| std::str::from_utf8(hex).ok())
+                    .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+                match hex {
+                    Some(b) => {
+                        decoded.push(b);
+                        i += 2;
+                    }
+                    None => decoded.push(b'%'),
+                }
+            }
+            b => decoded.push(b),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&decoded).to_string()
+}
+
+struct Params(Vec<(String, String)>);
+
+impl Params {
+    fn parse(query: &str) -> Self {
+        Self(
+            query
+                .split('&')
+                .filter(|pair| !pair.is_empty())
+                .map(|pair| {
+                    let (k, v) = pair.split_once('=').unwrap_or((pair, ""));
+                    (decode(k), decode(v))
+                })
+                .collect(),
+        )
+    }
+
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::AsyncReadExt;
+
+    use super::*;
+    use crate::cfg;
+
+    #[test]
+    fn t_encoding() {
+        assert_eq!("a b+c&d", decode("a+b%2Bc%26d"));
+        assert_eq!("100%", decode("100%"));
+        assert_eq!("a+b c", decode_path("a+b%20c"));
+        assert_eq!("from%3Aal%20ice", encode("from:al ice"));
+        assert_eq!("from:al ice", decode(&encode("from:al ice")));
+        assert_eq!("&lt;b&gt; &amp; &quot;", esc("<b> & \""));
+    }
+
+    #[test]
+    fn t_sanitize() {
+        let clean = sanitize(
+            "<p onclick=\"x()\">Hi</p><script>alert(1)</script>\
+            <a href=\"javascript:x()\">y</a>",
+        );
+        assert!(!clean.contains("script"), "{clean}");
+        assert!(!clean.contains("onclick"), "{clean}");
+        assert!(clean.contains("<p>Hi</p>"), "{clean}");
+    }
+
+    async fn get(addr: SocketAddr, target: &str) -> (u16, String) {
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream
+            .write_all(
+                format!("GET {target} HTTP/1.1\r\nHost: x\r\n\r\n")
+                    .as_bytes(),
+            )
+            .await
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        let status = response
+            .split_whitespace()
+            .nth(1)
+            .and_then(|s| s.parse().ok())
+            .unwrap();
+        (status, response)
+    }
+
+    #[tokio::test]
+    async fn t_serve() {
+        let cfg = cfg::Db {
+            file: tempfile::tempdir().unwrap().path().join("db"),
+        };
+        let db = Storage::connect(&cfg).await.unwrap();
+        let raw = "\
+From: Alice <alice@example.com>
+To: bob@example.com
+Date: Tue, 2 Mar 2021 09:30:00 -0500
+Subject: Hello <there>
+Message-ID: <hello@example.com>
+Content-Type: multipart/mixed; boundary=\"b\"
+
+--b
+Content-Type: text/html
+
+<p>Hi <b>Bob</b></p><script>alert(1)</script>
+--b
+Content-Type: application/octet-stream
+Content-Disposition: attachment; filename=\"data.bin\"
+
+AAAA
+--b--
+";
+        let hash = db.store_msg(raw.as_bytes()).await.unwrap();
+        db.store_msg(b"Subject: Other\n\nBye\n").await.unwrap();
+        let plus = "Message-ID: <a+b@example.com>\nSubject: Plus\n\nHi\n";
+        let plus_hash = db.store_msg(plus.as_bytes()).await.unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(serve_on(Arc::new(db), listener));
+
+        let (status, page) = get(addr, "/").await;
+        assert_eq!(200, status);
+        assert!(page.contains("1 - 3 of 3"), "{page}");
+        assert!(page.contains("Hello &lt;there&gt;"), "{page}");
+
+        let (status, page) = get(addr, "/?q=from%3Aalice").await;
+        assert_eq!(200, status);
+        assert!(page.contains("1 - 1 of 1"), "{page}");
+
+        let (status, _) = get(addr, "/?q=nope%3Ax").await;
+        assert_eq!(400, status);
+
+        let (status, page) =
+            get(addr, "/msg/%3Chello%40example.com%3E").await;
+        assert_eq!(200, status);
+        assert!(page.contains("Hi <b>Bob</b>"), "{page}");
+        assert!(!page.contains("alert"), "{page}");
+        assert!(page.contains("data.bin"), "{page}");
+
+        let (status, page) = get(addr, &format!("/msg/{hash}/raw")).await;
+        assert_eq!(200, status);
+        assert!(page.ends_with(raw), "{page}");
+
+        let (status, _) = get(addr, "/msg/ffffffff").await;
+        assert_eq!(404, status);
+
+        let (status, page) = get(addr, "/msg/a+b@example.com").await;
+        assert_eq!(200, status);
+        assert!(page.contains(&plus_hash), "{page}");
+
+        let (status, _) = get(addr, "/analyze/routes").await;
+        assert_eq!(200, status);
+        let (status, _) = get(addr, "/analyze/senders?q=from%3Aalice").await;
+        assert_eq!(200, status);
+    }
+}
@@ -1,12 +1,24 @@
-use std::{result, sync::Arc};
+use std::{fmt, result, sync::Arc, time::Duration};
 
+use async_imap::imap_proto::{
+    AttributeValue, MailboxDatum, MessageSection, RequestId, Response,
+    SectionPath, Status,
+};
 use async_imap::types::Capability;
-use futures::{Stream, StreamExt};
-use tokio::net::TcpStream;
+use futures::{future::BoxFuture, Stream, StreamExt, TryStreamExt};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::TcpStream,
+};
 use tokio_rustls::client::TlsStream;
 
 use crate::cfg;
 
+pub(crate) mod literal;
+#[cfg(test)]
+pub(crate) mod stand_in;
+pub(crate) mod throttle;
+
 #[derive(thiserror::Error, Debug)]
 pub enum Error {
     #[error("Bye")]
@@ -43,52 +55,213 @@ pub enum Error {
     Io(#[from] std::io::Error),
 }
 
+impl Error {
+    /// Whether the server refused the command for us going too fast, in
+    /// which case it is worth retrying after a pause.
+    #[must_use]
+    pub fn is_throttled(&self) -> bool {
+        match self {
+            Self::Imap(error) => throttle::is_throttled(error),
+            _ => false,
+        }
+    }
+}
+
 pub type Result<T> = result::Result<T, Error>;
 
-type ImapSession = async_imap::Session<TlsStream<TcpStream>>;
+/// A connection to the server: TLS to a provider or, in tests, plain TCP to
+/// a local stand-in.
+pub(crate) trait Io:
+    AsyncRead + AsyncWrite + Unpin + Send + fmt::Debug
+{
+}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send + fmt::Debug> Io for T {}
+
+type ImapSession = async_imap::Session<Box<dyn Io>>;
 type Meta = async_imap::types::Mailbox;
 
 pub struct Msg {
     pub uid: u32,
-    pub ord: u32,
     pub raw: Vec<u8>,
 }
 
+/// What the server says about a message besides its content.
+#[derive(Debug, Default, Clone)]
+pub struct Attrs {
+    pub uid: u32,
+    pub size: Option<u32>,
+    pub flags: Vec<String>,
+
+    /// As IMAP has it: "17-Jul-1996 02:44:25 -0700".
+    pub internal_date: Option<String>,
+}
+
 pub struct Session {
     session: ImapSession,
+
+    /// So that consecutive commands on the same mailbox don't re-examine it.
+    examined: Option<String>,
+
+    /// Shared with the account's other sessions.
+    pacer: Arc<throttle::Pacer>,
+
+    /// For sending literals, as of APPEND.
+    literals: literal::Queue,
+
+    /// Before the first retry of a throttled command.
+    backoff_pause: Duration,
+}
+
+/// A calendar day, as IMAP searches by, parsed from "YYYY-MM-DD".
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Day {
+    year: u16,
+    month: u8,
+    day: u8,
+}
+
+impl std::str::FromStr for Day {
+    type Err = String;
+
+    fn from_str(s: &str) -> result::Result<Self, Self::Err> {
+        let invalid = || format!("Not a YYYY-MM-DD date: {s:?}");
+        let mut fields = s.splitn(3, '-');
+        let mut field = || fields.next().ok_or_else(invalid);
+        let year: u16 = field()?.parse().map_err(|_| invalid())?;
+        let month: u8 = field()?.parse().map_err(|_| invalid())?;
+        let day: u8 = field()?.parse().map_err(|_| invalid())?;
+        if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+            return Err(invalid());
+        }
+        Ok(Self { year, month, day })
+    }
+}
+
+impl std::fmt::Display for Day {
+    /// As IMAP wants it: "1-Feb-2024".
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        const MONTHS: [&str; 12] = [
+            "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep",
+            "Oct", "Nov", "Dec",
+        ];
+        let month = MONTHS[usize::from(self.month - 1)];
+        write!(f, "{}-{}-{}", self.day, month, self.year)
+    }
 }
 
 impl Session {
     pub async fn close(&mut self) -> Result<()> {
-        self.session.close().await?;
-        Ok(())
+        self.command(&(), |session, ()| Box::pin(session.close()))
+            .await
     }
 
-    pub async fn new(account: &cfg::ImapAccount) -> Result<Self> {
-        let mut session = connect(account).await?;
+    /// Keeping, along with the account's other sessions given the same
+    /// limits, to the account's limits.
+    pub async fn new(
+        account: &cfg::ImapAccount,
+        limits: &throttle::Limits,
+    ) -> Result<Self> {
+        let stream = connect(account).await?;
+        Self::login(stream, account, limits).await
+    }
+
+    /// Log in over an already established connection.
+    pub(crate) async fn login(
+        stream: Box<dyn Io>,
+        account: &cfg::ImapAccount,
+        limits: &throttle::Limits,
+    ) -> Result<Self> {
+        let stream: Box<dyn Io> = match limits.budget() {
+            None => stream,
+            Some(budget) => {
+                Box::new(throttle::Throttled::new(stream, budget))
+            }
+        };
+        let literals = literal::Queue::default();
+        let stream: Box<dyn Io> =
+            Box::new(literal::Literals::new(stream, literals.clone()));
+        let client = async_imap::Client::new(stream);
+        let mut session = client
+            .login(&account.user, &account.pass)
+            .await
+            .map_err(|(e, _)| e)?;
+        tracing::debug!("Logged-in IMAP.");
         let capabilities = session.capabilities().await?;
         tracing::debug!(
             ?account,
             capabilities = ?capabilities.iter().collect::<Vec<&Capability>>(),
             "New IMAP session."
         );
-        Ok(Self { session })
+        Ok(Self {
+            session,
+            examined: None,
+            pacer: limits.pacer(),
+            literals,
+            backoff_pause: throttle::PAUSE,
+        })
+    }
+
+    /// Run the command, after pausing as much as the limit on the rate of
+    /// commands requires, and retry it, after longer and longer pauses, for
+    /// as long as the server says we're going too fast.
+    ///
+    /// The command's argument is passed through, rather than captured, for
+    /// the command's future to borrow it.
+    async fn command<A, T, F>(&mut self, arg: &A, mut command: F) -> Result<T>
+    where
+        A: ?Sized,
+        F: for<'a> FnMut(
+            &'a mut ImapSession,
+            &'a A,
+        )
+            -> BoxFuture<'a, async_imap::error::Result<T>>,
+    {
+        let mut backoff = self.backoff();
+        loop {
+            self.pacer.pace().await;
+            match command(&mut self.session, arg).await.map_err(Error::from) {
+                Err(error) if backoff.retry(&error).await => {}
+                result => return result,
+            }
+        }
+    }
+
+    /// For retrying throttled commands which the session can't retry by
+    /// itself, like those whose responses are streamed.
+    pub(crate) fn backoff(&self) -> throttle::Backoff {
+        throttle::Backoff::new(self.backoff_pause)
     }
 
     pub async fn examine(&mut self, mailbox: &str) -> Result<Meta> {
-        let meta = self.session.examine(mailbox).await.map_err(|error| {
-            tracing::error!(?error, "Failed to examine mailbox.");
-            error
-        })?;
+        self.examined = None;
+        let meta = self
+            .command(mailbox, |session, mailbox| {
+                Box::pin(session.examine(mailbox))
+            })
+            .await
+            .map_err(|error| {
+                tracing::error!(?error, "Failed to examine mailbox.");
+                error
+            })?;
         tracing::debug!(?mailbox, exists = meta.exists, "Switched mailbox.");
+        self.examined = Some(mailbox.to_string());
         Ok(meta)
     }
 
+    async fn examine_if_not_yet(&mut self, mailbox: &str) -> Result<()> {
+        if self.examined.as_deref() != Some(mailbox) {
+            self.examine(mailbox).await?;
+        }
+        Ok(())
+    }
+
     pub async fn list_mailboxes(
         &mut self,
     ) -> Result<impl Stream<Item = String> + '_> {
         let reference_name = None; // None is equivalent to Some("")
         let mailbox_pattern = Some("*");
+        self.pacer.pace().await;
         let names =
             self.session.list(reference_name, mailbox_pattern).await?;
         let names = names.filter_map(|result| async {
@@ -101,80 +274,545 @@ impl Session {
         Ok(names.boxed())
     }
 
-    #[tracing::instrument(skip_all)]
-    pub async fn fetch_msgs_all<'a>(
-        &'a mut self,
-        mailbox: &'a str,
-    ) -> Result<(Meta, impl Stream<Item = Msg> + 'a)> {
-        self.fetch_msgs(mailbox, None).await
+    /// The hierarchy delimiter separating the levels of mailbox names, as
+    /// in "Work/Old", or None if the names are flat.
+    pub async fn delimiter(&mut self) -> Result<Option<String>> {
+        // An empty pattern asks for just the delimiter, by RFC 3501.
+        let names = self
+            .command(&(), |session, ()| {
+                Box::pin(async move {
+                    session
+                        .list(None, None)
+                        .await?
+                        .try_collect::<Vec<_>>()
+                        .await
+                })
+            })
+            .await?;
+        Ok(names
+            .first()
+            .and_then(|name| name.delimiter())
+            .map(str::to_string))
     }
 
-    #[tracing::instrument(skip_all)]
-    pub async fn fetch_msgs_from<'a>(
-        &'a mut self,
-        mailbox: &'a str,
-        initial_uid: u32,
-    ) -> Result<(Meta, impl Stream<Item = Msg> + 'a)> {
-        self.fetch_msgs(mailbox, Some(initial_uid)).await
+    /// UIDs, in order, of the messages with UIDs from the given one onwards.
+    pub async fn uids_from(
+        &mut self,
+        mailbox: &str,
+        first_uid: u32,
+    ) -> Result<Vec<u32>> {
+        if first_uid == 0 {
+            return Err(Error::UidIsZero);
+        }
+        self.examine_if_not_yet(mailbox).await?;
+        let query = format!("UID {first_uid}:*");
+        let uids = self
+            .command(query.as_str(), |session, query| {
+                Box::pin(uid_search(session, query))
+            })
+            .await?;
+        // "n:*" always matches the last message, even if its UID is below n.
+        let mut uids: Vec<u32> =
+            uids.into_iter().filter(|uid| *uid >= first_uid).collect();
+        uids.sort_unstable();
+        Ok(uids)
+    }
+
+    /// Those of the given UIDs whose messages arrived (as per their
+    /// INTERNALDATE) on or after `since` and before `before`.
+    pub async fn uids_within(
+        &mut self,
+        mailbox: &str,
+        uids: &[u32],
+        since: Option<Day>,
+        before: Option<Day>,
+    ) -> Result<Vec<u32>> {
+        if uids.is_empty() {
+            return Ok(Vec::new());
+        }
+        self.examine_if_not_yet(mailbox).await?;
+        let mut query = format!("UID {}", uid_set(uids));
+        if let Some(since) = since {
+            query.push_str(&format!(" SINCE {since}"));
+        }
+        if let Some(before) = before {
+            query.push_str(&format!(" BEFORE {before}"));
+        }
+        let mut uids: Vec<u32> = self
+            .command(query.as_str(), |session, query| {
+                Box::pin(uid_search(session, query))
+            })
+            .await?
+            .into_iter()
+            .collect();
+        uids.sort_unstable();
+        Ok(uids)
     }
 
+    /// Sizes (RFC822.SIZE) of the messages with the given UIDs.
+    pub async fn sizes(
+        &mut self,
+        mailbox: &str,
+        uids: &[u32],
+    ) -> Result<Vec<(u32, u32)>> {
+        if uids.is_empty() {
+            return Ok(Vec::new());
+        }
+        self.examine_if_not_yet(mailbox).await?;
+        let set = uid_set(uids);
+        let fetches = self
+            .command(set.as_str(), |session, set| {
+                Box::pin(async move {
+                    uid_fetch(session, set, "(UID RFC822.SIZE)")
+                        .await?
+                        .try_collect::<Vec<Fetched>>()
+                        .await
+                })
+            })
+            .await?;
+        let mut sizes = Vec::new();
+        for fetch in fetches {
+            let uid = fetch.uid.ok_or(Error::FetchInvalidMissingUid)?;
+            if let Some(size) = fetch.size {
+                sizes.push((uid, size));
+            }
+        }
+        Ok(sizes)
+    }
+
+    /// Sizes, flags and INTERNALDATEs of the messages with the given UIDs.
+    pub async fn attrs(
+        &mut self,
+        mailbox: &str,
+        uids: &[u32],
+    ) -> Result<Vec<Attrs>> {
+        if uids.is_empty() {
+            return Ok(Vec::new());
+        }
+        self.examine_if_not_yet(mailbox).await?;
+        let set = uid_set(uids);
+        let fetches = self
+            .command(set.as_str(), |session, set| {
+                Box::pin(async move {
+                    let query = "(UID RFC822.SIZE FLAGS INTERNALDATE)";
+                    uid_fetch(session, set, query)
+                        .await?
+                        .try_collect::<Vec<Fetched>>()
+                        .await
+                })
+            })
+            .await?;
+        fetches
+            .into_iter()
+            .map(|fetch| {
+                Ok(Attrs {
+                    uid: fetch.uid.ok_or(Error::FetchInvalidMissingUid)?,
+                    size: fetch.size,
+                    flags: fetch.flags,
+                    internal_date: fetch.internal_date,
+                })
+            })
+            .collect()
+    }
+
+    /// Messages with exactly the given UIDs. Fetched with BODY.PEEK[], rather
+    /// than RFC822 or BODY[], which would mark them as read.
+    ///
+    /// A failure ends the stream with an error. If the server was throttling
+    /// us, the caller can pause and ask again for those not yet received.
     #[tracing::instrument(skip_all)]
     pub async fn fetch_msgs<'a>(
         &'a mut self,
         mailbox: &'a str,
-        beginning_with: Option<u32>,
-    ) -> Result<(Meta, impl Stream<Item = Msg> + 'a)> {
-        if let Some(0) = beginning_with {
-            return Err(Error::UidIsZero);
+        uids: &[u32],
+    ) -> Result<impl Stream<Item = Result<Msg>> + 'a> {
+        if uids.is_empty() {
+            return Ok(futures::stream::empty().boxed());
         }
-        tracing::debug!(?mailbox, ?beginning_with, "Fetching messages.");
-        let meta: Meta = self.examine(mailbox).await?;
-        let lo = beginning_with.unwrap_or(1);
-        let hi = "*";
-        let range = format!("{}:{}", lo, hi);
-        let fetches = self.session.fetch(&range, "(RFC822 UID)").await?;
-        let msgs = fetches.filter_map(move |result| async {
-            let mailbox = mailbox.to_string();
-            if let Err(error) = &result {
-                // TODO Should we terminate the stream or keep going/trying?
-                tracing::error!(?mailbox, ?error, "Failed fetch.");
+        tracing::debug!(?mailbox, uids = uids.len(), "Fetching messages.");
+        self.examine_if_not_yet(mailbox).await?;
+        self.pacer.pace().await;
+        let fetches =
+            uid_fetch(&mut self.session, &uid_set(uids), "(UID BODY.PEEK[])")
+                .await?;
+        let msgs = fetches.filter_map(move |result| async move {
+            let fetch = match result {
+                Ok(fetch) => fetch,
+                Err(error) => {
+                    tracing::error!(?mailbox, ?error, "Failed fetch.");
+                    return Some(Err(Error::from(error)));
+                }
+            };
+            match fetch {
+                Fetched {
+                    uid: Some(uid),
+                    body: Some(raw),
+                    ..
+                } => Some(Ok(Msg { uid, raw })),
+                _ => {
+                    // Like an unsolicited flags update.
+                    tracing::debug!(?mailbox, "Fetched no UID or body.");
+                    None
+                }
             }
-            result.ok().and_then(|f| {
-                f.uid.and_then(move |uid| {
-                    f.body().map(|body| Msg {
-                        uid,
-                        ord: f.message,
-                        raw: body.to_vec(),
-                    })
+        });
+        Ok(msgs.boxed())
+    }
+
+    /// Up to `len` bytes of the message, from `offset`. Fewer, or none,
+    /// past its end.
+    pub async fn fetch_part(
+        &mut self,
+        mailbox: &str,
+        uid: u32,
+        offset: usize,
+        len: u32,
+    ) -> Result<Vec<u8>> {
+        self.examine_if_not_yet(mailbox).await?;
+        let args = (
+            uid.to_string(),
+            format!("(UID BODY.PEEK[]<{offset}.{len}>)"),
+        );
+        let fetches = self
+            .command(&args, |session, (set, query)| {
+                Box::pin(async move {
+                    uid_fetch(session, set, query)
+                        .await?
+                        .try_collect::<Vec<Fetched>>()
+                        .await
                 })
             })
-        });
-        Ok((meta, msgs.boxed()))
+            .await?;
+        fetches
+            .into_iter()
+            .find(|fetch| fetch.uid == Some(uid))
+            .and_then(|fetch| fetch.body)
+            .ok_or(Error::FetchInvalidMissingBody { uid })
+    }
+
+    pub async fn create(&mut self, mailbox: &str) -> Result<()> {
+        self.command(mailbox, |session, mailbox| {
+            Box::pin(session.create(mailbox))
+        })
+        .await
+    }
+
+    /// Whether the mailbox has a message with the given Message-ID (without
+    /// the angle brackets). IDs which can't go in a search without a
+    /// charset, like those with 8-bit characters, are never found.
+    pub async fn has_message_id(
+        &mut self,
+        mailbox: &str,
+        message_id: &str,
+    ) -> Result<bool> {
+        if !message_id
+            .bytes()
+            .all(|b| b.is_ascii() && !b.is_ascii_control())
+        {
+            return Ok(false);
+        }
+        self.examine_if_not_yet(mailbox).await?;
+        let query = format!("HEADER Message-ID {}", quote(message_id));
+        let uids = self
+            .command(query.as_str(), |session, query| {
+                Box::pin(uid_search(session, query))
+            })
+            .await?;
+        Ok(!uids.is_empty())
+    }
+
+    /// Message-IDs (without the angle brackets) of all the mailbox's
+    /// messages, or None for those without one, as of examining it afresh.
+    pub async fn message_ids(
+        &mut self,
+        mailbox: &str,
+    ) -> Result<Vec<Option<String>>> {
+        let meta = self.examine(mailbox).await?;
+        if meta.exists == 0 {
+            return Ok(Vec::new());
+        }
+        let fetches = self
+            .command(&(), |session, ()| {
+                Box::pin(async move {
+                    let query = "(UID BODY.PEEK[HEADER.FIELDS (MESSAGE-ID)])";
+                    uid_fetch(session, "1:*", query)
+                        .await?
+                        .try_collect::<Vec<Fetched>>()
+                        .await
+                })
+            })
+            .await?;
+        let ids = fetches
+            .into_iter()
+            .filter(|fetch| fetch.uid.is_some())
+            .map(|fetch| {
+                let header = fetch.header.unwrap_or_default();
+                mail_parser::MessageParser::default()
+                    .parse_headers(&header)
+                    .and_then(|msg| msg.message_id().map(str::to_string))
+            })
+            .collect();
+        Ok(ids)
+    }
+
+    /// Upload the message to the mailbox, with the given flags, other than
+    /// `\Recent`, which only the server can set, and INTERNALDATE, if any.
+    pub async fn append(
+        &mut self,
+        mailbox: &str,
+        raw: &[u8],
+        flags: &[String],
+        internal_date: Option<&str>,
+    ) -> Result<()> {
+        let flags: Vec<&str> = flags
+            .iter()
+            .map(String::as_str)
+            .filter(|flag| !flag.eq_ignore_ascii_case("\\Recent"))
+            .collect();
+        let mut command =
+            format!("APPEND {} ({})", quote(mailbox), flags.join(" "));
+        if let Some(date) = internal_date {
+            command.push_str(&format!(" {}", quote(date)));
+        }
+        command.push_str(&format!(" {{{}}}", raw.len()));
+        let args = (command, raw, self.literals.clone());
+        self.command(&args, |session, (command, raw, literals)| {
+            Box::pin(append(session, command, raw, literals))
+        })
+        .await
+    }
+}
+
+/// UID SEARCH, which, unlike async-imap's, fails on a NO or BAD response,
+/// rather than returning no UIDs, which would pass for no new messages.
+async fn uid_search(
+    session: &mut ImapSession,
+    query: &str,
+) -> async_imap::error::Result<Vec<u32>> {
+    let id = session.run_command(format!("UID SEARCH {query}")).await?;
+    let mut uids = Vec::new();
+    while let Some(response) = session.read_response().await {
+        let response = response?;
+        match response.parsed() {
+            Response::MailboxData(MailboxDatum::Search(found)) => {
+                uids.extend(found);
+            }
+            parsed => {
+                if let Some(done) = done(parsed, &id) {
+                    return done.map(|()| uids);
+                }
+            }
+        }
+    }
+    Err(async_imap::error::Error::ConnectionLost)
+}
+
+/// The command is APPEND up to, and including, the literal's length, which
+/// is sent once the server asks for it.
+async fn append(
+    session: &mut ImapSession,
+    command: &str,
+    raw: &[u8],
+    literals: &literal::Queue,
+) -> async_imap::error::Result<()> {
+    use async_imap::error::Error;
+    let id = session.run_command(command).await?;
+    loop {
+        let response = session
+            .read_response()
+            .await
+            .ok_or(Error::ConnectionLost)??;
+        match response.parsed() {
+            Response::Continue { .. } => break,
+            parsed => match done(parsed, &id) {
+                None => {}
+                Some(Ok(())) => {
+                    return Err(Error::Bad(format!("{parsed:?}")));
+                }
+                Some(Err(error)) => return Err(error),
+            },
+        }
+    }
+    literals.push(raw);
+    // The literal goes out ahead of this command's line end.
+    session.run_command_untagged("").await?;
+    while let Some(response) = session.read_response().await {
+        if let Some(done) = done(response?.parsed(), &id) {
+            return done;
+        }
+    }
+    Err(Error::ConnectionLost)
+}
+
+/// Of a message, in a FETCH response.
+#[derive(Debug, Default)]
+struct Fetched {
+    uid: Option<u32>,
+    size: Option<u32>,
+    flags: Vec<String>,
+    internal_date: Option<String>,
+
+    /// Of BODY[HEADER], or of only some of its fields.
+    header: Option<Vec<u8>>,
+
+    body: Option<Vec<u8>>,
+}
+
+/// UID FETCH, whose responses, unlike async-imap's, end with an error on a
+/// NO or BAD response, rather than as if everything was fetched. Ends after
+/// an error, having read up to the end of the command's responses, if the
+/// connection allows, for the session to be ready for the next one.
+async fn uid_fetch<'a>(
+    session: &'a mut ImapSession,
+    set: &str,
+    query: &str,
+) -> async_imap::error::Result<
+    impl Stream<Item = async_imap::error::Result<Fetched>> + Send + 'a,
+> {
+    let id = session
+        .run_command(format!("UID FETCH {set} {query}"))
+        .await?;
+    let fetches = futures::stream::unfold(Some(session), move |session| {
+        let id = id.clone();
+        async move {
+            let session = session?;
+            while let Some(response) = session.read_response().await {
+                let response = match response {
+                    Ok(response) => response,
+                    Err(error) => return Some((Err(error.into()), None)),
+                };
+                match response.parsed() {
+                    Response::Fetch(_, attrs) => {
+                        let fetched = fetched(attrs);
+                        return Some((Ok(fetched), Some(session)));
+                    }
+                    parsed => match done(parsed, &id) {
+                        None => {}
+                        Some(Ok(())) => return None,
+                        Some(Err(error)) => return Some((Err(error), None)),
+                    },
+                }
+            }
+            Some((Err(async_imap::error::Error::ConnectionLost), None))
+        }
+    });
+    Ok(fetches)
+}
+
+fn fetched(attrs: &[AttributeValue]) -> Fetched {
+    let mut fetched = Fetched::default();
+    for attr in attrs {
+        match attr {
+            AttributeValue::Uid(uid) => fetched.uid = Some(*uid),
+            AttributeValue::Rfc822Size(size) => fetched.size = Some(*size),
+            AttributeValue::Flags(flags) => {
+                fetched.flags =
+                    flags.iter().map(|flag| flag.to_string()).collect();
+            }
+            AttributeValue::InternalDate(date) => {
+                fetched.internal_date = Some(date.to_string());
+            }
+            AttributeValue::BodySection {
+                section: None,
+                data: Some(body),
+                ..
+            }
+            | AttributeValue::Rfc822(Some(body)) => {
+                fetched.body = Some(body.to_vec());
+            }
+            AttributeValue::BodySection {
+                section: Some(SectionPath::Full(MessageSection::Header)),
+                data: Some(header),
+                ..
+            } => {
+                fetched.header = Some(header.to_vec());
+            }
+            _ => {}
+        }
+    }
+    fetched
+}
+
+/// The outcome of the command, if the response is its completion.
+fn done(
+    response: &Response,
+    id: &RequestId,
+) -> Option<async_imap::error::Result<()>> {
+    use async_imap::error::Error;
+    let Response::Done {
+        tag,
+        status,
+        code,
+        information,
+    } = response
+    else {
+        return None;
+    };
+    if tag != id {
+        return None;
+    }
+    let info = format!("code: {code:?}, info: {information:?}");
+    Some(match status {
+        Status::Ok => Ok(()),
+        Status::No => Err(Error::No(info)),
+        _ => Err(Error::Bad(info)),
+    })
+}
+
+/// As an IMAP quoted string.
+fn quote(s: &str) -> String {
+    format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// IMAP sequence set of the UIDs, with runs collapsed: "1:3,7,9:10".
+fn uid_set(uids: &[u32]) -> String {
+    let mut uids = uids.to_vec();
+    uids.sort_unstable();
+    uids.dedup();
+    let mut ranges: Vec<(u32, u32)> = Vec::new();
+    for uid in uids {
+        match ranges.last_mut() {
+            Some((_, hi)) if *hi + 1 == uid => *hi = uid,
+            _ => ranges.push((uid, uid)),
+        }
     }
+    ranges
+        .into_iter()
+        .map(|(lo, hi)| {
+            if lo == hi {
+                lo.to_string()
+            } else {
+                format!("{lo}:{hi}")
+            }
+        })
+        .collect::<Vec<String>>()
+        .join(",")
 }
 
 #[tracing::instrument]
-async fn connect(account: &cfg::ImapAccount) -> Result<ImapSession> {
+async fn connect(account: &cfg::ImapAccount) -> Result<Box<dyn Io>> {
     let cfg::ImapAccount {
         addr,
         port,
-        user,
-        pass,
+        user: _,
+        pass: _,
         ignore_mailboxes: _,
+        max_message_size: _,
+        connections: _,
+        chunk_size: _,
+        part_size: _,
+        max_bytes_per_sec: _,
+        max_commands_per_sec: _,
     } = account;
     tracing::debug!("Connecting ...");
     let tcp = TcpStream::connect((addr.as_str(), *port)).await?;
     tracing::debug!("Connected TCP.");
     let tls = tls_stream(addr, tcp).await?;
     tracing::debug!("Connected TLS.");
-    let client = async_imap::Client::new(tls);
-    let session: ImapSession =
-        client.login(user, pass).await.map_err(|(e, _)| e)?;
-    tracing::debug!("Logged-in IMAP.");
-    Ok(session)
+    Ok(Box::new(tls))
 }
 
-async fn tls_stream(
+pub(crate) async fn tls_stream(
     domain: &str,
     tcp_stream: TcpStream,
 ) -> std::io::Result<TlsStream<TcpStream>> {
@@ -198,3 +836,112 @@ async fn tls_stream(
     let tls_stream = connector.connect(domain, tcp_stream).await?;
     Ok(tls_stream)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn t_uid_set() {
+        assert_eq!("", uid_set(&[]));
+        assert_eq!("5", uid_set(&[5]));
+        assert_eq!("1:3,7,9:10", uid_set(&[10, 1, 2, 3, 7, 9, 2]));
+    }
+
+    #[test]
+    fn t_day() {
+        let day: Day = "2024-02-01".parse().unwrap();
+        assert_eq!("1-Feb-2024", day.to_string());
+        assert!("2024-13-01".parse::<Day>().is_err());
+        assert!("2024-02".parse::<Day>().is_err());
+        assert!("yesterday".parse::<Day>().is_err());
+    }
+
+    #[tokio::test]
+    async fn t_fetch_leaves_flags() {
+        let msgs: Vec<stand_in::Msg> = (1..=3)
+            .map(|uid| {
+                let raw = format!("Subject: {uid}\r\n\r\nHello {uid}\r\n");
+                stand_in::Msg::new(uid * 10, raw.as_bytes())
+            })
+            .collect();
+        let server = stand_in::StandIn::start(
+            [("INBOX".to_string(), msgs.clone())].into(),
+        )
+        .await;
+        let mut session = server.connect().await;
+
+        let uids = session.uids_from("INBOX", 15).await.unwrap();
+        assert_eq!(vec![20, 30], uids);
+        let fetched: Vec<(u32, Vec<u8>)> = session
+            .fetch_msgs("INBOX", &uids)
+            .await
+            .unwrap()
+            .map(|msg| msg.map(|msg| (msg.uid, msg.raw)).unwrap())
+            .collect()
+            .await;
+        let expected: Vec<(u32, Vec<u8>)> = msgs[1..]
+            .iter()
+            .map(|msg| (msg.uid, msg.raw.clone()))
+            .collect();
+        assert_eq!(expected, fetched);
+        let part = session.fetch_part("INBOX", 10, 2, 5).await.unwrap();
+        assert_eq!(&msgs[0].raw[2..7], &part[..]);
+
+        {
+            let state = server.state();
+            assert!(state.mailboxes["INBOX"].iter().all(|msg| !msg.seen()));
+            let fetches: Vec<&String> = state
+                .commands
+                .iter()
+                .filter(|command| command.contains("FETCH"))
+                .collect();
+            assert!(!fetches.is_empty());
+            assert!(fetches
+                .iter()
+                .all(|command| command.starts_with("UID ")));
+        }
+
+        // The stand-in does mark as read, like real servers, when not asked
+        // to peek.
+        let fetches =
+            session.session.uid_fetch("10", "BODY[]").await.unwrap();
+        assert_eq!(1, fetches.count().await);
+        assert!(server.state().mailboxes["INBOX"][0].seen());
+    }
+
+    #[tokio::test]
+    async fn t_throttled() {
+        let msgs = vec![stand_in::Msg::new(1, b"Subject: 1\r\n\r\n1\r\n")];
+        let server =
+            stand_in::StandIn::start([("INBOX".to_string(), msgs)].into())
+                .await;
+        let mut session = server.connect().await;
+
+        server.state().throttle = 2;
+        assert_eq!(vec![1], session.uids_from("INBOX", 1).await.unwrap());
+
+        // Streamed, so left for the caller to retry, hence the error, rather
+        // than the server's refusal passing for no messages.
+        server.state().throttle = 1;
+        let results: Vec<Result<Msg>> = session
+            .fetch_msgs("INBOX", &[1])
+            .await
+            .unwrap()
+            .collect()
+            .await;
+        assert!(matches!(&results[..], [Err(error)] if error.is_throttled()));
+
+        server.state().throttle = 100;
+        let error = session.uids_from("INBOX", 1).await.unwrap_err();
+        assert!(error.is_throttled());
+        let searches = server
+            .state()
+            .commands
+            .iter()
+            .filter(|command| command.starts_with("UID SEARCH"))
+            .count();
+        // 1 + 2 retries, then 1 + 5 retries.
+        assert_eq!(9, searches);
+    }
+}
@@ -0,0 +1,107 @@
+//! Sending literals, which async-imap has no way to do, other than by its own
+//! APPEND, which can't set flags or dates, and which takes any response for
+//! success.
+//!
+//! The connection is wrapped in [`Literals`], which writes whatever bytes are
+//! queued, through the queue's other handle, ahead of the next thing
+//! async-imap writes, like the line end after an empty untagged command.
+
+use std::{
+    io,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{ready, Context, Poll},
+};
+
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+/// Bytes to be written ahead of the next write.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Queue(Arc<Mutex<Vec<u8>>>);
+
+impl Queue {
+    pub fn push(&self, bytes: &[u8]) {
+        self.0.lock().unwrap().extend_from_slice(bytes);
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct Literals<S> {
+    inner: S,
+    queue: Queue,
+}
+
+impl<S> Literals<S> {
+    pub fn new(inner: S, queue: Queue) -> Self {
+        Self { inner, queue }
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for Literals<S> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_read(cx, buf)
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for Literals<S> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = &mut *self;
+        loop {
+            let mut queued = this.queue.0.lock().unwrap();
+            if queued.is_empty() {
+                break;
+            }
+            let written =
+                ready!(Pin::new(&mut this.inner).poll_write(cx, &queued))?;
+            if written == 0 {
+                return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+            }
+            queued.drain(..written);
+        }
+        Pin::new(&mut this.inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn t_literals() {
+        let (client, mut server) = tokio::io::duplex(1 << 16);
+        let queue = Queue::default();
+        let mut client = Literals::new(client, queue.clone());
+        client.write_all(b"A1 APPEND x {5}\r\n").await.unwrap();
+        queue.push(b"Hello");
+        client.write_all(b"\r\n").await.unwrap();
+        client.shutdown().await.unwrap();
+        drop(client);
+        let mut written = Vec::new();
+        server.read_to_end(&mut written).await.unwrap();
+        assert_eq!(b"A1 APPEND x {5}\r\nHello\r\n", &written[..]);
+    }
+}
@@ -0,0 +1,453 @@
+//! In-memory IMAP server standing in for a provider's in tests.
+//!
+//! Unlike the archive's own server, it keeps per-message flags and, as real
+//! servers do, sets `\Seen` on fetching a body without `.PEEK`. It only knows
+//! as much of IMAP as `imap::Session` uses, and records each command it gets,
+//! for tests to check what was asked of it.
+
+use std::{
+    collections::BTreeMap,
+    net::SocketAddr,
+    sync::{Arc, Mutex, MutexGuard},
+    time::Duration,
+};
+
+use anyhow::anyhow;
+use tokio::{
+    io::{AsyncWriteExt, BufReader},
+    net::{TcpListener, TcpStream},
+};
+
+use crate::{
+    cfg,
+    imap::{throttle::Limits, Session},
+    imap_server::{read_command, SeqSet, Token},
+};
+
+#[derive(Debug, Clone)]
+pub(crate) struct Msg {
+    pub uid: u32,
+    pub flags: Vec<String>,
+    pub internal_date: String,
+    pub raw: Vec<u8>,
+}
+
+impl Msg {
+    pub fn new(uid: u32, raw: &[u8]) -> Self {
+        Self {
+            uid,
+            flags: Vec::new(),
+            internal_date: "01-Jan-2024 00:00:00 +0000".to_string(),
+            raw: raw.to_vec(),
+        }
+    }
+
+    pub fn seen(&self) -> bool {
+        self.flags.iter().any(|flag| flag == "\\Seen")
+    }
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct State {
+    /// Messages, in UID order, by mailbox name.
+    pub mailboxes: BTreeMap<String, Vec<Msg>>,
+
+    /// Each command received, as "COMMAND ARGS..." with the tag dropped.
+    pub commands: Vec<String>,
+
+    /// How many of the next UID commands to refuse with `NO [THROTTLED]`.
+    pub throttle: u32,
+
+    /// Hierarchy delimiter of the mailbox names, if not flat.
+    pub delimiter: Option<char>,
+}
+
+#[derive(Clone)]
+pub(crate) struct StandIn {
+    pub addr: SocketAddr,
+    state: Arc<Mutex<State>>,
+}
+
+impl StandIn {
+    pub async fn start(mailboxes: BTreeMap<String, Vec<Msg>>) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let state = Arc::new(Mutex::new(State {
+            mailboxes,
+            delimiter: Some('/'),
+            ..State::default()
+        }));
+        let stand_in = Self { addr, state };
+        let server = stand_in.clone();
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                let server = server.clone();
+                tokio::spawn(async move {
+                    if let Err(error) = server.session(stream).await {
+                        tracing::error!(?error, "Stand-in session failed.");
+                    }
+                });
+            }
+        });
+        stand_in
+    }
+
+    pub fn state(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap()
+    }
+
+    pub fn account(&self) -> cfg::ImapAccount {
+        cfg::ImapAccount {
+            addr: self.addr.ip().to_string(),
+            port: self.addr.port(),
+            user: "user".to_string(),
+            pass: "pass".to_string(),
+            ..cfg::ImapAccount::default()
+        }
+    }
+
+    /// A logged-in client session, quick to retry throttled commands.
+    pub async fn connect(&self) -> Session {
+        let tcp = TcpStream::connect(self.addr).await.unwrap();
+        let account = self.account();
+        let limits = Limits::new(&account).unwrap();
+        let mut session = Session::login(Box::new(tcp), &account, &limits)
+            .await
+            .unwrap();
+        session.backoff_pause = Duration::from_millis(1);
+        session
+    }
+
+    async fn session(&self, stream: TcpStream) -> anyhow::Result<()> {
+        let (reader, mut writer) = stream.into_split();
+        let mut reader = BufReader::new(reader);
+        let mut selected: Option<String> = None;
+        writer.write_all(b"* OK Stand-in ready\r\n").await?;
+        while let Some(tokens) =
+            read_command(&mut reader, &mut writer).await?
+        {
+            let Some((Token::Atom(tag), args)) = tokens.split_first() else {
+                writer.write_all(b"* BAD Missing tag\r\n").await?;
+                continue;
+            };
+            let words: Vec<String> = args
+                .iter()
+                .map(|token| match token {
+                    Token::Atom(s) | Token::Str(s) => s.clone(),
+                    Token::Open => "(".to_string(),
+                    Token::Close => ")".to_string(),
+                })
+                .collect();
+            self.state().commands.push(words.join(" "));
+            let mut out = Vec::new();
+            let status = match self.dispatch(&words, &mut selected, &mut out)
+            {
+                Ok(status) => status,
+                Err(error) => format!("BAD {error}"),
+            };
+            out.extend_from_slice(format!("{tag} {status}\r\n").as_bytes());
+            writer.write_all(&out).await?;
+            writer.flush().await?;
+            if words.first().map(String::as_str) == Some("LOGOUT") {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    fn dispatch(
+        &self,
+        words: &[String],
+        selected: &mut Option<String>,
+        out: &mut Vec<u8>,
+    ) -> anyhow::Result<String> {
+        let command = words.first().map(|w| w.to_uppercase());
+        let args = words.get(1..).unwrap_or_default();
+        let mut state = self.state();
+        let status = match command.as_deref() {
+            Some("CAPABILITY") => {
+                out.extend_from_slice(b"* CAPABILITY IMAP4rev1\r\n");
+                "OK CAPABILITY completed".to_string()
+            }
+            Some("LOGIN") => "OK LOGIN completed".to_string(),
+            Some("NOOP") => "OK NOOP completed".to_string(),
+            Some("LOGOUT") => {
+                out.extend_from_slice(b"* BYE\r\n");
+                "OK LOGOUT completed".to_string()
+            }
+            Some("LIST") => {
+                let delimiter = state
+                    .delimiter
+                    .map_or("NIL".to_string(), |d| format!("\"{d}\""));
+                if args.get(1).is_some_and(String::is_empty) {
+                    // Just the delimiter, for an empty pattern.
+                    out.extend_from_slice(
+                        format!("* LIST (\\Noselect) {delimiter} \"\"\r\n")
+                            .as_bytes(),
+                    );
+                } else {
+                    for name in state.mailboxes.keys() {
+                        out.extend_from_slice(
+                            format!("* LIST () {delimiter} \"{name}\"\r\n")
+                                .as_bytes(),
+                        );
+                    }
+                }
+                "OK LIST completed".to_string()
+            }
+            Some(command @ ("SELECT" | "EXAMINE")) => {
+                let name = args.first().ok_or_else(|| anyhow!("No name"))?;
+                let Some(msgs) = state.mailboxes.get(name) else {
+                    return Ok("NO No such mailbox".to_string());
+                };
+                let uid_next = msgs.last().map_or(1, |msg| msg.uid + 1);
+                out.extend_from_slice(
+                    format!(
+                        "* FLAGS (\\Seen)\r\n* {} EXISTS\r\n* 0 RECENT\r\n\
+                         * OK [UIDVALIDITY 1] UIDs valid\r\n\
+                         * OK [UIDNEXT {uid_next}] Predicted next UID\r\n",
+                        msgs.len()
+                    )
+                    .as_bytes(),
+                );
+                *selected = Some(name.clone());
+                format!("OK {command} completed")
+            }
+            Some("CREATE") => {
+                let name = args.first().ok_or_else(|| anyhow!("No name"))?;
+                if state.mailboxes.contains_key(name) {
+                    return Ok(
+                        "NO [ALREADYEXISTS] Mailbox exists".to_string()
+                    );
+                }
+                state.mailboxes.insert(name.clone(), Vec::new());
+                "OK CREATE completed".to_string()
+            }
+            Some("APPEND") => {
+                let (name, rest) =
+                    args.split_first().ok_or_else(|| anyhow!("No name"))?;
+                let Some(msgs) = state.mailboxes.get_mut(name) else {
+                    return Ok("NO [TRYCREATE] No such mailbox".to_string());
+                };
+                let mut rest = rest;
+                let mut flags = Vec::new();
+                if rest.first().map(String::as_str) == Some("(") {
+                    let close = rest
+                        .iter()
+                        .position(|word| word == ")")
+                        .ok_or_else(|| anyhow!("Unclosed flags"))?;
+                    flags = rest[1..close].to_vec();
+                    rest = &rest[close + 1..];
+                }
+                let (date, raw) = match rest {
+                    [raw] => (None, raw),
+                    [date, raw] => (Some(date.clone()), raw),
+                    _ => return Err(anyhow!("Bad APPEND arguments")),
+                };
+                let uid = msgs.last().map_or(1, |msg| msg.uid + 1);
+                let mut msg = Msg::new(uid, raw.as_bytes());
+                msg.flags = flags;
+                if let Some(date) = date {
+                    msg.internal_date = date;
+                }
+                msgs.push(msg);
+                format!("OK [APPENDUID 1 {uid}] APPEND completed")
+            }
+            Some(command @ ("CLOSE" | "UNSELECT")) => {
+                *selected = None;
+                format!("OK {command} completed")
+            }
+            Some("UID") if state.throttle > 0 => {
+                state.throttle -= 1;
+                "NO [THROTTLED] Slow down".to_string()
+            }
+            Some("UID") => {
+                let Some(name) = selected.as_ref() else {
+                    return Ok("NO No mailbox selected".to_string());
+                };
+                let msgs = state
+                    .mailboxes
+                    .get_mut(name)
+                    .ok_or_else(|| anyhow!("Selected mailbox is gone"))?;
+                match args.first().map(|w| w.to_uppercase()).as_deref() {
+                    Some("FETCH") => uid_fetch(msgs, &args[1..], out)?,
+                    Some("SEARCH") => uid_search(msgs, &args[1..], out)?,
+                    _ => return Err(anyhow!("Unsupported UID command")),
+                }
+            }
+            _ => return Err(anyhow!("Unsupported command")),
+        };
+        Ok(status)
+    }
+}
+
+fn uid_fetch(
+    msgs: &mut [Msg],
+    args: &[String],
+    out: &mut Vec<u8>,
+) -> anyhow::Result<String> {
+    let (set, items) = args.split_first().ok_or_else(|| anyhow!("No set"))?;
+    let set = SeqSet::parse(set)?;
+    let max = msgs.last().map_or(0, |msg| msg.uid);
+    let items: Vec<String> = items
+        .iter()
+        .filter(|item| !matches!(item.as_str(), "(" | ")"))
+        .map(|item| item.to_uppercase())
+        .collect();
+    for (i, msg) in msgs.iter_mut().enumerate() {
+        if !set.contains(msg.uid, max) {
+            continue;
+        }
+        let mut attrs: Vec<Vec<u8>> = vec![format!("UID {}", msg.uid).into()];
+        for item in &items {
+            let (name, partial) = match item.split_once('<') {
+                None => (item.as_str(), None),
+                Some((name, partial)) => {
+                    let (offset, len) = partial
+                        .trim_end_matches('>')
+                        .split_once('.')
+                        .ok_or_else(|| anyhow!("Bad partial: {item:?}"))?;
+                    (name, Some((offset.parse()?, len.parse()?)))
+                }
+            };
+            let literal = |label: &str, data: &[u8]| {
+                let mut attr =
+                    format!("{label} {{{}}}\r\n", data.len()).into_bytes();
+                attr.extend_from_slice(data);
+                attr
+            };
+            match name {
+                "UID" => {}
+                "FLAGS" => {
+                    attrs.push(
+                        format!("FLAGS ({})", msg.flags.join(" ")).into(),
+                    );
+                }
+                "INTERNALDATE" => {
+                    attrs.push(
+                        format!("INTERNALDATE \"{}\"", msg.internal_date)
+                            .into(),
+                    );
+                }
+                "RFC822.SIZE" => {
+                    attrs.push(
+                        format!("RFC822.SIZE {}", msg.raw.len()).into(),
+                    );
+                }
+                "RFC822" | "BODY[]" | "BODY.PEEK[]" => {
+                    let data = match partial {
+                        None => msg.raw.clone(),
+                        Some((offset, len)) => {
+                            let offset: usize = offset;
+                            let lo = offset.min(msg.raw.len());
+                            let hi =
+                                offset.saturating_add(len).min(msg.raw.len());
+                            msg.raw[lo..hi].to_vec()
+                        }
+                    };
+                    let label = match (name, partial) {
+                        ("RFC822", _) => "RFC822".to_string(),
+                        (_, None) => "BODY[]".to_string(),
+                        (_, Some((offset, _))) => format!("BODY[]<{offset}>"),
+                    };
+                    attrs.push(literal(&label, &data));
+                    if name != "BODY.PEEK[]" && !msg.seen() {
+                        msg.flags.push("\\Seen".to_string());
+                    }
+                }
+                _ if name.contains("[HEADER.FIELDS (") => {
+                    let (_, fields) =
+                        name.split_once('(').unwrap_or_default();
+                    let fields: Vec<&str> =
+                        fields.trim_end_matches(")]").split(' ').collect();
+                    let mut data: Vec<u8> = header_lines(&msg.raw)
+                        .filter(|(field, _)| {
+                            fields
+                                .iter()
+                                .any(|f| f.eq_ignore_ascii_case(field))
+                        })
+                        .flat_map(|(_, line)| {
+                            format!("{line}\r\n").into_bytes()
+                        })
+                        .collect();
+                    data.extend_from_slice(b"\r\n");
+                    let label =
+                        format!("BODY[HEADER.FIELDS ({})]", fields.join(" "));
+                    attrs.push(literal(&label, &data));
+                    if !name.starts_with("BODY.PEEK[") && !msg.seen() {
+                        msg.flags.push("\\Seen".to_string());
+                    }
+                }
+                _ => return Err(anyhow!("Unsupported fetch item: {item:?}")),
+            }
+        }
+        out.extend_from_slice(format!("* {} FETCH (", i + 1).as_bytes());
+        out.extend_from_slice(&attrs.join(&b' '));
+        out.extend_from_slice(b")\r\n");
+    }
+    Ok("OK UID FETCH completed".to_string())
+}
+
+/// Only by "UID <set>", "HEADER <name> <substring>" and "ALL".
+fn uid_search(
+    msgs: &[Msg],
+    args: &[String],
+    out: &mut Vec<u8>,
+) -> anyhow::Result<String> {
+    let max = msgs.last().map_or(0, |msg| msg.uid);
+    let mut found: Vec<u32> = msgs.iter().map(|msg| msg.uid).collect();
+    let mut args = args.iter();
+    while let Some(key) = args.next() {
+        match key.to_uppercase().as_str() {
+            "ALL" => {}
+            "UID" => {
+                let set = args.next().ok_or_else(|| anyhow!("No set"))?;
+                let set = SeqSet::parse(set)?;
+                found.retain(|uid| set.contains(*uid, max));
+            }
+            "HEADER" => {
+                let (Some(name), Some(value)) = (args.next(), args.next())
+                else {
+                    return Err(anyhow!("No header name or value"));
+                };
+                found.retain(|uid| {
+                    msgs.iter()
+                        .find(|msg| msg.uid == *uid)
+                        .is_some_and(|msg| has_header(&msg.raw, name, value))
+                });
+            }
+            _ => return Err(anyhow!("Unsupported search key: {key:?}")),
+        }
+    }
+    out.extend_from_slice(b"* SEARCH");
+    for uid in found {
+        out.extend_from_slice(format!(" {uid}").as_bytes());
+    }
+    out.extend_from_slice(b"\r\n");
+    Ok("OK UID SEARCH completed".to_string())
+}
+
+/// Whether a header of the name contains the value, case-insensitively.
+fn has_header(raw: &[u8], name: &str, value: &str) -> bool {
+    let value = value.to_lowercase();
+    header_lines(raw).any(|(field, line)| {
+        field.eq_ignore_ascii_case(name)
+            && line[field.len() + 1..].to_lowercase().contains(&value)
+    })
+}
+
+/// Header lines, with their field names. Folded lines aren't unfolded, but
+/// left out.
+fn header_lines(raw: &[u8]) -> impl Iterator<Item = (String, String)> {
+    String::from_utf8_lossy(raw)
+        .lines()
+        .take_while(|line| !line.is_empty())
+        .filter_map(|line| {
+            let (field, _) = line.split_once(':')?;
+            (!field.starts_with([' ', '\t']))
+                .then(|| (field.to_string(), line.to_string()))
+        })
+        .collect::<Vec<_>>()
+        .into_iter()
+}
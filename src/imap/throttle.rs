@@ -0,0 +1,342 @@
+//! Politeness towards the server: limits on bandwidth and on the rate of
+//! commands, and backing off when the server says we're going too fast.
+
+use std::{
+    future::Future,
+    io,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{ready, Context, Poll},
+    time::{Duration, TryFromFloatSecsError},
+};
+
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    time::{Instant, Sleep},
+};
+
+use crate::cfg;
+
+/// Response codes, in NO or BAD responses, by which servers ask clients to
+/// slow down: Exchange's and RFC 5530's.
+const THROTTLE_CODES: [&str; 3] = ["[THROTTLED]", "[LIMIT]", "[UNAVAILABLE]"];
+
+/// Pause before the first retry of a throttled command, doubled after each.
+pub(crate) const PAUSE: Duration = Duration::from_secs(2);
+
+const RETRIES: u32 = 5;
+
+/// An account's limits, shared by all of its connections, so that the
+/// account as a whole keeps to them, however many connections it has.
+#[derive(Debug, Clone)]
+pub struct Limits {
+    budget: Option<Arc<Budget>>,
+    pacer: Arc<Pacer>,
+}
+
+impl Limits {
+    /// Fails for a rate of commands so small that the interval between them
+    /// can't be had.
+    pub fn new(
+        account: &cfg::ImapAccount,
+    ) -> Result<Self, TryFromFloatSecsError> {
+        Ok(Self {
+            budget: account
+                .max_bytes_per_sec
+                .map(|rate| Arc::new(Budget::new(rate))),
+            pacer: Arc::new(Pacer::new(account.max_commands_per_sec)?),
+        })
+    }
+
+    pub(crate) fn budget(&self) -> Option<Arc<Budget>> {
+        self.budget.clone()
+    }
+
+    pub(crate) fn pacer(&self) -> Arc<Pacer> {
+        Arc::clone(&self.pacer)
+    }
+}
+
+/// Bandwidth, in each direction, shared by connections: until when what's
+/// been read, and written, so far should take at the allowed rate.
+#[derive(Debug)]
+pub(crate) struct Budget {
+    bytes_per_sec: f64,
+    read_until: Mutex<Option<Instant>>,
+    write_until: Mutex<Option<Instant>>,
+}
+
+impl Budget {
+    pub fn new(bytes_per_sec: u32) -> Self {
+        Self {
+            bytes_per_sec: f64::from(bytes_per_sec.max(1)),
+            read_until: Mutex::default(),
+            write_until: Mutex::default(),
+        }
+    }
+
+    /// Add the time the bytes take, after that of those before them, and
+    /// return when it's up.
+    fn spend(&self, until: &Mutex<Option<Instant>>, bytes: usize) -> Instant {
+        let secs = bytes as f64 / self.bytes_per_sec;
+        let mut until = until.lock().unwrap_or_else(|e| e.into_inner());
+        let now = Instant::now();
+        let from = until.map_or(now, |until| until.max(now));
+        let end = from + Duration::from_secs_f64(secs);
+        *until = Some(end);
+        end
+    }
+}
+
+/// A connection which pauses after each read or write for as long as its
+/// bytes, after those of the other connections sharing its budget, should
+/// have taken at the allowed rate.
+#[derive(Debug)]
+pub(crate) struct Throttled<S> {
+    inner: S,
+    budget: Arc<Budget>,
+    read_pause: Option<Pin<Box<Sleep>>>,
+    write_pause: Option<Pin<Box<Sleep>>>,
+}
+
+impl<S> Throttled<S> {
+    pub fn new(inner: S, budget: Arc<Budget>) -> Self {
+        Self {
+            inner,
+            budget,
+            read_pause: None,
+            write_pause: None,
+        }
+    }
+
+    fn pause(
+        &self,
+        until: &Mutex<Option<Instant>>,
+        bytes: usize,
+    ) -> Option<Pin<Box<Sleep>>> {
+        (bytes > 0).then(|| {
+            let end = self.budget.spend(until, bytes);
+            Box::pin(tokio::time::sleep_until(end))
+        })
+    }
+}
+
+fn poll_pause(
+    pause: &mut Option<Pin<Box<Sleep>>>,
+    cx: &mut Context<'_>,
+) -> Poll<()> {
+    if let Some(sleep) = pause {
+        ready!(sleep.as_mut().poll(cx));
+        *pause = None;
+    }
+    Poll::Ready(())
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for Throttled<S> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = &mut *self;
+        ready!(poll_pause(&mut this.read_pause, cx));
+        let before = buf.filled().len();
+        ready!(Pin::new(&mut this.inner).poll_read(cx, buf))?;
+        let bytes = buf.filled().len() - before;
+        this.read_pause = this.pause(&this.budget.read_until, bytes);
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for Throttled<S> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = &mut *self;
+        ready!(poll_pause(&mut this.write_pause, cx));
+        let written = ready!(Pin::new(&mut this.inner).poll_write(cx, buf))?;
+        this.write_pause = this.pause(&this.budget.write_until, written);
+        Poll::Ready(Ok(written))
+    }
+
+    fn poll_flush(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+/// Spaces commands, of all connections sharing it, at least an interval
+/// apart.
+#[derive(Debug)]
+pub(crate) struct Pacer {
+    interval: Option<Duration>,
+    next: Mutex<Option<Instant>>,
+}
+
+impl Pacer {
+    pub fn new(
+        commands_per_sec: Option<f64>,
+    ) -> Result<Self, TryFromFloatSecsError> {
+        let interval = commands_per_sec
+            .filter(|rate| *rate > 0.0)
+            .map(|rate| Duration::try_from_secs_f64(1.0 / rate))
+            .transpose()?;
+        Ok(Self {
+            interval,
+            next: Mutex::default(),
+        })
+    }
+
+    /// Wait, if need be, before the next command, taking the next free
+    /// turn.
+    pub async fn pace(&self) {
+        let Some(interval) = self.interval else {
+            return;
+        };
+        let turn = {
+            let mut next =
+                self.next.lock().unwrap_or_else(|e| e.into_inner());
+            let now = Instant::now();
+            let turn = next.map_or(now, |next| next.max(now));
+            *next = Some(turn + interval);
+            turn
+        };
+        tokio::time::sleep_until(turn).await;
+    }
+}
+
+/// Whether the server refused the command for us going too fast.
+pub(crate) fn is_throttled(error: &async_imap::error::Error) -> bool {
+    use async_imap::error::Error;
+    match error {
+        Error::No(info) | Error::Bad(info) => {
+            let info = info.to_uppercase();
+            THROTTLE_CODES.iter().any(|code| info.contains(code))
+        }
+        _ => false,
+    }
+}
+
+/// Counts retries of a throttled command and pauses, increasingly, before
+/// each.
+#[derive(Debug)]
+pub(crate) struct Backoff {
+    pause: Duration,
+    retries: u32,
+}
+
+impl Backoff {
+    pub fn new(pause: Duration) -> Self {
+        Self { pause, retries: 0 }
+    }
+
+    /// Pause and return true if the command is to be retried.
+    pub async fn retry(&mut self, error: &crate::imap::Error) -> bool {
+        if !error.is_throttled() || self.retries >= RETRIES {
+            return false;
+        }
+        let pause = self.pause * 2u32.pow(self.retries);
+        self.retries += 1;
+        tracing::warn!(
+            ?error,
+            ?pause,
+            retry = self.retries,
+            "Throttled by server. Pausing before retrying."
+        );
+        tokio::time::sleep(pause).await;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    use super::*;
+
+    #[test]
+    fn t_is_throttled() {
+        use async_imap::error::Error;
+        let no = |info: &str| Error::No(info.to_string());
+        assert!(is_throttled(&no(
+            "code: None, info: Some(\"[THROTTLED] Request is throttled\")"
+        )));
+        assert!(is_throttled(&no(
+            "code: None, info: Some(\"[LIMIT] Slow\")"
+        )));
+        assert!(!is_throttled(&no("code: None, info: Some(\"No mailbox\")")));
+        assert!(!is_throttled(&Error::ConnectionLost));
+    }
+
+    #[tokio::test]
+    async fn t_throttled() {
+        let (client, mut server) = tokio::io::duplex(1 << 16);
+        let mut client =
+            Throttled::new(client, Arc::new(Budget::new(10_000)));
+        server.write_all(&[0; 3001]).await.unwrap();
+        let started = Instant::now();
+        client.read_exact(&mut [0; 3000]).await.unwrap();
+        // The pause after a read is only taken by the next one.
+        client.read_exact(&mut [0; 1]).await.unwrap();
+        assert!(started.elapsed() >= Duration::from_millis(300));
+
+        let started = Instant::now();
+        client.write_all(&[0; 2000]).await.unwrap();
+        client.write_all(&[0; 1]).await.unwrap();
+        assert!(started.elapsed() >= Duration::from_millis(200));
+    }
+
+    #[tokio::test]
+    async fn t_throttled_shared() {
+        let budget = Arc::new(Budget::new(10_000));
+        let started = Instant::now();
+        let clients = (0..2).map(|_| {
+            let budget = Arc::clone(&budget);
+            async move {
+                let (client, mut server) = tokio::io::duplex(1 << 16);
+                let mut client = Throttled::new(client, budget);
+                server.write_all(&[0; 1501]).await.unwrap();
+                client.read_exact(&mut [0; 1500]).await.unwrap();
+                client.read_exact(&mut [0; 1]).await.unwrap();
+            }
+        });
+        futures::future::join_all(clients).await;
+        // As if over one connection.
+        assert!(started.elapsed() >= Duration::from_millis(300));
+    }
+
+    #[tokio::test]
+    async fn t_pacer() {
+        let pacer = Pacer::new(Some(20.0)).unwrap();
+        let started = Instant::now();
+        for _ in 0..5 {
+            pacer.pace().await;
+        }
+        assert!(started.elapsed() >= Duration::from_millis(200));
+
+        // Shared, it spaces the commands of all its connections.
+        let pacer = Pacer::new(Some(20.0)).unwrap();
+        let started = Instant::now();
+        let connections = (0..2).map(|_| async {
+            for _ in 0..3 {
+                pacer.pace().await;
+            }
+        });
+        futures::future::join_all(connections).await;
+        assert!(started.elapsed() >= Duration::from_millis(250));
+
+        assert!(Pacer::new(Some(1e-320)).is_err());
+        assert!(Pacer::new(Some(0.0)).unwrap().interval.is_none());
+    }
+}
@@ -0,0 +1,1733 @@
+//! Read-only IMAP4rev1 (RFC 3501) server over the archive, so that it can be
+//! browsed with ordinary mail clients. Unencrypted and accepting any login,
+//! so meant to listen on localhost only.
+//!
+//! Folders:
+//! - INBOX -- everything
+//! - Accounts/<account>/<mailbox> -- where messages were fetched from
+//! - Years/<year> -- by "Date:", in UTC
+//!
+//! UIDs are handed out, in the order messages were stored, as messages are
+//! first served, and kept, never to change or be reused, so UIDVALIDITY is
+//! constant.
+
+use std::{collections::HashSet, io, net::SocketAddr, sync::Arc};
+
+use anyhow::anyhow;
+use mail_parser::{
+    Addr, Address, Group, Message, MessagePart, MimeHeaders, PartType,
+};
+use tokio::{
+    io::{
+        AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt,
+        BufReader,
+    },
+    net::{TcpListener, TcpStream},
+};
+
+use crate::{
+    data::{Filter, MsgMeta, Storage},
+    query::{Query, Term},
+};
+
+const CAPABILITIES: &str = "IMAP4rev1 LITERAL+ NAMESPACE ID UNSELECT";
+const DELIMITER: char = '/';
+const INBOX: &str = "INBOX";
+const ACCOUNTS: &str = "Accounts";
+const YEARS: &str = "Years";
+const UID_VALIDITY: u32 = 1;
+const MAX_LITERAL_LEN: usize = 1 << 20;
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct",
+    "Nov", "Dec",
+];
+
+pub async fn serve(db: Arc<Storage>, addr: SocketAddr) -> anyhow::Result<()> {
+    let listener = TcpListener::bind(addr).await?;
+    tracing::info!(addr = ?listener.local_addr()?, "Listening.");
+    serve_on(db, listener).await
+}
+
+pub async fn serve_on(
+    db: Arc<Storage>,
+    listener: TcpListener,
+) -> anyhow::Result<()> {
+    loop {
+        let (stream, peer) = listener.accept().await?;
+        tracing::info!(?peer, "Accepted.");
+        let db = Arc::clone(&db);
+        tokio::spawn(async move {
+            if let Err(error) = session(db, stream).await {
+                tracing::error!(?peer, ?error, "Session failed.");
+            }
+            tracing::info!(?peer, "Closed.");
+        });
+    }
+}
+
+async fn session(db: Arc<Storage>, stream: TcpStream) -> anyhow::Result<()> {
+    let (reader, mut writer) = stream.into_split();
+    let mut reader = BufReader::new(reader);
+    let mut session = Session {
+        db,
+        authenticated: false,
+        selected: None,
+    };
+    writer
+        .write_all(b"* OK [CAPABILITY IMAP4rev1] ma archive ready\r\n")
+        .await?;
+    while let Some(tokens) = read_command(&mut reader, &mut writer).await? {
+        tracing::debug!(?tokens, "Command.");
+        let mut out = Out::new(&mut writer);
+        let done = session.handle(tokens, &mut out).await?;
+        if done {
+            break;
+        }
+    }
+    Ok(())
+}
+
+// ----------------------------------------------------------------------------
+// Command input
+// ----------------------------------------------------------------------------
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Token {
+    Atom(String),
+    Str(String),
+    Open,
+    Close,
+}
+
+impl Token {
+    pub(crate) fn as_str(&self) -> Option<&str> {
+        match self {
+            Self::Atom(s) | Self::Str(s) => Some(s),
+            Self::Open | Self::Close => None,
+        }
+    }
+}
+
+/// Read a whole command, including any literals, or None on end of input.
+pub(crate) async fn read_command<R, W>(
+    reader: &mut BufReader<R>,
+    writer: &mut W,
+) -> anyhow::Result<Option<Vec<Token>>>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let mut tokens = Vec::new();
+    loop {
+        let mut line = Vec::new();
+        if reader.read_until(b'\n', &mut line).await? == 0 {
+            return Ok(None);
+        }
+        let line = String::from_utf8_lossy(&line);
+        let line = line.trim_end_matches(['\r', '\n']);
+        match literal_len(line) {
+            None => {
+                tokens.extend(tokenize(line));
+                return Ok(Some(tokens));
+            }
+            Some((prefix, len, non_sync)) => {
+                if len > MAX_LITERAL_LEN {
+                    return Err(anyhow!("Literal too long: {len}"));
+                }
+                tokens.extend(tokenize(prefix));
+                if !non_sync {
+                    writer.write_all(b"+ Ready\r\n").await?;
+                    writer.flush().await?;
+                }
+                let mut literal = vec![0; len];
+                reader.read_exact(&mut literal).await?;
+                tokens.push(Token::Str(
+                    String::from_utf8_lossy(&literal).to_string(),
+                ));
+            }
+        }
+    }
+}
+
+/// Line prefix, length and LITERAL+-ness of a trailing "{n}" or "{n+}".
+fn literal_len(line: &str) -> Option<(&str, usize, bool)> {
+    let inner = line.strip_suffix('}')?;
+    let lo = inner.rfind('{')?;
+    let (digits, non_sync) = match inner[lo + 1..].strip_suffix('+') {
+        Some(digits) => (digits, true),
+        None => (&inner[lo + 1..], false),
+    };
+    let len = digits.parse().ok()?;
+    Some((&line[..lo], len, non_sync))
+}
+
+fn tokenize(line: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut chars = line.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        match c {
+            ' ' | '\t' => {
+                chars.next();
+            }
+            '(' => {
+                chars.next();
+                tokens.push(Token::Open);
+            }
+            ')' => {
+                chars.next();
+                tokens.push(Token::Close);
+            }
+            '"' => {
+                chars.next();
+                let mut s = String::new();
+                while let Some(c) = chars.next() {
+                    match c {
+                        '"' => break,
+                        '\\' => {
+                            if let Some(c) = chars.next() {
+                                s.push(c);
+                            }
+                        }
+                        c => s.push(c),
+                    }
+                }
+                tokens.push(Token::Str(s));
+            }
+            _ => {
+                let mut atom = String::new();
+                let mut depth: usize = 0;
+                while let Some(&c) = chars.peek() {
+                    match c {
+                        '[' => depth += 1,
+                        ']' => depth = depth.saturating_sub(1),
+                        ' ' | '(' | ')' if depth == 0 => break,
+                        _ => {}
+                    }
+                    atom.push(c);
+                    chars.next();
+                }
+                tokens.push(Token::Atom(atom));
+            }
+        }
+    }
+    tokens
+}
+
+// ----------------------------------------------------------------------------
+// Response output
+// ----------------------------------------------------------------------------
+
+/// Responses, buffered until flushed, which they're to be after each
+/// message fetched, for a whole mailbox never to be held in memory at once.
+struct Out<'w> {
+    buf: Vec<u8>,
+    writer: &'w mut (dyn AsyncWrite + Send + Unpin),
+}
+
+impl<'w> Out<'w> {
+    fn new(writer: &'w mut (dyn AsyncWrite + Send + Unpin)) -> Self {
+        Self {
+            buf: Vec::new(),
+            writer,
+        }
+    }
+
+    async fn flush(&mut self) -> io::Result<()> {
+        self.writer.write_all(&self.buf).await?;
+        self.writer.flush().await?;
+        self.buf.clear();
+        Ok(())
+    }
+
+    fn raw(&mut self, s: &str) -> &mut Self {
+        self.buf.extend_from_slice(s.as_bytes());
+        self
+    }
+
+    fn string(&mut self, s: &[u8]) -> &mut Self {
+        let quotable = s.len() < 1024
+            && s.iter().all(|b| {
+                b.is_ascii() && !matches!(b, b'"' | b'\\' | b'\r' | b'\n')
+            });
+        if quotable {
+            self.buf.push(b'"');
+            self.buf.extend_from_slice(s);
+            self.buf.push(b'"');
+        } else {
+            self.literal(s);
+        }
+        self
+    }
+
+    fn nstring(&mut self, s: Option<&str>) -> &mut Self {
+        match s {
+            None => self.raw("NIL"),
+            Some(s) => self.string(s.as_bytes()),
+        }
+    }
+
+    fn literal(&mut self, s: &[u8]) -> &mut Self {
+        self.buf
+            .extend_from_slice(format!("{{{}}}\r\n", s.len()).as_bytes());
+        self.buf.extend_from_slice(s);
+        self
+    }
+}
+
+// ----------------------------------------------------------------------------
+// Folders
+// ----------------------------------------------------------------------------
+
+#[derive(Debug, Clone)]
+enum Folder {
+    All,
+    Location { account: String, mailbox: String },
+    Year(i64),
+}
+
+impl Folder {
+    fn filter(&self) -> anyhow::Result<Filter> {
+        let filter = match self {
+            Self::All => Filter::default(),
+            Self::Location { account, mailbox } => {
+                Filter::new(&Query::And(vec![
+                    Query::Term(Term::Account(glob_escape(account))),
+                    Query::Term(Term::Mailbox(glob_escape(mailbox))),
+                ]))
+            }
+            Self::Year(year) => {
+                Filter::new(&Query::parse(&format!("date:{year}"))?)
+            }
+        };
+        Ok(filter)
+    }
+}
+
+fn glob_escape(s: &str) -> String {
+    s.chars()
+        .map(|c| match c {
+            '*' | '?' | '[' => format!("[{c}]"),
+            c => c.to_string(),
+        })
+        .collect()
+}
+
+/// All folder names, each with its folder, or None for a non-selectable
+/// parent.
+async fn folders(
+    db: &Storage,
+) -> anyhow::Result<Vec<(String, Option<Folder>)>> {
+    let mut folders = vec![(INBOX.to_string(), Some(Folder::All))];
+    let mut parents: HashSet<String> = HashSet::new();
+    let mailboxes = db.fetch_mailboxes().await?;
+    if !mailboxes.is_empty() {
+        folders.push((ACCOUNTS.to_string(), None));
+    }
+    for (account, mailbox) in mailboxes {
+        let parent = format!("{ACCOUNTS}{DELIMITER}{account}");
+        if parents.insert(parent.clone()) {
+            folders.push((parent.clone(), None));
+        }
+        folders.push((
+            format!("{parent}{DELIMITER}{mailbox}"),
+            Some(Folder::Location { account, mailbox }),
+        ));
+    }
+    let years = db.fetch_years().await?;
+    if !years.is_empty() {
+        folders.push((YEARS.to_string(), None));
+    }
+    for year in years {
+        folders.push((
+            format!("{YEARS}{DELIMITER}{year}"),
+            Some(Folder::Year(year)),
+        ));
+    }
+    Ok(folders)
+}
+
+/// LIST pattern matching: "*" matches anything, "%" anything but the
+/// hierarchy delimiter.
+fn list_matches(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    fn go(p: &[char], n: &[char]) -> bool {
+        match p.split_first() {
+            None => n.is_empty(),
+            Some(('*', rest)) => (0..=n.len()).any(|i| go(rest, &n[i..])),
+            Some(('%', rest)) => (0..=n.len())
+                .take_while(|i| *i == 0 || n[i - 1] != DELIMITER)
+                .any(|i| go(rest, &n[i..])),
+            Some((c, rest)) => n.first() == Some(c) && go(rest, &n[1..]),
+        }
+    }
+    go(&pattern, &name)
+}
+
+// ----------------------------------------------------------------------------
+// Session
+// ----------------------------------------------------------------------------
+
+struct Selected {
+    /// (uid, hash), in UID order, so that index + 1 is the sequence number.
+    msgs: Vec<(u32, String)>,
+}
+
+struct Session {
+    db: Arc<Storage>,
+    authenticated: bool,
+    selected: Option<Selected>,
+}
+
+impl Session {
+    /// Respond to the command, returning whether the session is done.
+    async fn handle(
+        &mut self,
+        tokens: Vec<Token>,
+        out: &mut Out<'_>,
+    ) -> io::Result<bool> {
+        let mut tokens = tokens.into_iter();
+        let Some(Token::Atom(tag)) = tokens.next() else {
+            out.raw("* BAD Missing tag\r\n").flush().await?;
+            return Ok(false);
+        };
+        let Some(command) = tokens
+            .next()
+            .and_then(|t| t.as_str().map(str::to_uppercase))
+        else {
+            out.raw(&format!("{tag} BAD Missing command\r\n"))
+                .flush()
+                .await?;
+            return Ok(false);
+        };
+        let args: Vec<Token> = tokens.collect();
+        let (command, args, uid) = if command == "UID" {
+            match args.split_first() {
+                Some((Token::Atom(sub), rest)) => {
+                    (sub.to_uppercase(), rest.to_vec(), true)
+                }
+                _ => {
+                    out.raw(&format!("{tag} BAD Missing UID command\r\n"))
+                        .flush()
+                        .await?;
+                    return Ok(false);
+                }
+            }
+        } else {
+            (command, args, false)
+        };
+        let result = self.dispatch(&command, &args, uid, out).await;
+        let done = command == "LOGOUT";
+        match result {
+            Ok(status) => {
+                out.raw(&format!("{tag} {status}\r\n"));
+            }
+            Err(error) => {
+                tracing::debug!(?command, ?error, "Command failed.");
+                out.raw(&format!("{tag} BAD {error}\r\n"));
+            }
+        }
+        out.flush().await?;
+        Ok(done)
+    }
+
+    /// Writes untagged responses and returns the tagged status.
+    async fn dispatch(
+        &mut self,
+        command: &str,
+        args: &[Token],
+        uid: bool,
+        out: &mut Out<'_>,
+    ) -> anyhow::Result<String> {
+        let needs_auth = !matches!(
+            command,
+            "CAPABILITY" | "NOOP" | "LOGOUT" | "LOGIN" | "ID"
+        );
+        if needs_auth && !self.authenticated {
+            return Ok("NO Not logged in".to_string());
+        }
+        let needs_selected = matches!(
+            command,
+            "FETCH" | "SEARCH" | "CLOSE" | "UNSELECT" | "CHECK"
+        );
+        if needs_selected && self.selected.is_none() {
+            return Ok("NO No mailbox selected".to_string());
+        }
+        let status = match command {
+            "CAPABILITY" => {
+                out.raw(&format!("* CAPABILITY {CAPABILITIES}\r\n"));
+                "OK CAPABILITY completed".to_string()
+            }
+            "NOOP" | "CHECK" => format!("OK {command} completed"),
+            "LOGOUT" => {
+                out.raw("* BYE Logging out\r\n");
+                "OK LOGOUT completed".to_string()
+            }
+            "LOGIN" => {
+                self.authenticated = true;
+                "OK LOGIN completed".to_string()
+            }
+            "ID" => {
+                out.raw("* ID (\"name\" \"ma\")\r\n");
+                "OK ID completed".to_string()
+            }
+            "ENABLE" => "OK Nothing enabled".to_string(),
+            "NAMESPACE" => {
+                out.raw(&format!(
+                    "* NAMESPACE ((\"\" \"{DELIMITER}\")) NIL NIL\r\n"
+                ));
+                "OK NAMESPACE completed".to_string()
+            }
+            "LIST" | "LSUB" => self.list(command, args, out).await?,
+            "STATUS" => self.status(args, out).await?,
+            "SELECT" | "EXAMINE" => self.select(command, args, out).await?,
+            "CLOSE" | "UNSELECT" => {
+                self.selected = None;
+                format!("OK {command} completed")
+            }
+            "FETCH" => self.fetch(args, uid, out).await?,
+            "SEARCH" => self.search(args, uid, out).await?,
+            "APPEND" | "COPY" | "MOVE" | "STORE" | "EXPUNGE" | "CREATE"
+            | "DELETE" | "RENAME" | "SUBSCRIBE" | "UNSUBSCRIBE" => {
+                "NO [CANNOT] Read-only archive".to_string()
+            }
+            _ => return Err(anyhow!("Unknown command")),
+        };
+        Ok(status)
+    }
+
+    async fn list(
+        &self,
+        command: &str,
+        args: &[Token],
+        out: &mut Out<'_>,
+    ) -> anyhow::Result<String> {
+        let [reference, pattern] = args else {
+            return Err(anyhow!("Expected reference and pattern"));
+        };
+        let reference = reference.as_str().unwrap_or_default();
+        let pattern = pattern.as_str().unwrap_or_default();
+        if pattern.is_empty() {
+            out.raw(&format!(
+                "* {command} (\\Noselect) \"{DELIMITER}\" \"\"\r\n"
+            ));
+            return Ok(format!("OK {command} completed"));
+        }
+        let pattern = format!("{reference}{pattern}");
+        let folders = folders(&self.db).await?;
+        for (name, folder) in &folders {
+            // INBOX is case-insensitive.
+            let matches = list_matches(&pattern, name)
+                || (name == INBOX
+                    && list_matches(&pattern.to_uppercase(), name));
+            if !matches {
+                continue;
+            }
+            let has_children = folders.iter().any(|(other, _)| {
+                other
+                    .strip_prefix(name.as_str())
+                    .is_some_and(|rest| rest.starts_with(DELIMITER))
+            });
+            let mut attrs = vec![if has_children {
+                "\\HasChildren"
+            } else {
+                "\\HasNoChildren"
+            }];
+            if folder.is_none() {
+                attrs.push("\\Noselect");
+            }
+            out.raw(&format!(
+                "* {command} ({}) \"{DELIMITER}\" ",
+                attrs.join(" ")
+            ))
+            .string(name.as_bytes())
+            .raw("\r\n");
+        }
+        Ok(format!("OK {command} completed"))
+    }
+
+    async fn open(
+        &self,
+        name: &str,
+    ) -> anyhow::Result<Option<Vec<(u32, String)>>> {
+        let name = if name.eq_ignore_ascii_case(INBOX) {
+            INBOX
+        } else {
+            name
+        };
+        let folder = folders(&self.db)
+            .await?
+            .into_iter()
+            .find(|(n, _)| n == name)
+            .and_then(|(_, folder)| folder);
+        match folder {
+            None => Ok(None),
+            Some(folder) => {
+                let msgs = self.db.find_uids(&folder.filter()?).await?;
+                Ok(Some(msgs))
+            }
+        }
+    }
+
+    async fn status(
+        &self,
+        args: &[Token],
+        out: &mut Out<'_>,
+    ) -> anyhow::Result<String> {
+        let Some(name) = args.first().and_then(Token::as_str) else {
+            return Err(anyhow!("Expected mailbox"));
+        };
+        let Some(msgs) = self.open(name).await? else {
+            return Ok("NO No such mailbox".to_string());
+        };
+        let mut items = Vec::new();
+        for item in args.iter().skip(1).filter_map(Token::as_str) {
+            let item = item.to_uppercase();
+            let value = match item.as_str() {
+                "MESSAGES" => msgs.len().to_string(),
+                "RECENT" | "UNSEEN" => "0".to_string(),
+                "UIDNEXT" => uid_next(&msgs).to_string(),
+                "UIDVALIDITY" => UID_VALIDITY.to_string(),
+                _ => return Err(anyhow!("Unknown status item: {item:?}")),
+            };
+            items.push(format!("{item} {value}"));
+        }
+        out.raw("* STATUS ")
+            .string(name.as_bytes())
+            .raw(&format!(" ({})\r\n", items.join(" ")));
+        Ok("OK STATUS completed".to_string())
+    }
+
+    async fn select(
+        &mut self,
+        command: &str,
+        args: &[Token],
+        out: &mut Out<'_>,
+    ) -> anyhow::Result<String> {
+        self.selected = None;
+        let Some(name) = args.first().and_then(Token::as_str) else {
+            return Err(anyhow!("Expected mailbox"));
+        };
+        let Some(msgs) = self.open(name).await? else {
+            return Ok("NO No such mailbox".to_string());
+        };
+        out.raw("* FLAGS (\\Seen)\r\n")
+            .raw("* OK [PERMANENTFLAGS ()] Read-only\r\n")
+            .raw(&format!("* {} EXISTS\r\n", msgs.len()))
+            .raw("* 0 RECENT\r\n")
+            .raw(&format!("* OK [UIDVALIDITY {UID_VALIDITY}] UIDs valid\r\n"))
+            .raw(&format!(
+                "* OK [UIDNEXT {}] Predicted next UID\r\n",
+                uid_next(&msgs)
+            ));
+        self.selected = Some(Selected { msgs });
+        Ok(format!("OK [READ-ONLY] {command} completed"))
+    }
+
+    async fn fetch(
+        &self,
+        args: &[Token],
+        uid: bool,
+        out: &mut Out<'_>,
+    ) -> anyhow::Result<String> {
+        let selected =
+            self.selected.as_ref().unwrap_or_else(|| unreachable!());
+        let Some((set, items)) = args.split_first() else {
+            return Err(anyhow!("Expected sequence set"));
+        };
+        let set = SeqSet::parse(set.as_str().unwrap_or_default())?;
+        let mut items = FetchItem::parse_all(items)?;
+        if uid && !items.contains(&FetchItem::Uid) {
+            items.insert(0, FetchItem::Uid);
+        }
+        let max = if uid {
+            selected.msgs.last().map_or(0, |(uid, _)| *uid)
+        } else {
+            u32::try_from(selected.msgs.len())?
+        };
+        let needs_raw = items.iter().any(FetchItem::needs_raw);
+        for (i, (msg_uid, hash)) in selected.msgs.iter().enumerate() {
+            let seq = u32::try_from(i + 1)?;
+            if !set.contains(if uid { *msg_uid } else { seq }, max) {
+                continue;
+            }
+            let meta = self.db.fetch_meta(hash).await?;
+            let raw = if needs_raw || meta.is_none() {
+                let Some(msg) = self.db.fetch_msg(hash).await? else {
+                    continue;
+                };
+                Some(msg.raw)
+            } else {
+                None
+            };
+            let msg = Loaded { meta, raw };
+            out.raw(&format!("* {seq} FETCH ("));
+            for (j, item) in items.iter().enumerate() {
+                if j > 0 {
+                    out.raw(" ");
+                }
+                item.write(out, *msg_uid, &msg);
+            }
+            out.raw(")\r\n").flush().await?;
+        }
+        Ok("OK FETCH completed".to_string())
+    }
+
+    async fn search(
+        &self,
+        args: &[Token],
+        uid: bool,
+        out: &mut Out<'_>,
+    ) -> anyhow::Result<String> {
+        let selected =
+            self.selected.as_ref().unwrap_or_else(|| unreachable!());
+        let mut args = args;
+        if let Some(Token::Atom(a)) = args.first() {
+            if a.eq_ignore_ascii_case("CHARSET") {
+                args = args.get(2..).unwrap_or_default();
+            }
+        }
+        let mut leaves = Vec::new();
+        let mut tokens = args.iter();
+        let mut keys = Vec::new();
+        while tokens.len() > 0 {
+            keys.push(SearchKey::parse(&mut tokens, &mut leaves)?);
+        }
+        let key = SearchKey::And(keys);
+        let mut leaf_hashes: Vec<HashSet<String>> = Vec::new();
+        for leaf in leaves {
+            let hashes = match leaf {
+                Leaf::Query(query) => {
+                    self.db.find(&Filter::new(&query)).await?
+                }
+                Leaf::Header(name, value) => {
+                    self.db.find_by_header(&name, &value).await?
+                }
+            };
+            leaf_hashes.push(hashes.into_iter().collect());
+        }
+        let found = key.eval(&selected.msgs, &leaf_hashes);
+        let mut found: Vec<u32> = found
+            .into_iter()
+            .map(|i| {
+                if uid {
+                    selected.msgs[i].0
+                } else {
+                    u32::try_from(i + 1).unwrap_or(u32::MAX)
+                }
+            })
+            .collect();
+        found.sort_unstable();
+        out.raw("* SEARCH");
+        for n in found {
+            out.raw(&format!(" {n}"));
+        }
+        out.raw("\r\n");
+        Ok("OK SEARCH completed".to_string())
+    }
+}
+
+fn uid_next(msgs: &[(u32, String)]) -> u32 {
+    msgs.last().map_or(1, |(uid, _)| uid.saturating_add(1))
+}
+
+// ----------------------------------------------------------------------------
+// Sequence sets
+// ----------------------------------------------------------------------------
+
+/// Ranges, where None stands for "*": the highest number in the mailbox.
+#[derive(Debug)]
+pub(crate) struct SeqSet(Vec<(Option<u32>, Option<u32>)>);
+
+impl SeqSet {
+    pub(crate) fn parse(s: &str) -> anyhow::Result<Self> {
+        let num = |n: &str| -> anyhow::Result<Option<u32>> {
+            if n == "*" {
+                Ok(None)
+            } else {
+                Ok(Some(n.parse()?))
+            }
+        };
+        let mut ranges = Vec::new();
+        for range in s.split(',') {
+            let range = match range.split_once(':') {
+                None => (num(range)?, num(range)?),
+                Some((lo, hi)) => (num(lo)?, num(hi)?),
+            };
+            ranges.push(range);
+        }
+        Ok(Self(ranges))
+    }
+
+    pub(crate) fn contains(&self, n: u32, max: u32) -> bool {
+        self.0.iter().any(|(lo, hi)| {
+            let lo = lo.unwrap_or(max);
+            let hi = hi.unwrap_or(max);
+            let (lo, hi) = if lo <= hi { (lo, hi) } else { (hi, lo) };
+            (lo..=hi).contains(&n)
+        })
+    }
+}
+
+// ----------------------------------------------------------------------------
+// SEARCH
+// ----------------------------------------------------------------------------
+
+enum Leaf {
+    Query(Query),
+    Header(String, String),
+}
+
+enum SearchKey {
+    All,
+    None,
+    Seq(SeqSet),
+    Uid(SeqSet),
+    /// Index into the leaves, whose hashes are looked-up ahead of evaluation.
+    Leaf(usize),
+    Not(Box<SearchKey>),
+    Or(Box<SearchKey>, Box<SearchKey>),
+    And(Vec<SearchKey>),
+}
+
+impl SearchKey {
+    fn parse(
+        tokens: &mut std::slice::Iter<Token>,
+        leaves: &mut Vec<Leaf>,
+    ) -> anyhow::Result<Self> {
+        let arg = |tokens: &mut std::slice::Iter<Token>| {
+            tokens
+                .next()
+                .and_then(Token::as_str)
+                .map(str::to_string)
+                .ok_or_else(|| anyhow!("Missing search argument"))
+        };
+        let mut leaf = |leaf: Leaf| {
+            leaves.push(leaf);
+            Self::Leaf(leaves.len() - 1)
+        };
+        let key = match tokens.next() {
+            None => return Err(anyhow!("Missing search key")),
+            Some(Token::Close) => return Err(anyhow!("Unexpected )")),
+            Some(Token::Open) => {
+                let mut keys = Vec::new();
+                loop {
+                    match tokens.clone().next() {
+                        None => return Err(anyhow!("Missing )")),
+                        Some(Token::Close) => {
+                            tokens.next();
+                            break;
+                        }
+                        Some(_) => keys.push(Self::parse(tokens, leaves)?),
+                    }
+                }
+                Self::And(keys)
+            }
+            Some(Token::Str(s)) => {
+                return Err(anyhow!("Unexpected string: {s:?}"))
+            }
+            Some(Token::Atom(atom)) => match atom.to_uppercase().as_str() {
+                "ALL" | "SEEN" | "OLD" | "UNANSWERED" | "UNDELETED"
+                | "UNDRAFT" | "UNFLAGGED" => Self::All,
+                "ANSWERED" | "DELETED" | "DRAFT" | "FLAGGED" | "NEW"
+                | "RECENT" | "UNSEEN" => Self::None,
+                "KEYWORD" => {
+                    arg(tokens)?;
+                    Self::None
+                }
+                "UNKEYWORD" => {
+                    arg(tokens)?;
+                    Self::All
+                }
+                "UID" => Self::Uid(SeqSet::parse(&arg(tokens)?)?),
+                "NOT" => Self::Not(Box::new(Self::parse(tokens, leaves)?)),
+                "OR" => {
+                    let a = Self::parse(tokens, leaves)?;
+                    let b = Self::parse(tokens, leaves)?;
+                    Self::Or(Box::new(a), Box::new(b))
+                }
+                name @ ("FROM" | "TO" | "CC" | "BCC") => {
+                    leaf(Leaf::Header(name.to_string(), arg(tokens)?))
+                }
+                "HEADER" => {
+                    let name = arg(tokens)?;
+                    let value = arg(tokens)?;
+                    leaf(Leaf::Header(name, value))
+                }
+                "SUBJECT" => leaf(Leaf::Query(Query::Term(Term::Subject(
+                    arg(tokens)?,
+                )))),
+                "BODY" | "TEXT" => {
+                    leaf(Leaf::Query(Query::Term(Term::Text(arg(tokens)?))))
+                }
+                "LARGER" => leaf(Leaf::Query(Query::Term(Term::SizeAbove(
+                    arg(tokens)?.parse()?,
+                )))),
+                "SMALLER" => leaf(Leaf::Query(Query::Term(Term::SizeBelow(
+                    arg(tokens)?.parse()?,
+                )))),
+                "SINCE" | "SENTSINCE" => {
+                    let (lo, _) = search_date(&arg(tokens)?)?;
+                    leaf(Leaf::Query(Query::Term(Term::DateFrom(lo))))
+                }
+                "BEFORE" | "SENTBEFORE" => {
+                    let (lo, _) = search_date(&arg(tokens)?)?;
+                    leaf(Leaf::Query(Query::Term(Term::DateUntil(lo))))
+                }
+                "ON" | "SENTON" => {
+                    let (lo, hi) = search_date(&arg(tokens)?)?;
+                    leaf(Leaf::Query(Query::And(vec![
+                        Query::Term(Term::DateFrom(lo)),
+                        Query::Term(Term::DateUntil(hi)),
+                    ])))
+                }
+                _ => Self::Seq(SeqSet::parse(atom)?),
+            },
+        };
+        Ok(key)
+    }
+
+    /// Indices of matching messages.
+    fn eval(
+        &self,
+        msgs: &[(u32, String)],
+        leaves: &[HashSet<String>],
+    ) -> HashSet<usize> {
+        let all = || (0..msgs.len()).collect::<HashSet<usize>>();
+        match self {
+            Self::All => all(),
+            Self::None => HashSet::new(),
+            Self::Seq(set) => {
+                let max = u32::try_from(msgs.len()).unwrap_or(u32::MAX);
+                (0..msgs.len())
+                    .filter(|i| {
+                        set.contains(
+                            u32::try_from(i + 1).unwrap_or(u32::MAX),
+                            max,
+                        )
+                    })
+                    .collect()
+            }
+            Self::Uid(set) => {
+                let max = msgs.last().map_or(0, |(uid, _)| *uid);
+                (0..msgs.len())
+                    .filter(|i| set.contains(msgs[*i].0, max))
+                    .collect()
+            }
+            Self::Leaf(leaf) => (0..msgs.len())
+                .filter(|i| leaves[*leaf].contains(&msgs[*i].1))
+                .collect(),
+            Self::Not(key) => {
+                let excluded = key.eval(msgs, leaves);
+                all().difference(&excluded).copied().collect()
+            }
+            Self::Or(a, b) => {
+                let a = a.eval(msgs, leaves);
+                let b = b.eval(msgs, leaves);
+                a.union(&b).copied().collect()
+            }
+            Self::And(keys) => keys.iter().fold(all(), |acc, key| {
+                let found = key.eval(msgs, leaves);
+                acc.intersection(&found).copied().collect()
+            }),
+        }
+    }
+}
+
+/// Unix time range of an IMAP date, like "1-Feb-1994".
+fn search_date(date: &str) -> anyhow::Result<(i64, i64)> {
+    let invalid = || anyhow!("Invalid date: {date:?}");
+    let mut fields = date.split('-');
+    let day: u8 = fields.next().ok_or_else(invalid)?.parse()?;
+    let month = fields.next().ok_or_else(invalid)?;
+    let month = MONTHS
+        .iter()
+        .position(|m| m.eq_ignore_ascii_case(month))
+        .ok_or_else(invalid)?;
+    let year: u16 = fields.next().ok_or_else(invalid)?.parse()?;
+    let lo = mail_parser::DateTime {
+        year,
+        month: u8::try_from(month + 1)?,
+        day,
+        hour: 0,
+        minute: 0,
+        second: 0,
+        tz_before_gmt: false,
+        tz_hour: 0,
+        tz_minute: 0,
+    }
+    .to_timestamp();
+    Ok((lo, lo + 24 * 60 * 60))
+}
+
+// ----------------------------------------------------------------------------
+// FETCH
+// ----------------------------------------------------------------------------
+
+/// What fetch items are written from: the metadata, if the message has
+/// any, and the raw message, if any item needs it or there's no metadata.
+struct Loaded {
+    meta: Option<MsgMeta>,
+    raw: Option<Vec<u8>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum FetchItem {
+    Uid,
+    Flags,
+    InternalDate,
+    Rfc822Size,
+    Envelope,
+    BodyStructure,
+    Body,
+    Rfc822,
+    Rfc822Header,
+    Rfc822Text,
+    Section {
+        section: Section,
+        /// As requested, for echoing back.
+        label: String,
+        partial: Option<(usize, usize)>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Section {
+    path: Vec<usize>,
+    spec: SectionSpec,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum SectionSpec {
+    Whole,
+    Header,
+    HeaderFields { not: bool, names: Vec<String> },
+    Text,
+    Mime,
+}
+
+impl FetchItem {
+    fn parse_all(tokens: &[Token]) -> anyhow::Result<Vec<Self>> {
+        let atoms: Vec<&str> =
+            tokens.iter().filter_map(Token::as_str).collect();
+        let mut items = Vec::new();
+        for atom in atoms {
+            match atom.to_uppercase().as_str() {
+                "ALL" => items.extend([
+                    Self::Flags,
+                    Self::InternalDate,
+                    Self::Rfc822Size,
+                    Self::Envelope,
+                ]),
+                "FAST" => {
+                    items.extend([
+                        Self::Flags,
+                        Self::InternalDate,
+                        Self::Rfc822Size,
+                    ]);
+                }
+                "FULL" => items.extend([
+                    Self::Flags,
+                    Self::InternalDate,
+                    Self::Rfc822Size,
+                    Self::Envelope,
+                    Self::Body,
+                ]),
+                _ => items.push(Self::parse(atom)?),
+            }
+        }
+        if items.is_empty() {
+            return Err(anyhow!("No fetch items"));
+        }
+        Ok(items)
+    }
+
+    fn parse(atom: &str) -> anyhow::Result<Self> {
+        let item = match atom.to_uppercase().as_str() {
+            "UID" => Self::Uid,
+            "FLAGS" => Self::Flags,
+            "INTERNALDATE" => Self::InternalDate,
+            "RFC822.SIZE" => Self::Rfc822Size,
+            "ENVELOPE" => Self::Envelope,
+            "BODYSTRUCTURE" => Self::BodyStructure,
+            "BODY" => Self::Body,
+            "RFC822" => Self::Rfc822,
+            "RFC822.HEADER" => Self::Rfc822Header,
+            "RFC822.TEXT" => Self::Rfc822Text,
+            upper => {
+                let lo = upper
+                    .find('[')
+                    .ok_or_else(|| anyhow!("Unknown fetch item: {atom:?}"))?;
+                let hi = upper.rfind(']').ok_or_else(|| {
+                    anyhow!("Unterminated section: {atom:?}")
+                })?;
+                // Offsets are into the uppercased atom, which needn't be as
+                // long as the original.
+                if !matches!(&upper[..lo], "BODY" | "BODY.PEEK") {
+                    return Err(anyhow!("Unknown fetch item: {atom:?}"));
+                }
+                let inner = &upper[lo + 1..hi];
+                let partial = match upper[hi + 1..]
+                    .strip_prefix('<')
+                    .and_then(|p| p.strip_suffix('>'))
+                {
+                    None => None,
+                    Some(p) => {
+                        let (offset, len) =
+                            p.split_once('.').ok_or_else(|| {
+                                anyhow!("Invalid partial: {p:?}")
+                            })?;
+                        Some((offset.parse()?, len.parse()?))
+                    }
+                };
+                Self::Section {
+                    section: Section::parse(inner)?,
+                    label: format!("BODY[{inner}]"),
+                    partial,
+                }
+            }
+        };
+        Ok(item)
+    }
+
+    fn needs_raw(&self) -> bool {
+        !matches!(
+            self,
+            Self::Uid | Self::Flags | Self::InternalDate | Self::Rfc822Size
+        )
+    }
+
+    fn write(&self, out: &mut Out<'_>, uid: u32, msg: &Loaded) {
+        let raw = msg.raw.as_deref().unwrap_or_default();
+        match self {
+            Self::Uid => {
+                out.raw(&format!("UID {uid}"));
+            }
+            Self::Flags => {
+                out.raw("FLAGS (\\Seen)");
+            }
+            Self::InternalDate => {
+                let date = match &msg.meta {
+                    Some(meta) => meta.date.zip(meta.date_offset),
+                    None => raw_date(raw),
+                };
+                out.raw("INTERNALDATE ")
+                    .string(internal_date(date).as_bytes());
+            }
+            Self::Rfc822Size => {
+                let size = match &msg.meta {
+                    Some(meta) => u64::from(meta.size),
+                    None => raw.len() as u64,
+                };
+                out.raw(&format!("RFC822.SIZE {size}"));
+            }
+            Self::Envelope => {
+                let msg = parse(raw);
+                out.raw("ENVELOPE ");
+                envelope(out, &msg);
+            }
+            Self::BodyStructure => {
+                out.raw("BODYSTRUCTURE ");
+                body_structure(out, raw);
+            }
+            Self::Body => {
+                out.raw("BODY ");
+                body_structure(out, raw);
+            }
+            Self::Rfc822 => {
+                out.raw("RFC822 ").literal(raw);
+            }
+            Self::Rfc822Header => {
+                out.raw("RFC822.HEADER ").literal(split_header(raw).0);
+            }
+            Self::Rfc822Text => {
+                out.raw("RFC822.TEXT ").literal(split_header(raw).1);
+            }
+            Self::Section {
+                section,
+                label,
+                partial,
+            } => {
+                let data = section.extract(raw).unwrap_or_default();
+                match partial {
+                    None => {
+                        out.raw(&format!("{label} ")).literal(&data);
+                    }
+                    Some((offset, len)) => {
+                        let lo = (*offset).min(data.len());
+                        let hi = offset.saturating_add(*len).min(data.len());
+                        out.raw(&format!("{label}<{offset}> "))
+                            .literal(&data[lo..hi]);
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl Section {
+    fn parse(inner: &str) -> anyhow::Result<Self> {
+        let (spec, names) = match inner.split_once(' ') {
+            None => (inner, None),
+            Some((spec, names)) => (spec, Some(names)),
+        };
+        let mut path = Vec::new();
+        let mut rest: Vec<&str> = Vec::new();
+        for field in spec.split('.').filter(|f| !f.is_empty()) {
+            match field.parse::<usize>() {
+                Ok(n) if rest.is_empty() && n > 0 => path.push(n),
+                _ => rest.push(field),
+            }
+        }
+        let names = || -> Vec<String> {
+            names
+                .unwrap_or_default()
+                .trim_matches(['(', ')'])
+                .split_whitespace()
+                .map(|n| n.trim_matches('"').to_string())
+                .collect()
+        };
+        let spec = match rest.join(".").as_str() {
+            "" => SectionSpec::Whole,
+            "HEADER" => SectionSpec::Header,
+            "HEADER.FIELDS" => SectionSpec::HeaderFields {
+                not: false,
+                names: names(),
+            },
+            "HEADER.FIELDS.NOT" => SectionSpec::HeaderFields {
+                not: true,
+                names: names(),
+            },
+            "TEXT" => SectionSpec::Text,
+            "MIME" if !path.is_empty() => SectionSpec::Mime,
+            other => return Err(anyhow!("Unknown section: {other:?}")),
+        };
+        Ok(Self { path, spec })
+    }
+
+    fn extract(&self, raw: &[u8]) -> Option<Vec<u8>> {
+        extract(raw, &self.path, &self.spec)
+    }
+}
+
+fn extract(
+    raw: &[u8],
+    path: &[usize],
+    spec: &SectionSpec,
+) -> Option<Vec<u8>> {
+    let Some((first, rest)) = path.split_first() else {
+        let (header, text) = split_header(raw);
+        return match spec {
+            SectionSpec::Whole => Some(raw.to_vec()),
+            SectionSpec::Header => Some(header.to_vec()),
+            SectionSpec::Text => Some(text.to_vec()),
+            SectionSpec::HeaderFields { not, names } => {
+                Some(header_fields(header, *not, names))
+            }
+            SectionSpec::Mime => None,
+        };
+    };
+    let msg = parse(raw);
+    let root = msg.parts.first()?;
+    let mut part: &MessagePart = match &root.body {
+        PartType::Multipart(children) => {
+            msg.parts.get(*children.get(first - 1)?)?
+        }
+        _ if *first == 1 => root,
+        _ => return None,
+    };
+    let mut rest = rest;
+    while let Some((n, tail)) = rest.split_first() {
+        match &part.body {
+            PartType::Multipart(children) => {
+                part = msg.parts.get(*children.get(n - 1)?)?;
+                rest = tail;
+            }
+            PartType::Message(_) => {
+                let nested = raw.get(part.offset_body..part.offset_end)?;
+                return extract(nested, rest, spec);
+            }
+            _ => return None,
+        }
+    }
+    let body = raw.get(part.offset_body..part.offset_end)?;
+    match spec {
+        SectionSpec::Whole => Some(body.to_vec()),
+        SectionSpec::Mime => raw
+            .get(part.offset_header..part.offset_body)
+            .map(<[u8]>::to_vec),
+        SectionSpec::Header
+        | SectionSpec::Text
+        | SectionSpec::HeaderFields { .. } => {
+            if part.is_message() {
+                extract(body, &[], spec)
+            } else {
+                None
+            }
+        }
+    }
+}
+
+fn parse(raw: &[u8]) -> Message<'_> {
+    mail_parser::MessageParser::default()
+        .parse(raw)
+        .unwrap_or_default()
+}
+
+/// Header block (including the blank line that ends it) and body.
+fn split_header(raw: &[u8]) -> (&[u8], &[u8]) {
+    let mut i = 0;
+    while i < raw.len() {
+        let line_end = raw[i..]
+            .iter()
+            .position(|b| *b == b'\n')
+            .map_or(raw.len(), |p| i + p + 1);
+        let line = &raw[i..line_end];
+        if line == b"\r\n" || line == b"\n" {
+            return (&raw[..line_end], &raw[line_end..]);
+        }
+        i = line_end;
+    }
+    (raw, &[])
+}
+
+fn header_fields(header: &[u8], not: bool, names: &[String]) -> Vec<u8> {
+    let mut fields: Vec<Vec<u8>> = Vec::new();
+    for line in header.split_inclusive(|b| *b == b'\n') {
+        if line == b"\r\n" || line == b"\n" {
+            break;
+        }
+        match (line.first(), fields.last_mut()) {
+            (Some(b' ' | b'\t'), Some(field)) => {
+                field.extend_from_slice(line)
+            }
+            _ => fields.push(line.to_vec()),
+        }
+    }
+    let mut selected: Vec<u8> = fields
+        .into_iter()
+        .filter(|field| {
+            let name = field
+                .split(|b| *b == b':')
+                .next()
+                .map(|n| String::from_utf8_lossy(n).trim().to_string())
+                .unwrap_or_default();
+            let listed = names.iter().any(|n| n.eq_ignore_ascii_case(&name));
+            listed != not
+        })
+        .flatten()
+        .collect();
+    selected.extend_from_slice(b"\r\n");
+    selected
+}
+
+/// "Date:" as Unix time and minutes east of UTC, as in [`MsgMeta`].
+fn raw_date(raw: &[u8]) -> Option<(i64, i32)> {
+    let msg = parse(raw);
+    let d = msg.date().filter(|d| d.is_valid())?;
+    let offset = i32::from(d.tz_hour) * 60 + i32::from(d.tz_minute);
+    let offset = if d.tz_before_gmt { -offset } else { offset };
+    Some((d.to_timestamp(), offset))
+}
+
+/// INTERNALDATE of a "Date:", given as Unix time and minutes east of UTC.
+fn internal_date(date: Option<(i64, i32)>) -> String {
+    let Some((timestamp, offset)) = date else {
+        return "01-Jan-1970 00:00:00 +0000".to_string();
+    };
+    let d = mail_parser::DateTime::from_timestamp(
+        timestamp + i64::from(offset) * 60,
+    );
+    let sign = if offset < 0 { '-' } else { '+' };
+    let offset = offset.unsigned_abs();
+    format!(
+        "{:02}-{}-{:04} {:02}:{:02}:{:02} {sign}{:02}{:02}",
+        d.day,
+        MONTHS[usize::from(d.month.clamp(1, 12) - 1)],
+        d.year,
+        d.hour,
+        d.minute,
+        d.second,
+        offset / 60,
+        offset % 60,
+    )
+}
+
+fn envelope(out: &mut Out<'_>, msg: &Message) {
+    let header = |name: &str| {
+        msg.header_raw(name).map(|value| value.trim().to_string())
+    };
+    out.raw("(");
+    out.nstring(header("Date").as_deref()).raw(" ");
+    out.nstring(msg.subject()).raw(" ");
+    let from = msg.from();
+    for (i, address) in [
+        from,
+        msg.sender().or(from),
+        msg.reply_to().or(from),
+        msg.to(),
+        msg.cc(),
+        msg.bcc(),
+    ]
+    .into_iter()
+    .enumerate()
+    {
+        if i > 0 {
+            out.raw(" ");
+        }
+        addresses(out, address);
+    }
+    out.raw(" ");
+    out.nstring(header("In-Reply-To").as_deref()).raw(" ");
+    out.nstring(header("Message-ID").as_deref());
+    out.raw(")");
+}
+
+fn addresses(out: &mut Out<'_>, address: Option<&Address>) {
+    let addrs: Vec<&Addr> = match address {
+        None => Vec::new(),
+        Some(Address::List(addrs)) => addrs.iter().collect(),
+        Some(Address::Group(groups)) => groups
+            .iter()
+            .flat_map(|Group { name: _, addresses }| addresses)
+            .collect(),
+    };
+    let addrs: Vec<(Option<&str>, &str)> = addrs
+        .into_iter()
+        .filter_map(|Addr { name, address }| {
+            address.as_deref().map(|address| (name.as_deref(), address))
+        })
+        .collect();
+    if addrs.is_empty() {
+        out.raw("NIL");
+        return;
+    }
+    out.raw("(");
+    for (name, address) in addrs {
+        let (mailbox, host) = match address.rsplit_once('@') {
+            Some((mailbox, host)) => (mailbox, Some(host)),
+            None => (address, None),
+        };
+        out.raw("(");
+        out.nstring(name).raw(" NIL ");
+        out.nstring(Some(mailbox)).raw(" ");
+        out.nstring(host).raw(")");
+    }
+    out.raw(")");
+}
+
+fn body_structure(out: &mut Out<'_>, raw: &[u8]) {
+    let msg = parse(raw);
+    if msg.parts.is_empty() {
+        out.raw(&format!(
+            "(\"TEXT\" \"PLAIN\" (\"CHARSET\" \"US-ASCII\") NIL NIL \"7BIT\" {} {})",
+            raw.len(),
+            lines(raw)
+        ));
+        return;
+    }
+    part_structure(out, &msg, raw, 0);
+}
+
+fn part_structure(out: &mut Out<'_>, msg: &Message, raw: &[u8], idx: usize) {
+    let Some(part) = msg.parts.get(idx) else {
+        out.raw("NIL");
+        return;
+    };
+    let content_type = crate::data::part_content_type(part).to_uppercase();
+    let (ctype, subtype) = content_type
+        .split_once('/')
+        .unwrap_or((content_type.as_str(), ""));
+    if let PartType::Multipart(children) = &part.body {
+        out.raw("(");
+        for child in children {
+            part_structure(out, msg, raw, *child);
+        }
+        out.raw(" ").string(subtype.as_bytes()).raw(")");
+        return;
+    }
+    let body = raw
+        .get(part.offset_body..part.offset_end)
+        .unwrap_or_default();
+    out.raw("(")
+        .string(ctype.as_bytes())
+        .raw(" ")
+        .string(subtype.as_bytes())
+        .raw(" ");
+    let attributes: Vec<(String, String)> = part
+        .content_type()
+        .and_then(|ct| ct.attributes())
+        .map(|attrs| {
+            attrs
+                .iter()
+                .map(|(k, v)| (k.to_uppercase(), v.to_string()))
+                .collect()
+        })
+        .unwrap_or_default();
+    if attributes.is_empty() {
+        if ctype == "TEXT" {
+            out.raw("(\"CHARSET\" \"US-ASCII\")");
+        } else {
+            out.raw("NIL");
+        }
+    } else {
+        out.raw("(");
+        for (i, (k, v)) in attributes.iter().enumerate() {
+            if i > 0 {
+                out.raw(" ");
+            }
+            out.string(k.as_bytes()).raw(" ").string(v.as_bytes());
+        }
+        out.raw(")");
+    }
+    out.raw(" ");
+    out.nstring(part.content_id()).raw(" ");
+    out.nstring(part.content_description()).raw(" ");
+    let encoding = part
+        .content_transfer_encoding()
+        .unwrap_or("7BIT")
+        .to_uppercase();
+    out.string(encoding.as_bytes())
+        .raw(&format!(" {}", body.len()));
+    if part.is_message() {
+        let nested = parse(body);
+        out.raw(" ");
+        envelope(out, &nested);
+        out.raw(" ");
+        body_structure(out, body);
+        out.raw(&format!(" {}", lines(body)));
+    } else if ctype == "TEXT" {
+        out.raw(&format!(" {}", lines(body)));
+    }
+    out.raw(")");
+}
+
+fn lines(data: &[u8]) -> usize {
+    data.iter().filter(|b| **b == b'\n').count()
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::StreamExt;
+
+    use super::*;
+    use crate::cfg;
+
+    #[test]
+    fn t_tokenize() {
+        assert_eq!(
+            vec![
+                Token::Atom("a1".into()),
+                Token::Atom("FETCH".into()),
+                Token::Atom("1:*".into()),
+                Token::Open,
+                Token::Atom("UID".into()),
+                Token::Atom("BODY.PEEK[HEADER.FIELDS (FROM TO)]<0.100>".into()),
+                Token::Close,
+            ],
+            tokenize("a1 FETCH 1:* (UID BODY.PEEK[HEADER.FIELDS (FROM TO)]<0.100>)")
+        );
+        assert_eq!(
+            vec![Token::Atom("a".into()), Token::Str("x \"y\"".into())],
+            tokenize(r#"a "x \"y\"""#)
+        );
+        assert_eq!(Some(("a LOGIN ", 3, false)), literal_len("a LOGIN {3}"));
+        assert_eq!(Some(("a LOGIN ", 3, true)), literal_len("a LOGIN {3+}"));
+        assert_eq!(None, literal_len("a LOGIN x"));
+    }
+
+    #[test]
+    fn t_list_matches() {
+        assert!(list_matches("*", "Accounts/work/INBOX"));
+        assert!(list_matches("%", "INBOX"));
+        assert!(!list_matches("%", "Accounts/work"));
+        assert!(list_matches("Accounts/%", "Accounts/work"));
+        assert!(list_matches("Years/20*", "Years/2021"));
+    }
+
+    #[test]
+    fn t_sections() {
+        let raw = b"\
+Subject: hi\r
+Content-Type: multipart/mixed; boundary=\"b\"\r
+\r
+--b\r
+Content-Type: text/plain\r
+\r
+one\r
+--b\r
+Content-Type: message/rfc822\r
+\r
+Subject: inner\r
+\r
+two\r
+--b--\r
+";
+        let section = |s: &str| {
+            Section::parse(s)
+                .unwrap()
+                .extract(raw)
+                .map(|d| String::from_utf8(d).unwrap())
+        };
+        assert_eq!(
+            Some("Subject: hi\r\n\r\n".to_string()),
+            section("HEADER.FIELDS (SUBJECT)")
+        );
+        assert_eq!(
+            Some("one".to_string()),
+            section("1").map(|s| s.trim_end().to_string())
+        );
+        assert_eq!(
+            Some("Content-Type: text/plain\r\n\r\n".to_string()),
+            section("1.MIME")
+        );
+        assert_eq!(
+            Some("Subject: inner\r\n\r\n".to_string()),
+            section("2.HEADER")
+        );
+        assert_eq!(
+            Some("two".to_string()),
+            section("2.TEXT").map(|s| s.trim_end().to_string())
+        );
+        assert_eq!(None, section("3"));
+    }
+
+    #[test]
+    fn t_fetch_item() {
+        assert_eq!(
+            FetchItem::Section {
+                section: Section {
+                    path: vec![],
+                    spec: SectionSpec::HeaderFields {
+                        not: false,
+                        names: vec!["SUBJECT".to_string()],
+                    },
+                },
+                label: "BODY[HEADER.FIELDS (SUBJECT)]".to_string(),
+                partial: None,
+            },
+            FetchItem::parse("body.peek[header.fields (subject)]").unwrap()
+        );
+        // Longer once uppercased.
+        let label = match FetchItem::parse("body[header.fields (\u{390})]") {
+            Ok(FetchItem::Section { label, .. }) => label,
+            other => panic!("{other:?}"),
+        };
+        assert_eq!(
+            format!("BODY[HEADER.FIELDS ({})]", "\u{390}".to_uppercase()),
+            label
+        );
+    }
+
+    #[tokio::test]
+    async fn t_serve() {
+        let cfg = cfg::Db {
+            file: tempfile::tempdir().unwrap().path().join("db"),
+        };
+        let db = Storage::connect(&cfg).await.unwrap();
+        let raw = "\
+From: Alice <alice@example.com>\r
+To: bob@example.com\r
+Date: Tue, 2 Mar 2021 09:30:00 -0500\r
+Subject: Hello\r
+Message-ID: <hello@example.com>\r
+\r
+Hi Bob\r
+";
+        let hash = db.store_msg(raw.as_bytes()).await.unwrap();
+        db.store_location(&hash, "work", "INBOX", 42).await.unwrap();
+        db.store_msg(b"Subject: Other\r\n\r\nBye\r\n")
+            .await
+            .unwrap();
+
+        let db = Arc::new(db);
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(serve_on(Arc::clone(&db), listener));
+
+        let tcp = TcpStream::connect(addr).await.unwrap();
+        let client = async_imap::Client::new(tcp);
+        let mut session = client
+            .login("any", "thing")
+            .await
+            .map_err(|(e, _)| e)
+            .unwrap();
+
+        let mut names: Vec<String> = session
+            .list(None, Some("*"))
+            .await
+            .unwrap()
+            .filter_map(|n| async { n.ok().map(|n| n.name().to_string()) })
+            .collect()
+            .await;
+        names.sort();
+        assert_eq!(
+            vec![
+                "Accounts",
+                "Accounts/work",
+                "Accounts/work/INBOX",
+                "INBOX",
+                "Years",
+                "Years/2021"
+            ],
+            names
+        );
+
+        let mailbox = session.examine("INBOX").await.unwrap();
+        assert_eq!(2, mailbox.exists);
+        let mailbox = session.examine("Accounts/work/INBOX").await.unwrap();
+        assert_eq!(1, mailbox.exists);
+
+        let fetches: Vec<_> = session
+            .uid_fetch(
+                "1:*",
+                "(UID FLAGS RFC822.SIZE ENVELOPE BODYSTRUCTURE BODY.PEEK[])",
+            )
+            .await
+            .unwrap()
+            .collect::<Vec<_>>()
+            .await
+            .into_iter()
+            .map(|f| f.unwrap())
+            .collect();
+        assert_eq!(1, fetches.len());
+        let fetch = &fetches[0];
+        assert_eq!(Some(1), fetch.uid);
+        assert_eq!(Some(raw.as_bytes()), fetch.body());
+        assert_eq!(Some(u32::try_from(raw.len()).unwrap()), fetch.size);
+        let envelope = fetch.envelope().unwrap();
+        assert_eq!(Some(&b"Hello"[..]), envelope.subject.as_deref());
+        assert!(fetch.bodystructure().is_some());
+
+        // Off the metadata alone.
+        let fetches: Vec<_> = session
+            .fetch("1", "(INTERNALDATE RFC822.SIZE)")
+            .await
+            .unwrap()
+            .collect::<Vec<_>>()
+            .await;
+        let fetch = fetches[0].as_ref().unwrap();
+        assert_eq!(
+            Some("2021-03-02T09:30:00-05:00".to_string()),
+            fetch.internal_date().map(|date| date.to_rfc3339())
+        );
+        assert_eq!(Some(u32::try_from(raw.len()).unwrap()), fetch.size);
+
+        session.examine("INBOX").await.unwrap();
+        let found =
+            session.search("FROM alice SINCE 1-Mar-2021").await.unwrap();
+        assert_eq!(HashSet::from([1]), found);
+        let found = session.uid_search("NOT SUBJECT hello").await.unwrap();
+        assert_eq!(HashSet::from([2]), found);
+
+        // UIDs stay put, and new messages get new ones.
+        db.store_msg(b"Subject: New\r\n\r\nHi\r\n").await.unwrap();
+        session.examine("INBOX").await.unwrap();
+        let found = session.uid_search("ALL").await.unwrap();
+        assert_eq!(HashSet::from([1, 2, 3]), found);
+        let found = session.uid_search("SUBJECT hello").await.unwrap();
+        assert_eq!(HashSet::from([1]), found);
+
+        session.logout().await.unwrap();
+    }
+}
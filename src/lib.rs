@@ -4,5 +4,13 @@ pub mod data;
 pub mod file;
 pub mod fs;
 pub mod hash;
+pub mod hook;
+pub mod http_server;
 pub mod imap;
+pub mod imap_server;
+pub mod output;
+pub mod pop3;
+pub mod progress;
+pub mod query;
+pub mod thread;
 pub mod tracing;
@@ -1,6 +1,7 @@
-use std::{env, path::PathBuf};
+use std::{env, path::PathBuf, process::ExitCode};
 
 use clap::Parser;
+use ma::output::Outcome;
 use tracing::{info_span, Instrument};
 
 #[derive(Parser, Debug)]
@@ -10,6 +11,19 @@ struct Cli {
     #[clap(short, long, default_value = ".")]
     dir: PathBuf,
 
+    /// Format of command results written to stdout.
+    #[clap(short, long, global = true, value_enum, default_value_t)]
+    output: ma::output::Format,
+
+    /// How to report progress on stderr. Defaults to bars on a terminal and
+    /// to plain lines otherwise, like under cron.
+    #[clap(long, global = true, value_enum)]
+    progress: Option<ma::progress::Mode>,
+
+    /// Report no progress. Same as --progress=none.
+    #[clap(long, global = true, conflicts_with = "progress")]
+    quiet: bool,
+
     #[clap(subcommand)]
     command: Cmd,
 }
@@ -17,42 +31,152 @@ struct Cli {
 #[derive(Debug, clap::Subcommand)]
 enum Cmd {
     /// Download all messages from all mailboxes from all accounts to database.
+    /// Exits with 2 if some accounts or mailboxes failed and 3 if all did.
     Fetch(ma::cmd::fetch::Cmd),
 
+    /// Summarize the latest fetch runs and any mailboxes that keep failing.
+    Status(ma::cmd::status::Cmd),
+
     /// Export fetched messages from database to git-inspired file tree.
     Export(ma::cmd::export::Cmd),
 
     /// Import exported messages from file tree to database.
     Import(ma::cmd::import::Cmd),
 
+    /// Upload archived messages to a mailbox of an IMAP account, skipping
+    /// those it already has. Resumes where an interrupted run stopped.
+    Restore(ma::cmd::restore::Cmd),
+
+    /// Copy an account's archived mailboxes and messages to another account,
+    /// then verify the copy. Resumes where an interrupted run stopped.
+    Migrate(ma::cmd::migrate::Cmd),
+
+    /// Re-derive parsed tables (headers, parts, addresses, etc.) from the
+    /// raw messages already in the database.
+    Reindex(ma::cmd::reindex::Cmd),
+
+    /// Print hashes of messages matching a query.
+    Find(ma::cmd::find::Cmd),
+
+    /// Write the raw message to stdout.
+    Cat(ma::cmd::cat::Cmd),
+
+    /// Print the decoded headers, text body and attachment list of a message.
+    Show(ma::cmd::show::Cmd),
+
+    /// Print the conversation thread containing the given message.
+    Thread(ma::cmd::thread::Cmd),
+
+    /// Report messages stored more than once, under different raw bytes.
+    Dedup(ma::cmd::dedup::Cmd),
+
     /// Experimental analyses.
     Analyze(ma::cmd::analyze::Cmd),
+
+    /// List or extract attachments stored apart from their messages.
+    Attachments(ma::cmd::attachments::Cmd),
+
+    /// Serve a web UI for browsing and searching the archive.
+    ServeHttp(ma::cmd::serve_http::Cmd),
+
+    /// Serve the archive, read-only, to mail clients over IMAP.
+    ServeImap(ma::cmd::serve_imap::Cmd),
 }
 
 #[tokio::main]
-async fn main() -> anyhow::Result<()> {
+async fn main() -> anyhow::Result<ExitCode> {
     human_panic_setup();
     let cli = Cli::parse();
+    ma::progress::init(if cli.quiet {
+        Some(ma::progress::Mode::None)
+    } else {
+        cli.progress
+    });
     env::set_current_dir(&cli.dir)?;
     let _logger_guard = ma::tracing::init().await?;
     tracing::info!(pwd = ?env::current_dir()?, ?cli, "Start");
     let cfg = ma::cfg::Cfg::read_or_init().await?;
     tracing::info!(?cfg, "Config");
-    match cli.command {
+    let outcome = match cli.command {
         Cmd::Fetch(cmd) => {
-            cmd.run(&cfg).instrument(info_span!("fetch")).await?;
+            let output =
+                cmd.run(&cfg).instrument(info_span!("fetch")).await?;
+            cli.output.print(&output)?
+        }
+        Cmd::Status(cmd) => {
+            let output =
+                cmd.run(&cfg).instrument(info_span!("status")).await?;
+            cli.output.print(&output)?
         }
         Cmd::Export(cmd) => {
-            cmd.run(&cfg).instrument(info_span!("export")).await?;
+            let output =
+                cmd.run(&cfg).instrument(info_span!("export")).await?;
+            cli.output.print(&output)?
         }
         Cmd::Import(cmd) => {
-            cmd.run(&cfg).instrument(info_span!("import")).await?;
+            let output =
+                cmd.run(&cfg).instrument(info_span!("import")).await?;
+            cli.output.print(&output)?
+        }
+        Cmd::Restore(cmd) => {
+            let output =
+                cmd.run(&cfg).instrument(info_span!("restore")).await?;
+            cli.output.print(&output)?
+        }
+        Cmd::Migrate(cmd) => {
+            let output =
+                cmd.run(&cfg).instrument(info_span!("migrate")).await?;
+            cli.output.print(&output)?
+        }
+        Cmd::Reindex(cmd) => {
+            let output =
+                cmd.run(&cfg).instrument(info_span!("reindex")).await?;
+            cli.output.print(&output)?
+        }
+        Cmd::Find(cmd) => {
+            let output = cmd.run(&cfg).instrument(info_span!("find")).await?;
+            cli.output.print(&output)?
+        }
+        Cmd::Cat(cmd) => {
+            let output = cmd.run(&cfg).instrument(info_span!("cat")).await?;
+            cli.output.print(&output)?
+        }
+        Cmd::Show(cmd) => {
+            let output = cmd.run(&cfg).instrument(info_span!("show")).await?;
+            cli.output.print(&output)?
+        }
+        Cmd::Thread(cmd) => {
+            let output =
+                cmd.run(&cfg).instrument(info_span!("thread")).await?;
+            cli.output.print(&output)?
+        }
+        Cmd::Dedup(cmd) => {
+            let output =
+                cmd.run(&cfg).instrument(info_span!("dedup")).await?;
+            cli.output.print(&output)?
         }
         Cmd::Analyze(cmd) => {
-            cmd.run(&cfg).instrument(info_span!("analyze")).await?;
+            let output =
+                cmd.run(&cfg).instrument(info_span!("analyze")).await?;
+            cli.output.print(&output)?
         }
-    }
-    Ok(())
+        Cmd::Attachments(cmd) => {
+            let output =
+                cmd.run(&cfg).instrument(info_span!("attachments")).await?;
+            cli.output.print(&output)?
+        }
+        Cmd::ServeHttp(cmd) => {
+            cmd.run(&cfg).instrument(info_span!("serve_http")).await?;
+            Outcome::Success
+        }
+        Cmd::ServeImap(cmd) => {
+            cmd.run(&cfg).instrument(info_span!("serve_imap")).await?;
+            Outcome::Success
+        }
+    };
+    ma::progress::wait();
+    tracing::info!(?outcome, "End");
+    Ok(outcome.into())
 }
 
 fn human_panic_setup() {
@@ -0,0 +1,140 @@
+//! Command results, written either for people (text) or for programs
+//! (JSON), so that scripts don't need to scrape the text.
+
+use std::io::{self, Write};
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Format {
+    /// Human-oriented, as it always was.
+    #[default]
+    Text,
+
+    /// One pretty-printed JSON document.
+    Json,
+
+    /// One compact JSON value per line: each element when the result is a
+    /// list, otherwise the whole result.
+    Jsonl,
+}
+
+/// How a command went, beyond merely not erroring-out, which is reflected
+/// in the process exit status, for the likes of cron and systemd.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    Success,
+    /// Some of the work failed, like some of the accounts or mailboxes.
+    PartialFailure,
+    /// All of the work failed.
+    TotalFailure,
+}
+
+impl Outcome {
+    /// 1 is left to errors that stop the command outright (anyhow's).
+    #[must_use]
+    pub fn exit_code(self) -> u8 {
+        match self {
+            Self::Success => 0,
+            Self::PartialFailure => 2,
+            Self::TotalFailure => 3,
+        }
+    }
+}
+
+impl From<Outcome> for std::process::ExitCode {
+    fn from(outcome: Outcome) -> Self {
+        Self::from(outcome.exit_code())
+    }
+}
+
+/// A command result.
+pub trait Output: serde::Serialize {
+    fn write_text(&self, w: &mut dyn Write) -> io::Result<()>;
+
+    fn outcome(&self) -> Outcome {
+        Outcome::Success
+    }
+}
+
+impl Output for () {
+    fn write_text(&self, _: &mut dyn Write) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Format {
+    pub fn print<O: Output>(self, output: &O) -> anyhow::Result<Outcome> {
+        let mut stdout = io::stdout().lock();
+        self.write(&mut stdout, output)?;
+        stdout.flush()?;
+        Ok(output.outcome())
+    }
+
+    pub fn write<W: Write, O: Output>(
+        self,
+        mut w: W,
+        output: &O,
+    ) -> anyhow::Result<()> {
+        match self {
+            Self::Text => {
+                output.write_text(&mut w)?;
+            }
+            Self::Json => {
+                serde_json::to_writer_pretty(&mut w, output)?;
+                writeln!(w)?;
+            }
+            Self::Jsonl => match serde_json::to_value(output)? {
+                serde_json::Value::Array(items) => {
+                    for item in items {
+                        serde_json::to_writer(&mut w, &item)?;
+                        writeln!(w)?;
+                    }
+                }
+                value => {
+                    serde_json::to_writer(&mut w, &value)?;
+                    writeln!(w)?;
+                }
+            },
+        }
+        Ok(())
+    }
+}
+
+/// Serialize bytes as a string, replacing invalid UTF-8, for raw messages
+/// which are almost, but not always, text.
+pub fn lossy<S: serde::Serializer>(
+    bytes: &[u8],
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&String::from_utf8_lossy(bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(serde::Serialize)]
+    #[serde(transparent)]
+    struct Lines(Vec<String>);
+
+    impl Output for Lines {
+        fn write_text(&self, w: &mut dyn Write) -> io::Result<()> {
+            for line in &self.0 {
+                writeln!(w, "{line}")?;
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn t_formats() {
+        let lines = Lines(vec!["a".to_string(), "b".to_string()]);
+        let write = |format: Format| {
+            let mut buf = Vec::new();
+            format.write(&mut buf, &lines).unwrap();
+            String::from_utf8(buf).unwrap()
+        };
+        assert_eq!("a\nb\n", write(Format::Text));
+        assert_eq!("[\n  \"a\",\n  \"b\"\n]\n", write(Format::Json));
+        assert_eq!("\"a\"\n\"b\"\n", write(Format::Jsonl));
+    }
+}
@@ -0,0 +1,198 @@
+//! POP3 client, for accounts which don't speak IMAP: just enough to list
+//! the maildrop's messages by their unique-ids and download them.
+
+use std::result;
+
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::TcpStream,
+};
+
+use crate::{
+    cfg,
+    imap::{self, Io},
+};
+
+#[cfg(test)]
+pub(crate) mod stand_in;
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("Server said: {0}")]
+    Err(String),
+
+    #[error("Unexpected response: {0:?}")]
+    Unexpected(String),
+
+    #[error("ConnectionLost")]
+    ConnectionLost,
+
+    #[error("IO error: {0:?}")]
+    Io(#[from] std::io::Error),
+}
+
+pub type Result<T> = result::Result<T, Error>;
+
+pub struct Session {
+    stream: BufReader<Box<dyn Io>>,
+}
+
+impl Session {
+    pub async fn new(account: &cfg::Pop3Account) -> Result<Self> {
+        let stream = connect(account).await?;
+        Self::login(stream, account).await
+    }
+
+    /// Log in over an already established connection.
+    pub(crate) async fn login(
+        stream: Box<dyn Io>,
+        account: &cfg::Pop3Account,
+    ) -> Result<Self> {
+        let mut session = Self {
+            stream: BufReader::new(stream),
+        };
+        session.read_status().await?;
+        session.command(&format!("USER {}", account.user)).await?;
+        session.command(&format!("PASS {}", account.pass)).await?;
+        tracing::debug!(?account, "New POP3 session.");
+        Ok(session)
+    }
+
+    /// Number of messages in the maildrop and their total size.
+    pub async fn stat(&mut self) -> Result<(u32, u64)> {
+        let status = self.command("STAT").await?;
+        let mut fields = status.split_whitespace();
+        let count = fields.next().and_then(|count| count.parse().ok());
+        let size = fields.next().and_then(|size| size.parse().ok());
+        count.zip(size).ok_or(Error::Unexpected(status))
+    }
+
+    /// Message numbers, in order, with the messages' unique-ids, which,
+    /// unlike the numbers, persist across sessions.
+    pub async fn uidl(&mut self) -> Result<Vec<(u32, String)>> {
+        self.command("UIDL").await?;
+        let mut uids = Vec::new();
+        for line in self.read_multi_line().await? {
+            let line = String::from_utf8_lossy(&line);
+            let line = line.trim_end();
+            let parsed = line.split_once(' ').and_then(|(n, uid)| {
+                Some((n.parse().ok()?, uid.trim().to_string()))
+            });
+            uids.push(parsed.ok_or_else(|| Error::Unexpected(line.into()))?);
+        }
+        Ok(uids)
+    }
+
+    /// The whole message, as is, other than the transfer's dot-stuffing.
+    pub async fn retr(&mut self, msg: u32) -> Result<Vec<u8>> {
+        self.command(&format!("RETR {msg}")).await?;
+        Ok(self.read_multi_line().await?.concat())
+    }
+
+    pub async fn quit(mut self) -> Result<()> {
+        self.command("QUIT").await?;
+        Ok(())
+    }
+
+    /// Send the command and read its status line, returning the text after
+    /// "+OK".
+    async fn command(&mut self, command: &str) -> Result<String> {
+        let name = command.split(' ').next().unwrap_or_default();
+        tracing::trace!(command = name, "Sending.");
+        let stream = self.stream.get_mut();
+        stream.write_all(command.as_bytes()).await?;
+        stream.write_all(b"\r\n").await?;
+        stream.flush().await?;
+        self.read_status().await
+    }
+
+    async fn read_status(&mut self) -> Result<String> {
+        let line = self.read_line().await?;
+        let line = String::from_utf8_lossy(&line);
+        let line = line.trim_end();
+        if let Some(text) = line.strip_prefix("+OK") {
+            Ok(text.trim().to_string())
+        } else if let Some(text) = line.strip_prefix("-ERR") {
+            Err(Error::Err(text.trim().to_string()))
+        } else {
+            Err(Error::Unexpected(line.to_string()))
+        }
+    }
+
+    /// Lines, with their line ends, up to the terminating ".", which is
+    /// dropped, as are the dots stuffed in front of lines starting with one.
+    async fn read_multi_line(&mut self) -> Result<Vec<Vec<u8>>> {
+        let mut lines = Vec::new();
+        loop {
+            let line = self.read_line().await?;
+            if line == b".\r\n" || line == b".\n" {
+                return Ok(lines);
+            }
+            match line.strip_prefix(b".") {
+                Some(unstuffed) => lines.push(unstuffed.to_vec()),
+                None => lines.push(line),
+            }
+        }
+    }
+
+    async fn read_line(&mut self) -> Result<Vec<u8>> {
+        let mut line = Vec::new();
+        if self.stream.read_until(b'\n', &mut line).await? == 0 {
+            return Err(Error::ConnectionLost);
+        }
+        Ok(line)
+    }
+}
+
+#[tracing::instrument]
+async fn connect(account: &cfg::Pop3Account) -> Result<Box<dyn Io>> {
+    let cfg::Pop3Account {
+        addr,
+        port,
+        user: _,
+        pass: _,
+    } = account;
+    tracing::debug!("Connecting ...");
+    let tcp = TcpStream::connect((addr.as_str(), *port)).await?;
+    tracing::debug!("Connected TCP.");
+    let tls = imap::tls_stream(addr, tcp).await?;
+    tracing::debug!("Connected TLS.");
+    Ok(Box::new(tls))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn t_session() {
+        let msgs = [
+            ("a1", "Subject: 1\r\n\r\nHi\r\n"),
+            ("b2", "Subject: 2\r\n\r\n.Dotted\r\n..\r\n"),
+        ];
+        let server = stand_in::StandIn::start(
+            msgs.iter()
+                .map(|(uid, raw)| stand_in::Msg::new(uid, raw.as_bytes()))
+                .collect(),
+        )
+        .await;
+        let mut session = server.connect().await;
+        let size: usize = msgs.iter().map(|(_, raw)| raw.len()).sum();
+        assert_eq!((2, size as u64), session.stat().await.unwrap());
+        assert_eq!(
+            vec![(1, "a1".to_string()), (2, "b2".to_string())],
+            session.uidl().await.unwrap()
+        );
+        assert_eq!(msgs[1].1.as_bytes(), &session.retr(2).await.unwrap()[..]);
+        assert!(matches!(session.retr(3).await, Err(Error::Err(_))));
+        assert_eq!(msgs[0].1.as_bytes(), &session.retr(1).await.unwrap()[..]);
+        session.quit().await.unwrap();
+        assert_eq!(
+            vec![
+                "USER", "PASS", "STAT", "UIDL", "RETR", "RETR", "RETR",
+                "QUIT"
+            ],
+            server.commands()
+        );
+    }
+}
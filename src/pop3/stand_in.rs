@@ -0,0 +1,150 @@
+//! In-memory POP3 server standing in for a provider's in tests. Records the
+//! name of each command it gets, for tests to check what was asked of it.
+
+use std::{
+    net::SocketAddr,
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::{TcpListener, TcpStream},
+};
+
+use crate::{cfg, pop3::Session};
+
+#[derive(Debug, Clone)]
+pub(crate) struct Msg {
+    pub uid: String,
+    pub raw: Vec<u8>,
+}
+
+impl Msg {
+    pub fn new(uid: &str, raw: &[u8]) -> Self {
+        Self {
+            uid: uid.to_string(),
+            raw: raw.to_vec(),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct State {
+    /// The maildrop, in message number order.
+    pub msgs: Vec<Msg>,
+
+    /// Name of each command received.
+    pub commands: Vec<String>,
+}
+
+#[derive(Clone)]
+pub(crate) struct StandIn {
+    pub addr: SocketAddr,
+    state: Arc<Mutex<State>>,
+}
+
+impl StandIn {
+    pub async fn start(msgs: Vec<Msg>) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let state = Arc::new(Mutex::new(State {
+            msgs,
+            ..State::default()
+        }));
+        let stand_in = Self { addr, state };
+        let server = stand_in.clone();
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                let server = server.clone();
+                tokio::spawn(async move {
+                    if let Err(error) = server.session(stream).await {
+                        tracing::error!(?error, "Stand-in session failed.");
+                    }
+                });
+            }
+        });
+        stand_in
+    }
+
+    pub fn state(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap()
+    }
+
+    pub fn commands(&self) -> Vec<String> {
+        self.state().commands.clone()
+    }
+
+    pub fn account(&self) -> cfg::Pop3Account {
+        cfg::Pop3Account {
+            addr: self.addr.ip().to_string(),
+            port: self.addr.port(),
+            user: "user".to_string(),
+            pass: "pass".to_string(),
+        }
+    }
+
+    /// A logged-in client session.
+    pub async fn connect(&self) -> Session {
+        let tcp = TcpStream::connect(self.addr).await.unwrap();
+        Session::login(Box::new(tcp), &self.account())
+            .await
+            .unwrap()
+    }
+
+    async fn session(&self, stream: TcpStream) -> anyhow::Result<()> {
+        let (reader, mut writer) = stream.into_split();
+        let mut reader = BufReader::new(reader);
+        writer.write_all(b"+OK Stand-in ready\r\n").await?;
+        let mut line = String::new();
+        while reader.read_line(&mut line).await? > 0 {
+            let mut words = line.split_whitespace();
+            let command = words.next().unwrap_or_default().to_uppercase();
+            let arg: Option<usize> =
+                words.next().and_then(|a| a.parse().ok());
+            self.state().commands.push(command.clone());
+            let out = self.dispatch(&command, arg);
+            writer.write_all(&out).await?;
+            writer.flush().await?;
+            if command == "QUIT" {
+                break;
+            }
+            line.clear();
+        }
+        Ok(())
+    }
+
+    fn dispatch(&self, command: &str, arg: Option<usize>) -> Vec<u8> {
+        let state = self.state();
+        let msg = arg.and_then(|n| state.msgs.get(n.checked_sub(1)?));
+        match (command, msg) {
+            ("USER" | "PASS" | "QUIT", _) => b"+OK\r\n".to_vec(),
+            ("STAT", _) => {
+                let size: usize =
+                    state.msgs.iter().map(|m| m.raw.len()).sum();
+                format!("+OK {} {size}\r\n", state.msgs.len()).into_bytes()
+            }
+            ("UIDL", _) => {
+                let mut out = b"+OK\r\n".to_vec();
+                for (i, msg) in state.msgs.iter().enumerate() {
+                    out.extend(format!("{} {}\r\n", i + 1, msg.uid).bytes());
+                }
+                out.extend(b".\r\n");
+                out
+            }
+            ("RETR", Some(msg)) => {
+                let mut out = b"+OK\r\n".to_vec();
+                for line in msg.raw.split_inclusive(|b| *b == b'\n') {
+                    if line.starts_with(b".") {
+                        out.push(b'.');
+                    }
+                    out.extend(line);
+                }
+                out.extend(b".\r\n");
+                out
+            }
+            ("RETR", None) => b"-ERR No such message\r\n".to_vec(),
+            _ => b"-ERR Unsupported command\r\n".to_vec(),
+        }
+    }
+}
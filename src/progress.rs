@@ -0,0 +1,163 @@
+//! How, if at all, long-running commands report their progress.
+//!
+//! Bars are only good on a terminal; under cron or CI their redraws garble
+//! the captured output, so there we default to plain lines, printed
+//! periodically, which read fine in logs.
+
+use std::{
+    io::{self, IsTerminal},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex, OnceLock,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use indicatif::{MultiProgress, ProgressBar, ProgressDrawTarget};
+
+const PLAIN_INTERVAL: Duration = Duration::from_secs(10);
+const PLAIN_POLL: Duration = Duration::from_millis(100);
+
+static MODE: OnceLock<Mode> = OnceLock::new();
+static REPORTERS: Mutex<Vec<JoinHandle<()>>> = Mutex::new(Vec::new());
+static STOPPING: AtomicBool = AtomicBool::new(false);
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    /// A line per bar every few seconds and when it finishes.
+    Plain,
+
+    /// Interactive bars.
+    Bars,
+
+    /// Nothing.
+    None,
+}
+
+impl Mode {
+    /// Bars on a terminal, plain lines otherwise.
+    #[must_use]
+    pub fn detect() -> Self {
+        if io::stderr().is_terminal() {
+            Self::Bars
+        } else {
+            Self::Plain
+        }
+    }
+}
+
+/// Set the mode for the rest of the process. Only the first call counts.
+pub fn init(mode: Option<Mode>) {
+    let _ = MODE.set(mode.unwrap_or_else(Mode::detect));
+}
+
+#[must_use]
+pub fn mode() -> Mode {
+    *MODE.get_or_init(Mode::detect)
+}
+
+/// A group of bars, for the likes of concurrently fetched accounts. Hidden
+/// unless bars are on, since the group would otherwise draw its members.
+#[must_use]
+pub fn multi() -> MultiProgress {
+    match mode() {
+        Mode::Bars => MultiProgress::new(),
+        Mode::Plain | Mode::None => {
+            MultiProgress::with_draw_target(ProgressDrawTarget::hidden())
+        }
+    }
+}
+
+/// A bar as per the current mode, with `label` prefixed to its plain lines.
+#[must_use]
+pub fn bar(len: u64, label: &str) -> ProgressBar {
+    match mode() {
+        Mode::Bars => ProgressBar::new(len),
+        Mode::None => ProgressBar::hidden(),
+        Mode::Plain => {
+            let bar = ProgressBar::hidden();
+            bar.set_length(len);
+            report(&bar, label.to_string());
+            bar
+        }
+    }
+}
+
+/// Print the bar's state to stderr, periodically, until it is finished or
+/// until `wait` is called.
+fn report(bar: &ProgressBar, label: String) {
+    let bar = bar.clone();
+    let started = Instant::now();
+    let reporter = thread::spawn(move || {
+        let mut reported = Instant::now();
+        loop {
+            thread::sleep(PLAIN_POLL);
+            let last = bar.is_finished() || STOPPING.load(Ordering::Relaxed);
+            if last || reported.elapsed() >= PLAIN_INTERVAL {
+                eprintln!("{}", line(&label, &bar, started.elapsed()));
+                reported = Instant::now();
+            }
+            if last {
+                break;
+            }
+        }
+    });
+    if let Ok(mut reporters) = REPORTERS.lock() {
+        reporters.push(reporter);
+    }
+}
+
+/// Have the plain reporters print their last lines and wait for them to,
+/// so that those aren't lost when the process exits.
+pub fn wait() {
+    STOPPING.store(true, Ordering::Relaxed);
+    let reporters = match REPORTERS.lock() {
+        Ok(mut reporters) => std::mem::take(&mut *reporters),
+        Err(_) => return,
+    };
+    for reporter in reporters {
+        let _ = reporter.join();
+    }
+}
+
+fn line(label: &str, bar: &ProgressBar, elapsed: Duration) -> String {
+    let pos = bar.position();
+    let len = bar.length().map_or("?".to_string(), |len| len.to_string());
+    let state = if bar.is_finished() {
+        "done"
+    } else {
+        "progress"
+    };
+    let msg = bar.message();
+    let msg = console::strip_ansi_codes(&msg);
+    let secs = elapsed.as_secs();
+    let mut line = format!("{state} {label} {pos}/{len} {secs}s");
+    if !msg.is_empty() {
+        line.push(' ');
+        line.push_str(&msg);
+    }
+    line
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn t_line() {
+        let bar = ProgressBar::hidden();
+        bar.set_length(10);
+        bar.inc(3);
+        bar.set_message(format!("{}", console::style("\"a\"").red()));
+        assert_eq!(
+            "progress fetch 3/10 5s \"a\"",
+            line("fetch", &bar, Duration::from_secs(5))
+        );
+        bar.finish();
+        assert_eq!(
+            "done fetch 10/10 5s \"a\"",
+            line("fetch", &bar, Duration::from_secs(5))
+        );
+    }
+}
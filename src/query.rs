@@ -0,0 +1,434 @@
+//! A small query language for selecting messages, like:
+//!
+//! ```text
+//! from:alice subject:"quarterly report" date:2021 has:attachment
+//! (to:bob OR cc:bob) -mailbox:Spam size>1M
+//! ```
+//!
+//! Terms are ANDed, unless separated by OR. A term is negated by a leading
+//! "-" or NOT. Bare words match the subject or the text body.
+//!
+//! Keys:
+//! - from:, to: (To, Cc or Bcc), cc:, bcc: -- substring of address or name
+//! - subject: -- substring
+//! - account:, mailbox: -- where it was fetched from, "*" and "?" wildcards.
+//!   A POP3 account's maildrop is INBOX
+//! - date:, after:, before: -- YYYY, YYYY-MM or YYYY-MM-DD, in UTC.
+//!   date: also takes ranges, like 2020-06..2021-03
+//! - has:attachment
+//! - size>N, size<N -- raw bytes, with optional K, M or G suffix
+
+use std::result;
+
+#[derive(thiserror::Error, Debug, PartialEq, Eq)]
+pub enum Error {
+    #[error("Empty query")]
+    Empty,
+
+    #[error("Unexpected end of query")]
+    UnexpectedEnd,
+
+    #[error("Unbalanced parentheses")]
+    UnbalancedParens,
+
+    #[error("Unterminated quote")]
+    UnterminatedQuote,
+
+    #[error("Unknown key: {0:?}")]
+    UnknownKey(String),
+
+    #[error("Invalid value for {key:?}: {value:?}")]
+    InvalidValue { key: String, value: String },
+}
+
+pub type Result<T> = result::Result<T, Error>;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Query {
+    And(Vec<Query>),
+    Or(Vec<Query>),
+    Not(Box<Query>),
+    Term(Term),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Term {
+    From(String),
+    To(String),
+    Cc(String),
+    Bcc(String),
+    Subject(String),
+    Text(String),
+    Account(String),
+    Mailbox(String),
+    HasAttachment,
+    SizeAbove(u64),
+    SizeBelow(u64),
+    /// Unix time, inclusive.
+    DateFrom(i64),
+    /// Unix time, exclusive.
+    DateUntil(i64),
+}
+
+impl std::str::FromStr for Query {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Self::parse(s)
+    }
+}
+
+impl Query {
+    pub fn parse(input: &str) -> Result<Self> {
+        let tokens = tokenize(input)?;
+        if tokens.is_empty() {
+            return Err(Error::Empty);
+        }
+        let mut parser = Parser { tokens, pos: 0 };
+        let query = parser.or()?;
+        match parser.tokens.get(parser.pos) {
+            None => Ok(query),
+            Some(Token::RParen) => Err(Error::UnbalancedParens),
+            Some(_) => Err(Error::UnexpectedEnd),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    LParen,
+    RParen,
+    Not,
+    Or,
+    Word(String),
+}
+
+fn tokenize(input: &str) -> Result<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let mut chars = input.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        match c {
+            c if c.is_whitespace() => {
+                chars.next();
+            }
+            '(' => {
+                chars.next();
+                tokens.push(Token::LParen);
+            }
+            ')' => {
+                chars.next();
+                tokens.push(Token::RParen);
+            }
+            '-' => {
+                chars.next();
+                tokens.push(Token::Not);
+            }
+            _ => {
+                let mut word = String::new();
+                while let Some(&c) = chars.peek() {
+                    match c {
+                        '"' => {
+                            chars.next();
+                            loop {
+                                match chars.next() {
+                                    None => {
+                                        return Err(Error::UnterminatedQuote)
+                                    }
+                                    Some('"') => break,
+                                    Some(c) => word.push(c),
+                                }
+                            }
+                        }
+                        c if c.is_whitespace() || c == '(' || c == ')' => {
+                            break;
+                        }
+                        c => {
+                            chars.next();
+                            word.push(c);
+                        }
+                    }
+                }
+                tokens.push(match word.as_str() {
+                    "OR" => Token::Or,
+                    "NOT" => Token::Not,
+                    _ => Token::Word(word),
+                });
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn or(&mut self) -> Result<Query> {
+        let mut alternatives = vec![self.and()?];
+        while let Some(Token::Or) = self.peek() {
+            self.next();
+            alternatives.push(self.and()?);
+        }
+        Ok(simplify(alternatives, Query::Or))
+    }
+
+    fn and(&mut self) -> Result<Query> {
+        let mut conjuncts = Vec::new();
+        while let Some(token) = self.peek() {
+            if matches!(token, Token::Or | Token::RParen) {
+                break;
+            }
+            conjuncts.push(self.unary()?);
+        }
+        if conjuncts.is_empty() {
+            return Err(Error::UnexpectedEnd);
+        }
+        Ok(simplify(conjuncts, Query::And))
+    }
+
+    fn unary(&mut self) -> Result<Query> {
+        match self.next() {
+            None => Err(Error::UnexpectedEnd),
+            Some(Token::Not) => Ok(Query::Not(Box::new(self.unary()?))),
+            Some(Token::LParen) => {
+                let query = self.or()?;
+                match self.next() {
+                    Some(Token::RParen) => Ok(query),
+                    _ => Err(Error::UnbalancedParens),
+                }
+            }
+            Some(Token::RParen) => Err(Error::UnbalancedParens),
+            Some(Token::Or) => Err(Error::UnexpectedEnd),
+            Some(Token::Word(word)) => term(&word),
+        }
+    }
+}
+
+fn simplify(
+    mut queries: Vec<Query>,
+    combine: fn(Vec<Query>) -> Query,
+) -> Query {
+    if queries.len() == 1 {
+        queries.remove(0)
+    } else {
+        combine(queries)
+    }
+}
+
+fn term(word: &str) -> Result<Query> {
+    let invalid = |key: &str, value: &str| Error::InvalidValue {
+        key: key.to_string(),
+        value: value.to_string(),
+    };
+    if let Some(size) = word.strip_prefix("size>") {
+        let size = parse_size(size).ok_or_else(|| invalid("size", size))?;
+        return Ok(Query::Term(Term::SizeAbove(size)));
+    }
+    if let Some(size) = word.strip_prefix("size<") {
+        let size = parse_size(size).ok_or_else(|| invalid("size", size))?;
+        return Ok(Query::Term(Term::SizeBelow(size)));
+    }
+    let Some((key, value)) = word.split_once(':') else {
+        return Ok(Query::Term(Term::Text(word.to_string())));
+    };
+    let value = value.to_string();
+    let term = match key {
+        "from" => Term::From(value),
+        "to" => Term::To(value),
+        "cc" => Term::Cc(value),
+        "bcc" => Term::Bcc(value),
+        "subject" => Term::Subject(value),
+        "account" => Term::Account(value),
+        "mailbox" => Term::Mailbox(value),
+        "has" if value == "attachment" => Term::HasAttachment,
+        "after" => Term::DateFrom(
+            date_range(&value).ok_or_else(|| invalid(key, &value))?.0,
+        ),
+        "before" => Term::DateUntil(
+            date_range(&value).ok_or_else(|| invalid(key, &value))?.0,
+        ),
+        "date" => {
+            let (lo, hi) = match value.split_once("..") {
+                None => date_range(&value),
+                Some((lo, hi)) => date_range(lo)
+                    .zip(date_range(hi))
+                    .map(|((lo, _), (_, hi))| (lo, hi)),
+            }
+            .ok_or_else(|| invalid(key, &value))?;
+            return Ok(Query::And(vec![
+                Query::Term(Term::DateFrom(lo)),
+                Query::Term(Term::DateUntil(hi)),
+            ]));
+        }
+        "has" => return Err(invalid(key, &value)),
+        _ => return Err(Error::UnknownKey(key.to_string())),
+    };
+    Ok(Query::Term(term))
+}
+
+fn parse_size(size: &str) -> Option<u64> {
+    let size = size.to_ascii_uppercase();
+    let (digits, multiplier) = match size.chars().last()? {
+        'K' => (&size[..size.len() - 1], 1 << 10),
+        'M' => (&size[..size.len() - 1], 1 << 20),
+        'G' => (&size[..size.len() - 1], 1 << 30),
+        _ => (&size[..], 1),
+    };
+    digits.parse::<u64>().ok()?.checked_mul(multiplier)
+}
+
+/// Unix time range, [start, end), of a YYYY, YYYY-MM or YYYY-MM-DD date.
+fn date_range(date: &str) -> Option<(i64, i64)> {
+    let fields: Vec<&str> = date.split('-').collect();
+    let year: u16 = fields.first()?.parse().ok()?;
+    let month: Option<u8> = match fields.get(1) {
+        None => None,
+        Some(m) => Some(m.parse().ok().filter(|m| (1..=12).contains(m))?),
+    };
+    let day: Option<u8> = match fields.get(2) {
+        None => None,
+        Some(d) => Some(d.parse().ok().filter(|d| (1..=31).contains(d))?),
+    };
+    if fields.len() > 3 {
+        return None;
+    }
+    let timestamp = |year: u16, month: u8, day: u8| {
+        mail_parser::DateTime {
+            year,
+            month,
+            day,
+            hour: 0,
+            minute: 0,
+            second: 0,
+            tz_before_gmt: false,
+            tz_hour: 0,
+            tz_minute: 0,
+        }
+        .to_timestamp()
+    };
+    let range = match (month, day) {
+        (None, _) => {
+            (timestamp(year, 1, 1), timestamp(year.checked_add(1)?, 1, 1))
+        }
+        (Some(12), None) => (
+            timestamp(year, 12, 1),
+            timestamp(year.checked_add(1)?, 1, 1),
+        ),
+        (Some(month), None) => {
+            (timestamp(year, month, 1), timestamp(year, month + 1, 1))
+        }
+        (Some(month), Some(day)) => {
+            let start = timestamp(year, month, day);
+            (start, start + 24 * 60 * 60)
+        }
+    };
+    Some(range)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn t(term: Term) -> Query {
+        Query::Term(term)
+    }
+
+    #[test]
+    fn t_parse() {
+        assert_eq!(
+            Ok(t(Term::From("alice".into()))),
+            Query::parse("from:alice")
+        );
+        assert_eq!(
+            Ok(Query::And(vec![
+                t(Term::Subject("quarterly report".into())),
+                t(Term::HasAttachment),
+            ])),
+            Query::parse("subject:\"quarterly report\" has:attachment")
+        );
+        assert_eq!(
+            Ok(Query::And(vec![
+                Query::Or(vec![
+                    t(Term::To("bob".into())),
+                    t(Term::Cc("bob".into())),
+                ]),
+                Query::Not(Box::new(t(Term::Mailbox("Spam".into())))),
+                t(Term::SizeAbove(1 << 20)),
+                t(Term::Text("hello".into())),
+            ])),
+            Query::parse("(to:bob OR cc:bob) -mailbox:Spam size>1M hello")
+        );
+        assert_eq!(
+            Ok(Query::Not(Box::new(t(Term::Account("work".into()))))),
+            Query::parse("NOT account:work")
+        );
+    }
+
+    #[test]
+    fn t_parse_dates() {
+        assert_eq!(
+            Ok(Query::And(vec![
+                t(Term::DateFrom(1_609_459_200)), // 2021-01-01
+                t(Term::DateUntil(1_640_995_200)), // 2022-01-01
+            ])),
+            Query::parse("date:2021")
+        );
+        assert_eq!(
+            Ok(Query::And(vec![
+                t(Term::DateFrom(1_638_316_800)), // 2021-12-01
+                t(Term::DateUntil(1_640_995_200)), // 2022-01-01
+            ])),
+            Query::parse("date:2021-12")
+        );
+        assert_eq!(
+            Ok(Query::And(vec![
+                t(Term::DateFrom(1_590_969_600)), // 2020-06-01
+                t(Term::DateUntil(1_614_643_200)), // 2021-03-02
+            ])),
+            Query::parse("date:2020-06..2021-03-01")
+        );
+        assert_eq!(
+            Ok(t(Term::DateUntil(1_614_643_200))),
+            Query::parse("before:2021-03-02")
+        );
+    }
+
+    #[test]
+    fn t_parse_errors() {
+        assert_eq!(Err(Error::Empty), Query::parse("  "));
+        assert_eq!(Err(Error::UnbalancedParens), Query::parse("(a"));
+        assert_eq!(Err(Error::UnbalancedParens), Query::parse("a)"));
+        assert_eq!(Err(Error::UnterminatedQuote), Query::parse("\"a"));
+        assert_eq!(Err(Error::UnexpectedEnd), Query::parse("a OR"));
+        assert_eq!(
+            Err(Error::UnknownKey("frmo".into())),
+            Query::parse("frmo:alice")
+        );
+        assert!(matches!(
+            Query::parse("date:2021-13"),
+            Err(Error::InvalidValue { .. })
+        ));
+        for date in ["date:65535", "date:65535-12", "after:65535"] {
+            assert!(matches!(
+                Query::parse(date),
+                Err(Error::InvalidValue { .. })
+            ));
+        }
+        assert!(matches!(
+            Query::parse("size>lots"),
+            Err(Error::InvalidValue { .. })
+        ));
+    }
+}
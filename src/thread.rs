@@ -0,0 +1,426 @@
+//! Conversation threading, after Jamie Zawinski's algorithm:
+//! <https://www.jwz.org/doc/threading.html>
+
+use std::collections::HashMap;
+
+/// What threading needs to know about a message.
+#[derive(Debug, Clone)]
+pub struct Msg {
+    pub hash: String,
+    pub message_id: Option<String>,
+    /// "References", oldest first, followed by "In-Reply-To",
+    /// as returned by [`references`].
+    pub references: Vec<String>,
+    pub subject: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Link {
+    pub hash: String,
+    pub thread_id: String,
+    pub parent_hash: Option<String>,
+}
+
+/// Extract message IDs (without angle brackets) from a header value like
+/// that of "References" or "In-Reply-To".
+#[must_use]
+pub fn msg_ids(header_value: &str) -> Vec<String> {
+    let mut ids = Vec::new();
+    let mut rest = header_value;
+    while let Some(lo) = rest.find('<') {
+        rest = &rest[lo + 1..];
+        match rest.find('>') {
+            None => break,
+            Some(hi) => {
+                let id = rest[..hi].trim();
+                if !id.is_empty() && !id.contains('<') {
+                    ids.push(id.to_string());
+                }
+                rest = &rest[hi + 1..];
+            }
+        }
+    }
+    ids
+}
+
+/// Combine "References" and "In-Reply-To" into a single ancestry chain,
+/// oldest first, with the direct parent last.
+#[must_use]
+pub fn references(
+    references: &[String],
+    in_reply_to: &[String],
+) -> Vec<String> {
+    let mut chain: Vec<String> = Vec::new();
+    for id in references {
+        if !chain.contains(id) {
+            chain.push(id.clone());
+        }
+    }
+    // References is authoritative, but some clients only set In-Reply-To:
+    if let Some(parent) = in_reply_to.first() {
+        if chain.last() != Some(parent) {
+            chain.retain(|id| id != parent);
+            chain.push(parent.clone());
+        }
+    }
+    chain
+}
+
+/// Subject with reply/forward markers and list tags removed, lowercased,
+/// and whether any reply/forward marker was found.
+#[must_use]
+pub fn subject_base(subject: &str) -> (String, bool) {
+    const MARKERS: [&str; 5] = ["re:", "fw:", "fwd:", "aw:", "sv:"];
+    let mut base = subject.trim().to_lowercase();
+    let mut is_reply = false;
+    loop {
+        let before = base.len();
+        if base.starts_with('[') {
+            if let Some(hi) = base.find(']') {
+                base = base[hi + 1..].trim_start().to_string();
+            }
+        }
+        for marker in MARKERS {
+            if let Some(rest) = base.strip_prefix(marker) {
+                base = rest.trim_start().to_string();
+                is_reply = true;
+            }
+        }
+        if base.len() == before {
+            break;
+        }
+    }
+    let base = base.split_whitespace().collect::<Vec<&str>>().join(" ");
+    (base, is_reply)
+}
+
+struct Container {
+    id: String,
+    msg: Option<usize>,
+    parent: Option<usize>,
+    children: Vec<usize>,
+}
+
+struct Tree {
+    containers: Vec<Container>,
+    by_id: HashMap<String, usize>,
+}
+
+impl Tree {
+    fn container(&mut self, id: &str) -> usize {
+        if let Some(c) = self.by_id.get(id) {
+            return *c;
+        }
+        let c = self.new_container(id.to_string());
+        self.by_id.insert(id.to_string(), c);
+        c
+    }
+
+    fn new_container(&mut self, id: String) -> usize {
+        self.containers.push(Container {
+            id,
+            msg: None,
+            parent: None,
+            children: Vec::new(),
+        });
+        self.containers.len() - 1
+    }
+
+    /// Is `a` an ancestor of (or same as) `b`?
+    fn is_ancestor(&self, a: usize, b: usize) -> bool {
+        let mut cur = Some(b);
+        while let Some(c) = cur {
+            if c == a {
+                return true;
+            }
+            cur = self.containers[c].parent;
+        }
+        false
+    }
+
+    fn unlink(&mut self, child: usize) {
+        if let Some(parent) = self.containers[child].parent.take() {
+            self.containers[parent].children.retain(|c| *c != child);
+        }
+    }
+
+    fn link(&mut self, parent: usize, child: usize) {
+        self.unlink(child);
+        self.containers[child].parent = Some(parent);
+        self.containers[parent].children.push(child);
+    }
+
+    fn roots(&self) -> Vec<usize> {
+        (0..self.containers.len())
+            .filter(|c| self.containers[*c].parent.is_none())
+            .collect()
+    }
+
+    /// Remove empty containers, promoting their children, except at the
+    /// root level where an empty container is kept to hold siblings together.
+    /// Returns what should replace the given container in its parent.
+    fn prune(&mut self, c: usize, is_root: bool) -> Vec<usize> {
+        let children = std::mem::take(&mut self.containers[c].children);
+        let mut kept = Vec::new();
+        for child in children {
+            kept.extend(self.prune(child, false));
+        }
+        if self.containers[c].msg.is_none()
+            && (kept.is_empty() || !is_root || kept.len() == 1)
+        {
+            for child in &kept {
+                self.containers[*child].parent = None;
+            }
+            return kept;
+        }
+        for child in &kept {
+            self.containers[*child].parent = Some(c);
+        }
+        self.containers[c].children = kept;
+        vec![c]
+    }
+
+    fn subject(&self, c: usize, msgs: &[Msg]) -> Option<(String, bool)> {
+        let container = &self.containers[c];
+        let msg = container.msg.or_else(|| {
+            container
+                .children
+                .first()
+                .and_then(|child| self.containers[*child].msg)
+        })?;
+        msgs[msg]
+            .subject
+            .as_deref()
+            .map(subject_base)
+            .filter(|(base, _)| !base.is_empty())
+    }
+}
+
+/// Thread the given messages. Each one gets a link to its parent, if any
+/// could be found, and the ID of its thread, which is the ID of the root
+/// (possibly missing) message.
+#[must_use]
+pub fn jwz(msgs: &[Msg]) -> Vec<Link> {
+    let mut tree = Tree {
+        containers: Vec::new(),
+        by_id: HashMap::new(),
+    };
+
+    // 1. Containers for messages and everything they reference.
+    for (i, msg) in msgs.iter().enumerate() {
+        let c = match &msg.message_id {
+            Some(id) => {
+                let c = tree.container(id);
+                if tree.containers[c].msg.is_some() {
+                    // Duplicate ID. Keep both, but thread only the first.
+                    tree.container(&msg.hash)
+                } else {
+                    c
+                }
+            }
+            None => tree.container(&msg.hash),
+        };
+        tree.containers[c].msg = Some(i);
+
+        let mut prev: Option<usize> = None;
+        for id in &msg.references {
+            let r = tree.container(id);
+            if let Some(p) = prev {
+                if tree.containers[r].parent.is_none()
+                    && !tree.is_ancestor(r, p)
+                {
+                    tree.link(p, r);
+                }
+            }
+            prev = Some(r);
+        }
+        // The message itself knows its parent better than its descendants
+        // guessed:
+        tree.unlink(c);
+        if let Some(p) = prev {
+            if !tree.is_ancestor(c, p) {
+                tree.link(p, c);
+            }
+        }
+    }
+
+    // 2-4. Prune empty containers.
+    let mut roots = Vec::new();
+    for root in tree.roots() {
+        roots.extend(tree.prune(root, true));
+    }
+
+    // 5. Group roots by subject.
+    let mut by_subject: HashMap<String, usize> = HashMap::new();
+    for root in &roots {
+        let Some((base, _)) = tree.subject(*root, msgs) else {
+            continue;
+        };
+        let prefer = |tree: &Tree, c: usize| {
+            let is_reply = tree.subject(c, msgs).is_some_and(|(_, r)| r);
+            tree.containers[c].msg.is_none() || !is_reply
+        };
+        match by_subject.get(&base) {
+            Some(other) if prefer(&tree, *other) => {}
+            _ => {
+                by_subject.insert(base, *root);
+            }
+        }
+    }
+    for root in roots {
+        let Some((base, is_reply)) = tree.subject(root, msgs) else {
+            continue;
+        };
+        let Some(&other) = by_subject.get(&base) else {
+            continue;
+        };
+        if other == root {
+            continue;
+        }
+        let root_is_empty = tree.containers[root].msg.is_none();
+        let other_is_empty = tree.containers[other].msg.is_none();
+        let other_is_reply =
+            tree.subject(other, msgs).is_some_and(|(_, r)| r);
+        if root_is_empty && other_is_empty {
+            for child in tree.containers[root].children.clone() {
+                tree.link(other, child);
+            }
+        } else if other_is_empty || (is_reply && !other_is_reply) {
+            tree.link(other, root);
+        } else {
+            let id = tree.containers[other].id.clone();
+            let dummy = tree.new_container(id);
+            tree.link(dummy, other);
+            tree.link(dummy, root);
+            by_subject.insert(base, dummy);
+        }
+    }
+
+    // Flatten.
+    let mut links = Vec::new();
+    for root in tree.roots() {
+        let thread_id = tree.containers[root].id.clone();
+        let mut frontier: Vec<(usize, Option<usize>)> = vec![(root, None)];
+        while let Some((c, parent_msg)) = frontier.pop() {
+            let msg = tree.containers[c].msg;
+            if let Some(m) = msg {
+                links.push(Link {
+                    hash: msgs[m].hash.clone(),
+                    thread_id: thread_id.clone(),
+                    parent_hash: parent_msg.map(|p| msgs[p].hash.clone()),
+                });
+            }
+            for child in &tree.containers[c].children {
+                frontier.push((*child, msg.or(parent_msg)));
+            }
+        }
+    }
+    links
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn msg(hash: &str, id: &str, refs: &[&str], subject: &str) -> Msg {
+        Msg {
+            hash: hash.to_string(),
+            message_id: Some(id.to_string()),
+            references: refs.iter().map(|r| r.to_string()).collect(),
+            subject: Some(subject.to_string()),
+        }
+    }
+
+    fn link(hash: &str, thread_id: &str, parent: Option<&str>) -> Link {
+        Link {
+            hash: hash.to_string(),
+            thread_id: thread_id.to_string(),
+            parent_hash: parent.map(|p| p.to_string()),
+        }
+    }
+
+    #[test]
+    fn t_msg_ids() {
+        assert_eq!(
+            vec!["a@x".to_string(), "b@y".to_string()],
+            msg_ids(" <a@x>\r\n\t<b@y> junk <")
+        );
+        assert!(msg_ids("").is_empty());
+    }
+
+    #[test]
+    fn t_references() {
+        let ids = |ids: &[&str]| -> Vec<String> {
+            ids.iter().map(|id| id.to_string()).collect()
+        };
+        assert_eq!(ids(&["a", "b"]), references(&ids(&["a", "b"]), &[]));
+        assert_eq!(ids(&["a", "b"]), references(&ids(&["a"]), &ids(&["b"])));
+        assert_eq!(
+            ids(&["a", "b"]),
+            references(&ids(&["a", "b"]), &ids(&["b"]))
+        );
+        assert_eq!(ids(&["b"]), references(&[], &ids(&["b"])));
+    }
+
+    #[test]
+    fn t_subject_base() {
+        assert_eq!(("hi".to_string(), false), subject_base("Hi"));
+        assert_eq!(
+            ("hi there".to_string(), true),
+            subject_base("Re: hi  there")
+        );
+        assert_eq!(
+            ("hi".to_string(), true),
+            subject_base("[list] RE: Fwd: Hi")
+        );
+        assert_eq!((String::new(), true), subject_base("Re:"));
+    }
+
+    #[test]
+    fn t_jwz() {
+        let msgs = vec![
+            msg("3", "c", &["a", "b"], "Re: Hi"),
+            msg("1", "a", &[], "Hi"),
+            // Parent "b" was never seen:
+            msg("4", "d", &["a", "b"], "Re: Hi"),
+            // No references, but a reply by subject:
+            msg("5", "e", &[], "Re: hi"),
+            msg("6", "f", &[], "Unrelated"),
+        ];
+        let mut actual = jwz(&msgs);
+        actual.sort();
+        assert_eq!(
+            vec![
+                link("1", "a", None),
+                link("3", "a", Some("1")),
+                link("4", "a", Some("1")),
+                link("5", "a", Some("1")),
+                link("6", "f", None),
+            ],
+            actual
+        );
+    }
+
+    #[test]
+    fn t_jwz_missing_root() {
+        let msgs = vec![
+            msg("2", "b", &["a"], "Re: Hi"),
+            msg("3", "c", &["a"], "Re: Hi"),
+        ];
+        let mut actual = jwz(&msgs);
+        actual.sort();
+        assert_eq!(vec![link("2", "a", None), link("3", "a", None)], actual);
+    }
+
+    #[test]
+    fn t_jwz_loop() {
+        let msgs =
+            vec![msg("1", "a", &["b"], "x"), msg("2", "b", &["a"], "y")];
+        let actual = jwz(&msgs);
+        assert_eq!(2, actual.len());
+        assert_eq!(
+            1,
+            actual.iter().filter(|l| l.parent_hash.is_none()).count()
+        );
+    }
+}